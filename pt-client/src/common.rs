@@ -7,6 +7,7 @@ mod color;
 mod multifocus;
 mod layout;
 mod files;
+pub mod learn;
 
 pub use pcm::generate_waveform;
 pub use pcm::generate_waveforms;
@@ -17,7 +18,7 @@ pub use pcm::Asset;
 pub use pcm::AudioRegion;
 pub use pcm::MidiRegion;
 pub use pcm::Track;
-pub use pcm::REGIONS_PER_TRACK;
+pub use libcommon::REGIONS_PER_TRACK;
 
 pub use color::Color;
 pub use color::write_bg;
@@ -40,8 +41,8 @@ pub use layout::MARGIN_D1;
 pub use layout::MARGIN_D2;
 
 pub use files::get_files;
-pub use files::PALIT_PROJECTS;
-pub use files::PALIT_MODULES;
+
+pub use learn::Learn;
 
 pub type Screen = RawTerminal<BufWriter<File>>;
 
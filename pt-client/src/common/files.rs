@@ -2,11 +2,7 @@ use std::path::Path;
 use std::fs::{self, File};
 use std::io;
 
-pub const PALIT_PROJECTS: &str = "./";
-pub const PALIT_MODULES: &str = "./modules/";
-
-pub fn get_files(path: &str, file_type: &str, mut collection: Vec<String>) -> io::Result<Vec<String>> {
-    let dir = Path::new(path);
+pub fn get_files(dir: &Path, file_type: &str, mut collection: Vec<String>) -> io::Result<Vec<String>> {
     if dir.is_dir() {
         for entry in fs::read_dir(dir)? {
             let entry = entry?;
@@ -0,0 +1,62 @@
+use libcommon::{Action, Param, BindingDoc, bind};
+
+/*
+    MIDI learn for views with params. The learn key on a selected param
+    asks pt-sound to bind it to the next controller moved, and on a
+    bound or learning param forgets it again. pt-sound moves the param
+    itself and tells the view which controller moved, so the view
+    scales it the same way to follow along.
+*/
+
+#[derive(Clone, Debug, Default)]
+pub struct Learn {
+    pub learning: Option<String>,
+    pub bindings: Vec<BindingDoc>,
+}
+
+pub fn reduce(learn: Learn, action: &Action) -> Learn {
+    match action {
+        Action::LearnParam(param, _, _) => Learn {
+            learning: Some(param.clone()),
+            bindings: learn.bindings,
+        },
+        Action::BindParam(cc, param, min, max) => {
+            let mut bindings = learn.bindings;
+            bind(&mut bindings, BindingDoc { cc: *cc, param: param.clone(), min: *min, max: *max });
+            Learn { learning: None, bindings }
+        },
+        Action::ForgetParam(param) => Learn {
+            learning: learn.learning.filter(|p| p != param),
+            bindings: learn.bindings.into_iter().filter(|b| &b.param != param).collect(),
+        },
+        _ => learn
+    }
+}
+
+// What the learn key does on a param
+pub fn toggle(learn: &Learn, param: &str, min: Param, max: Param) -> Action {
+    let learning = learn.learning.as_ref().map_or(false, |p| p == param);
+    if learning || learn.bindings.iter().any(|b| b.param == param) {
+        Action::ForgetParam(param.to_string())
+    } else {
+        Action::LearnParam(param.to_string(), min, max)
+    }
+}
+
+// The params a controller moved, as pt-sound set them
+pub fn moved(learn: &Learn, cc: u8, value: u8) -> Vec<Action> {
+    learn.bindings.iter()
+        .filter(|b| b.cc == cc)
+        .map(|b| Action::SetParam(b.param.clone(), b.scale(value)))
+        .collect()
+}
+
+// Shown with a param, ? while learning and its controller once bound
+pub fn mark(learn: &Learn, param: &str) -> String {
+    if learn.learning.as_ref().map_or(false, |p| p == param) {
+        return "?".to_string();
+    }
+    learn.bindings.iter()
+        .find(|b| b.param == param)
+        .map_or(String::new(), |b| format!("CC{}", b.cc))
+}
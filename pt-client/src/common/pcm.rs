@@ -1,10 +1,7 @@
-extern crate xmltree;
-
 use std::fs::{self, OpenOptions};
 use std::collections::HashMap;
 use std::io::BufReader;
 use itertools::{self, Itertools};
-use xmltree::Element;
 use hound;
 use libcommon::{Note, Offset};
 
@@ -41,10 +38,10 @@ pub struct Track {
     pub monitor: bool,
     pub index: u16,
     pub id: u16,
+    pub gain: f32,
+    pub pan: f32,
 }
 
-pub static REGIONS_PER_TRACK: u16 = 1000;
-
 pub fn char_offset(sample_offset: u32, rate: u32, bpm: u16, zoom: usize) -> u16 {
     // return how many beats passed based on a given sample rate
     let samples_per_beat = (60 * rate) / (bpm as u32);
@@ -103,7 +100,11 @@ pub fn generate_waveform(mut file: hound::WavReader<BufReader<std::fs::File>>, w
 pub fn generate_waveforms(assets: &mut HashMap<u16, Asset>, 
         rate: u32, tempo: u16, zoom: usize) {
     for (_, asset) in assets.iter_mut() {
-        let asset_file = hound::WavReader::open(asset.src.clone()).unwrap();
+        // Missing assets are reported when the project loads
+        let asset_file = match hound::WavReader::open(asset.src.clone()) {
+            Ok(a) => a,
+            Err(_) => continue,
+        };
 
         let num_pairs = char_offset(
             asset.duration, rate, tempo, zoom) as usize;
@@ -13,6 +13,7 @@ pub mod bigtext;
 pub mod ivories;
 pub mod roll;
 pub mod scroll;
+pub mod master_meter;
 
 // multifocus components
 pub mod region_audio;
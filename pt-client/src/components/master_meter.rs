@@ -0,0 +1,37 @@
+use termion::cursor;
+use std::io::Write;
+use crate::common::Screen;
+
+// Room taken in the header, bars and gain
+pub const WIDTH: u16 = 22;
+
+// One cell for every 4dB above the floor
+const CELLS: u16 = 12;
+const FLOOR_DB: f32 = -48.0;
+
+fn cells(level: f32) -> u16 {
+    let db = if level > 0.0 { 20.0 * level.log10() } else { FLOOR_DB };
+    ((db - FLOOR_DB) / -FLOOR_DB * CELLS as f32).max(0.0).min(CELLS as f32) as u16
+}
+
+// Levels are linear peak L/R, RMS L/R and the limiter's reduction in dB
+pub fn render(out: &mut Screen, 
+    x: u16, 
+    y: u16, 
+    levels: (f32, f32, f32, f32, f32),
+    gain: f32) {
+    let (peak_l, peak_r, rms_l, rms_r, reduction) = levels;
+    for (i, (label, peak, rms)) in [("L", peak_l, rms_l), ("R", peak_r, rms_r)].iter().enumerate() {
+        let bar: String = (0..CELLS).map(|c| {
+            if c < cells(*rms) { '█' } 
+            else if c < cells(*peak) { '▒' } 
+            else { '·' }
+        }).collect();
+        write!(out, "{}{} {}", cursor::Goto(x, y + i as u16), label, bar).unwrap();
+    }
+    write!(out, "{}{:+.0}dB ", cursor::Goto(x + CELLS + 3, y), gain).unwrap();
+    // Only shown while the limiter is working
+    if reduction >= 0.1 {
+        write!(out, "{}-{:.1} ", cursor::Goto(x + CELLS + 3, y + 1), reduction).unwrap();
+    }
+}
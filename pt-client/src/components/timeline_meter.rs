@@ -3,7 +3,7 @@ use termion::cursor;
 use libcommon::Action;
 
 use crate::common::{Screen, MultiFocus, FocusType, ID, Window};
-use crate::components::{tempo};
+use crate::components::{tempo, master_meter};
 use crate::views::TimelineState;
 
 pub fn new() -> MultiFocus::<TimelineState> {
@@ -18,9 +18,19 @@ pub fn new() -> MultiFocus::<TimelineState> {
         w_id: void_id.clone(),
         w: void_render,
 
-        b_id: void_id.clone(),
-        b_t: void_transform,
-        b: void_render,
+        b_id: (FocusType::Param, 0),
+        b_t: |a, id, state| match a {
+            Action::Up => Action::SetMasterGain((state.master_gain + 1.0).min(12.0)),
+            Action::Down => Action::SetMasterGain((state.master_gain - 1.0).max(-60.0)),
+            // Levels keep coming while the gain is held
+            a @ Action::MasterLevels(_, _, _, _, _) => a,
+            _ => Action::Noop,
+        },
+        b: |out, window, id, state, focus| {
+            master_meter::render(out, 
+                window.x + window.w - 20 - master_meter::WIDTH, 2,
+                state.master_levels, state.master_gain);
+        },
 
         y_id: (FocusType::Param, 0),
         y_t: |a, id, state| {
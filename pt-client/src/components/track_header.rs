@@ -11,8 +11,6 @@ pub fn new(track_id: u16) -> MultiFocus::<TimelineState> {
     let void_id: ID = (FocusType::Void, 0);
     let void_render: fn(&mut Screen, Window, ID, &TimelineState, bool) =
         |_, _, _, _, _| {};
-    let void_transform: fn(Action, ID, &TimelineState) -> Action = 
-        |a, _, _| a;
 
     MultiFocus::<TimelineState> {
         w_id: (FocusType::Button, track_id),
@@ -101,9 +99,35 @@ pub fn new(track_id: u16) -> MultiFocus::<TimelineState> {
             ).unwrap();
         },
 
+        // Fader, gain while held with up/down, pan with left/right
         y_id: void_id.clone(),
-        y_t: void_transform,
-        y: void_render,
+        y_t: |action, id, state| {
+            let track = state.tracks.get(&id.1).unwrap();
+            match action {
+                Action::Up => Action::SetTrackGain(id.1, (track.gain + 1.0).min(12.0)),
+                Action::Down => Action::SetTrackGain(id.1, (track.gain - 1.0).max(-60.0)),
+                Action::Left => Action::SetTrackPan(id.1, (track.pan - 0.1).max(-1.0)),
+                Action::Right => Action::SetTrackPan(id.1, (track.pan + 0.1).min(1.0)),
+                _ => Action::Noop,
+            }
+        },
+        y: |mut out, win, id, state, focus| {
+            let x = win.x + TRACKS_X + 8;
+            let y = win.y + TIMELINE_Y + 2 * id.1;
+            let track = state.tracks.get(&id.1).unwrap();
+            // -60dB to +12dB over eight steps, 0dB sits on the seventh
+            let level = ((track.gain + 60.0) / 72.0 * 7.0).round() as usize;
+            let pan = if track.pan < -0.05 { "◂" }
+                else if track.pan > 0.05 { "▸" }
+                else { "" };
+            write!(out, "{}{}{}", cursor::Goto(x, y),
+                ["▁", "▂", "▃", "▄", "▅", "▆", "▇", "█"][level.min(7)],
+                pan
+            ).unwrap();
+            write!(out, "{}{}", cursor::Goto(x, y + 1),
+                if track.gain != 0.0 || track.pan != 0.0 { "V" } else { "v" }
+            ).unwrap();
+        },
 
         active: None,
     }
@@ -9,19 +9,16 @@ mod modules;
 
 use std::io::{Write, Stdout, stdout, BufWriter};
 use std::io::prelude::*;
-use std::fs::{OpenOptions, File};
-use std::os::unix::fs::OpenOptionsExt;
-use std::ffi::CString;
+use std::fs::File;
 use std::process::Command;
 use std::os::unix::io::FromRawFd;
 use std::ops::DerefMut;
 use std::collections::VecDeque;
 use std::{thread, time};
-use xmltree::Element;
 use termion::{clear, color, cursor, terminal_size};
 use termion::raw::{IntoRawMode, RawTerminal};
-use libcommon::{Action, Module, Anchor, PALIT_ROOT};
-use libcommon::{Document, read_document, write_document};
+use libcommon::{Action, Link, Config, Module, Anchor, config};
+use libcommon::{Document, ModuleDoc, PatchDoc, read_document, write_document, collect};
 
 use views::{Layer, 
     Home, 
@@ -35,6 +32,7 @@ use views::{Layer,
     Modules,
     Project,
     Plugin,
+    Input,
 };
 
 use common::{Screen, MARGIN_D0, MARGIN_D1, MARGIN_D2};
@@ -72,44 +70,51 @@ fn render(stdout: &mut Screen, layers: &VecDeque<(u16, Box<Layer>)>) {
 }
 
 // Clears and populates a mutable event queue
-fn ipc_action(mut ipc_in: &File, queue: &mut VecDeque<Action>) {
+fn ipc_action(link: &mut Link, queue: &mut VecDeque<Action>) -> std::io::Result<()> {
     queue.clear();
-    let mut buf: String = String::new();
 
-    // FIXME: Err but still writes to buf?
-    match ipc_in.read_to_string(&mut buf) {
-        _ => {}
-    };
-    let mut ipc_iter = buf.split(" ");
-
-    while let Some(action_raw) = ipc_iter.next() {
-        match action_raw.parse::<Action>() {
+    // Partial actions stay in the link until the rest arrives
+    for action in link.recv()? {
+        match action {
             Ok(Action::Noop) => (),
             Ok(a) => queue.push_back(a),
-            Err(a) => (),
+            // Skip the token and report it like any other error
+            Err(e) => queue.push_back(Action::Error(e.to_string())),
         };
     };
+    Ok(())
+}
+
+// Sends an action to pt-sound, reporting if it has gone away
+fn ipc_send(link: &mut Link, action: &Action) -> bool {
+    match link.send(action) {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("Lost pt-sound: {}", e);
+            false
+        }
+    }
 }
+
 fn add_layer(a: &mut VecDeque<(u16, Box<Layer>)>, b: Box<Layer>, id: u16) {
     a.push_back((id, b)); // End of layers is front of the screen
 }
 
 fn add_module(
     layers: &mut VecDeque<(u16, Box<Layer>)>, 
-    name: &str, 
     id: u16, 
     size: (u16, u16), 
-    el: Element) {
-    match name {
-        "timeline" => add_layer(layers, 
-            Box::new(Timeline::new(1, 1, size.0, size.1, (el).to_owned())), id),
-        "hammond" => add_layer(layers,
-            Box::new(Hammond::new(5,5,size.0,size.1, (el).to_owned())), id),
-        "keyboard" => add_layer(layers,
-            Box::new(Keyboard::new(1, 1, size.0, size.1, (el).to_owned())), id),
-        "arpeggio" => add_layer(layers,
-            Box::new(Arpeggio::new(1, 1, size.0, size.1, (el).to_owned())), id),
-        "patch" => { 
+    module: ModuleDoc) {
+    match module {
+        ModuleDoc::Timeline(doc) => add_layer(layers, 
+            Box::new(Timeline::new(1, 1, size.0, size.1, doc)), id),
+        ModuleDoc::Hammond(doc) => add_layer(layers,
+            Box::new(Hammond::new(5,5,size.0,size.1, doc)), id),
+        ModuleDoc::Keyboard(doc) => add_layer(layers,
+            Box::new(Keyboard::new(1, 1, size.0, size.1, doc)), id),
+        ModuleDoc::Arpeggio(doc) => add_layer(layers,
+            Box::new(Arpeggio::new(1, 1, size.0, size.1, doc)), id),
+        ModuleDoc::Patch(doc) => { 
             // Remove any existing patch 
             layers.retain(|(id, _)| *id != DEFAULT_ROUTE_ID);
             add_layer(layers, Box::new(
@@ -118,50 +123,72 @@ fn add_module(
                     MARGIN_D0.1,
                     size.0 - (MARGIN_D0.0 * 2),
                     size.1 - (MARGIN_D0.1 * 2), 
-                Some((el).to_owned()))
+                Some(doc))
             ), DEFAULT_ROUTE_ID);
         },
-        name @ "chord" => { eprintln!("Unimplemented module {}", name); },
-        plugin => {
+        ModuleDoc::Input(doc) => add_layer(layers,
+            Box::new(Input::new(1, 1, size.0, size.1, doc)), id),
+        ModuleDoc::Chord(_) => { eprintln!("Unimplemented module chord"); },
+        ModuleDoc::Plugin(doc) => {
             let cmd = format!(r#"
-                ./bin/faust -lang c -cn mydsp ./modules/{name}.dsp > ./modules/_plugin_part.c; 
-                cat ./modules/faust.h ./modules/_plugin_part.c > ./modules/_plugin.c; 
-                ./bin/gcc -c -fpic ./modules/_plugin.c -o ./modules/_plugin.o; 
-                ./bin/gcc -shared -o ./modules/{name}.so ./modules/_plugin.o; 
-                rm ./modules/_plugin_part.c; 
-                rm ./modules/_plugin.o;
-                rm ./modules/_plugin.c"#, name=plugin);
+                ./bin/faust -lang c -cn mydsp {dir}/{name}.dsp > {dir}/_plugin_part.c; 
+                cat {dir}/faust.h {dir}/_plugin_part.c > {dir}/_plugin.c; 
+                ./bin/gcc -c -fpic {dir}/_plugin.c -o {dir}/_plugin.o; 
+                ./bin/gcc -shared -o {dir}/{name}.so {dir}/_plugin.o; 
+                rm {dir}/_plugin_part.c; 
+                rm {dir}/_plugin.o;
+                rm {dir}/_plugin.c"#, name=doc.name, 
+                dir=config().modules.display().to_string().trim_end_matches('/'));
             // Run make as arg to sh in parent directory
             let result = Command::new("sh").arg("-c").arg(cmd).status()
                 .expect("failed to run plugin compiler");
             if result.success() {
                 add_layer(layers, 
-                    Box::new(Plugin::new(1, 1, size.0, size.1, (el).to_owned())), id)
+                    Box::new(Plugin::new(1, 1, size.0, size.1, doc)), id)
             } else {
                 // Make sure that plugin.so exists by the time we leave this function, or panic
-                panic!("Failed to compile {}", plugin);
+                panic!("Failed to compile {}", doc.name);
                 //add_layer(a, Box::new(Plugin::new(1, 1, size.0, size.1, (el).to_owned())), id)
             }
         }
     }
 }
 
+// The document's modules as their views currently have them
+fn snapshot(document: &Document, layers: &VecDeque<(u16, Box<Layer>)>) -> Vec<(u16, ModuleDoc)> {
+    document.modules.iter().map(|(id, module)| {
+        if let Some((_, layer)) = layers.iter().find(|(_id, l)| _id == id) {
+            if let Some(_module) = layer.save() {
+                (*id, _module)
+            } else {
+                // Layer did not return a module
+                (*id, module.to_owned())
+            }
+        } else {
+            // Could not find id from document in layers
+            (*id, module.to_owned())
+        }
+    }).collect()
+}
+
 fn main() -> std::io::Result<()> {
 
-    // Public action fifo /tmp/pt-client
-    let mut ipc_in = OpenOptions::new()
-        .custom_flags(libc::O_NONBLOCK)
-	    .read(true)
-	    .open("/tmp/pt-client").unwrap();
+    let args: Vec<String> = std::env::args().collect();
+    let config = match Config::load(&args) {
+        Ok(c) => config::init(c),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
+    let transport = config.transport.clone();
 
     // Blocked by pt-sound reader
     // If a process writes to stdout and nobody 
     // is around to read it, should it continue?
     eprintln!("Waiting for pt-sound...");
 
-    let mut ipc_sound = OpenOptions::new()
-        .write(true)
-        .open("/tmp/pt-sound").unwrap();
+    let mut link = Link::connect(&transport)?;
 
     // Allocate 8MB buffer in raw mode
     let mut out = unsafe {
@@ -169,14 +196,13 @@ fn main() -> std::io::Result<()> {
     };
 
     // Configure input polling array
-    let in_src = CString::new("/tmp/pt-client").unwrap();
-    let mut fds: Vec<libc::pollfd> = unsafe {vec![
+    let mut fds: Vec<libc::pollfd> = link.as_raw_fds().into_iter().map(|fd| {
         libc::pollfd { 
-            fd: libc::open(in_src.as_ptr(), libc::O_RDONLY),
+            fd,
             events: libc::POLLIN,
             revents: 0,
-        },
-    ]};
+        }
+    }).collect();
 
     // Configure margins and sizes
     let size: (u16, u16) = terminal_size().unwrap();
@@ -202,10 +228,12 @@ fn main() -> std::io::Result<()> {
             libc::poll(&mut fds[0] as *mut libc::pollfd, fds.len() as libc::nfds_t, 100);
         }
 
-        // If anybody else closes the pipe, halt TODO: Throw error
-        if fds[0].revents & libc::POLLHUP == libc::POLLHUP { break 'event; }
-        if fds[0].revents > 0 {
-            ipc_action(&mut ipc_in, &mut events);
+        if fds.iter().any(|fd| fd.revents > 0) {
+            // If pt-sound goes away, halt
+            if let Err(e) = ipc_action(&mut link, &mut events) {
+                eprintln!("Lost pt-sound: {}", e);
+                break 'event;
+            }
         } else { continue; };
 
         while let Some(event) = events.pop_front() {
@@ -233,9 +261,9 @@ fn main() -> std::io::Result<()> {
                         size.1 - (MARGIN_D2.1 * 2),
                     );
                     if let Some(doc) = document {
-                        let modules: Vec<Module> = doc.modules.iter().map(|(id, el)| Module {
+                        let modules: Vec<Module> = doc.modules.iter().map(|(id, module)| Module {
                             id: id.clone(),
-                            name: el.name.clone(),
+                            name: module.name().to_string(),
                         }).collect();
                         project_view.dispatch(Action::ShowProject(doc.src.clone(), modules));
                         add_layer(&mut layers, Box::new(project_view), DEFAULT_PROJECT_ID); 
@@ -243,6 +271,58 @@ fn main() -> std::io::Result<()> {
                     }
                     Action::Noop
                 },
+                Action::Error(message) => {
+                    eprintln!("{}", message);
+                    Action::Noop
+                },
+                a @ Action::AudioStatus(_, _, _, _) |
+                a @ Action::Glitches(_, _) |
+                a @ Action::BounceProgress(_, _) => {
+                    if let Some((_, layer)) = layers.iter_mut().find(|(id, _)| *id == DEFAULT_PROJECT_ID) {
+                        layer.dispatch(a);
+                    }
+                    Action::Noop
+                },
+                a @ Action::MasterLevels(_, _, _, _, _) => {
+                    // Every timeline shows the master meter
+                    if let Some(doc) = &document {
+                        for (id, module) in doc.modules.iter() {
+                            if let ModuleDoc::Timeline(_) = module {
+                                if let Some((_, layer)) = layers.iter_mut().find(|(l_id, _)| l_id == id) {
+                                    layer.dispatch(a.clone());
+                                }
+                            }
+                        }
+                    }
+                    Action::Noop
+                },
+                a @ Action::MidiPorts(_) => {
+                    // Every keyboard can pick from them
+                    if let Some(doc) = &document {
+                        for (id, module) in doc.modules.iter() {
+                            if let ModuleDoc::Keyboard(_) = module {
+                                if let Some((_, layer)) = layers.iter_mut().find(|(l_id, _)| l_id == id) {
+                                    layer.dispatch(a.clone());
+                                }
+                            }
+                        }
+                    }
+                    Action::Noop
+                },
+                a @ Action::RouteLevel(_, _) |
+                a @ Action::AnchorLevel(_, _, _) => {
+                    if let Some((_, layer)) = layers.iter_mut().find(|(id, _)| *id == DEFAULT_ROUTE_ID) {
+                        layer.dispatch(a);
+                    }
+                    Action::Noop
+                },
+                Action::PatchRefused(m_id, a_id, r_id, reason) => {
+                    eprintln!("Refused patch: {}", reason);
+                    if let Some((_, layer)) = layers.iter_mut().find(|(id, _)| *id == DEFAULT_ROUTE_ID) {
+                        layer.dispatch(Action::PatchRefused(m_id, a_id, r_id, reason));
+                    }
+                    Action::Noop
+                },
                 Action::Help => { 
                     add_layer(&mut layers, Box::new(Help::new(
                         MARGIN_D1.0,
@@ -279,7 +359,7 @@ fn main() -> std::io::Result<()> {
             // capture default action if returned from layer
             match default {
                 Action::Exit => { 
-                    ipc_sound.write(Action::Exit.to_string().as_bytes()).unwrap();
+                    ipc_send(&mut link, &Action::Exit);
                     break 'event; 
                 },
                 Action::Close => {
@@ -380,12 +460,24 @@ fn main() -> std::io::Result<()> {
                     }
                 }, 
                 Action::OpenProject(title) => {
-                    ipc_sound.write(Action::OpenProject(title.clone()).to_string().as_bytes()).unwrap();
-                    let mut doc = read_document(title);
-                    for (id, el) in doc.modules.iter().rev() {
-                        add_module(&mut layers, &el.name, *id, size, el.to_owned());
+                    match read_document(title.clone()) {
+                        Ok(doc) => {
+                            if !ipc_send(&mut link, &Action::OpenProject(title)) { break 'event; }
+                            for (id, module) in doc.modules.iter().rev() {
+                                add_module(&mut layers, *id, size, module.to_owned());
+                            }
+                            // For keyboards to choose from
+                            document = Some(doc);
+                            if !ipc_send(&mut link, &Action::ListMidiPorts) { break 'event; }
+                        },
+                        // Stay home and show what is wrong with it
+                        Err(e) => {
+                            eprintln!("Could not open {}: {}", title, e);
+                            if let Some((_, layer)) = layers.iter_mut().find(|(id, _)| *id == DEFAULT_HOME_ID) {
+                                layer.dispatch(Action::Error(format!("{}: {}", title, e)));
+                            }
+                        }
                     }
-                    document = Some(doc.clone());
                 },
                 Action::ShowAnchors(anchors) => {
                     let mut routes_index: Option<usize> = None;
@@ -407,7 +499,7 @@ fn main() -> std::io::Result<()> {
                         ), DEFAULT_ROUTE_ID);
                         routes_index = Some(layers.len()-1);
                         if let Some(mut doc) = document.to_owned() {
-                            doc.modules.push((DEFAULT_ROUTE_ID, Element::new("patch")));
+                            doc.modules.push((DEFAULT_ROUTE_ID, ModuleDoc::Patch(PatchDoc::default())));
                             document = Some(doc);
                         }
                     }
@@ -426,34 +518,36 @@ fn main() -> std::io::Result<()> {
                     add_layer(&mut layers, route_view, DEFAULT_ROUTE_ID);
                 },
                 Action::AddModule(0, name) => {
-                    let mut new_id = match &name[..] {
-                        "keyboard" => 104,
-                        // Get next sequential ID
-                        _ => layers.iter().fold(0, |max, (id,_)| 
-                            if *id > max { *id } else { max }) + 1
-                    };
-                    // Make empty element with tag and id
-                    let mut new_el = Element::new(&name);
-                    new_el.attributes.insert("id".to_string(), new_id.to_string());
-                    add_module(&mut layers, &name, new_id, size, new_el.clone());
+                    // Get next sequential ID
+                    let mut new_id = layers.iter().fold(0, |max, (id,_)| 
+                        if *id > max { *id } else { max }) + 1;
+                    // Make empty module of this kind
+                    let new_module = ModuleDoc::new(&name);
+                    add_module(&mut layers, new_id, size, new_module.clone());
                     if let Some(mut doc) = document.to_owned() {
-                        doc.modules.push((new_id, new_el));
+                        doc.modules.push((new_id, new_module));
                         document = Some(doc);
                     } else {
                         document = Some(Document {
                             title: "Untitled".to_string(),
                             src: "untitled.xml".to_string(),
                             sample_rate: 48_000,
-                            modules: vec![(new_id, new_el)],
+                            bit_depth: 16,
+                            modules: vec![(new_id, new_module)],
+                            migrations: vec![],
+                            relinks: vec![],
                         });
                     }
-                    ipc_sound.write(Action::AddModule(new_id, name).to_string().as_bytes()).unwrap();
+                    if name == "keyboard" && !ipc_send(&mut link, &Action::ListMidiPorts) {
+                        break 'event;
+                    }
+                    if !ipc_send(&mut link, &Action::AddModule(new_id, name)) { break 'event; }
                     // Make sure modules view is still in front so it can Cancel
                     layers.swap(layers.len()-1, layers.len()-2);
                     events.push_back(Action::Back);
                 },
                 Action::DelModule(id) => {
-                    ipc_sound.write(Action::DelModule(id).to_string().as_bytes()).unwrap();
+                    if !ipc_send(&mut link, &Action::DelModule(id)) { break 'event; }
                     layers.retain(|(i, _)| *i != id);
                     if let Some(mut doc) = document.to_owned() {
                         doc.modules.retain(|(i, _)| *i != id);
@@ -469,41 +563,62 @@ fn main() -> std::io::Result<()> {
                         layers[r_id].1.dispatch(Action::DelModule(id));
                     }
                 },
-                Action::SaveAs(title) => {
+                a @ Action::SaveAs(_) | a @ Action::Collect(_, _) => {
                     // This action must only be dispatched by the Save view
+                    let title = match a {
+                        Action::SaveAs(ref title) | Action::Collect(ref title, _) => title.clone(),
+                        _ => unreachable!(),
+                    };
                     let mut filename: String = title.replace(&[' ', '/'][..], "");
                     filename.make_ascii_lowercase();
-                    let new_modules: Vec<(u16, Element)> = 
-                        document.to_owned().unwrap().modules.iter().map(|(id, el)| {
-                            if let Some((_, layer)) = layers.iter().find(|(_id, l)| _id == id) {
-                                if let Some(_el) = layer.save() {
-                                    (*id, _el)
-                                } else {
-                                    // Layer did not return Element
-                                    (*id, el.to_owned())
-                                }
-                            } else {
-                                // Could not find id from document in layers
-                                (*id, el.to_owned())
-                            }
-                        }
-                    ).collect();
-                    let mut new_document = Document {
+                    let new_modules = snapshot(document.as_ref().unwrap(), &layers);
+                    let new_document = Document {
                         title,
                         src: filename.clone(),
-                        sample_rate: document.unwrap().sample_rate,
+                        sample_rate: document.as_ref().unwrap().sample_rate,
+                        bit_depth: document.as_ref().unwrap().bit_depth,
                         modules: new_modules,
+                        migrations: vec![],
+                        relinks: vec![],
                     };
-                    write_document(&mut new_document);
-                    eprintln!("Saved to {}", filename);
-                    document = Some(new_document);
-
-                    if let Some((_, layer)) = layers.iter_mut().find(|(id, _)| *id == DEFAULT_HOME_ID) {
-                        layer.dispatch(Action::ShowProject(format!("{}.xml", filename), vec![]));
+                    if let Action::Collect(_, archive) = a {
+                        // The open project stays where it is, the bundle is a copy
+                        let dest = config.projects.join(&filename);
+                        match collect(&new_document, &dest, archive) {
+                            Ok(path) => eprintln!("Collected to {}", path.display()),
+                            Err(e) => eprintln!("Could not collect {}: {}", filename, e),
+                        };
+                    } else {
+                        match write_document(&new_document) {
+                            Ok(()) => eprintln!("Saved to {}", filename),
+                            Err(e) => eprintln!("Could not save {}: {}", filename, e),
+                        };
+                        document = Some(new_document);
+
+                        if let Some((_, layer)) = layers.iter_mut().find(|(id, _)| *id == DEFAULT_HOME_ID) {
+                            layer.dispatch(Action::ShowProject(format!("{}.xml", filename), vec![]));
+                        }
                     }
 
                     layers.pop_back();
                 },
+                Action::Bounce(_, stems, looped) => {
+                    // pt-sound bounces the project from disk, so save it first
+                    if let Some(doc) = document.as_mut() {
+                        let modules = snapshot(doc, &layers);
+                        doc.modules = modules;
+                        // Saved under the name it was opened as, which stays as it is
+                        let mut saved = doc.clone();
+                        saved.src = doc.src.trim_end_matches(".xml").to_string();
+                        match write_document(&saved) {
+                            Ok(()) => {
+                                let bounce = Action::Bounce(format!("{}.xml", saved.src), stems, looped);
+                                if !ipc_send(&mut link, &bounce) { break 'event; }
+                            },
+                            Err(e) => eprintln!("Could not save {}: {}", saved.src, e),
+                        };
+                    }
+                },
                 Action::Save => {
                     // Document will never be None when Save is dispatched because
                     // ... it requires the Project view to appear
@@ -522,27 +637,36 @@ fn main() -> std::io::Result<()> {
                 Action::Pepper => {
                     add_layer(&mut layers, Box::new(Help::new(10, 10, 44, 15)), 0); 
                 },
-                Action::CreateProject(title) => {
-                    ipc_sound.write(format!("NEW_PROJECT:{} ", title).as_bytes());
-                    add_layer(&mut layers, Box::new(Timeline::new(1, 1, size.0, size.1, title)));
-                },
                 Action::Error(message) => {
                     layers.push(Box::new(Error::new(message))) ;
                 }
                 */
+                Action::CreateProject(title) => {
+                    // pt-sound only hears about a new project through its modules
+                    let mut src: String = title.replace(&[' ', '/'][..], "");
+                    src.make_ascii_lowercase();
+                    document = Some(Document {
+                        title,
+                        src: format!("{}.xml", src),
+                        sample_rate: 48_000,
+                        bit_depth: 16,
+                        modules: vec![],
+                        migrations: vec![],
+                        relinks: vec![],
+                    });
+                },
+                a @ Action::SetMasterGain(_) |
                 a @ Action::DelRoute(_) |
                 a @ Action::AddRoute(_) |
                 a @ Action::PatchIn(_, _, _) |
                 a @ Action::PatchOut(_, _, _) |
                 a @ Action::DelPatch(_, _, _) => {
-                    ipc_sound.write(a.to_string().as_bytes()).unwrap();
+                    if !ipc_send(&mut link, &a) { break 'event; }
                 },
                 Action::Noop => {},
                 direct_action => {
-                    ipc_sound.write(Action::At(
-                        target_id, 
-                        Box::new(direct_action)
-                    ).to_string().as_bytes()).unwrap();
+                    let direct = Action::At(target_id, Box::new(direct_action));
+                    if !ipc_send(&mut link, &direct) { break 'event; }
                 }
             };	
         }
@@ -1,46 +1,31 @@
-use std::convert::TryInto;
 use std::collections::HashMap;
-use xmltree::Element;
-use libcommon::{Route, Anchor, Param, param_map, mark_map};
+use libcommon::{Route, Anchor, PatchDoc, RouteDoc, AnchorDoc};
 
 use crate::views::PatchState;
 
-pub fn write(state: PatchState) -> Element {
-    let mut root = Element::new("patch");
-
-    let mut has_master: bool = false;
+pub fn write(state: PatchState) -> PatchDoc {
+    let mut doc = PatchDoc::default();
 
     for (id, route) in state.routes.iter() {
-        if *id == 1 { has_master = true; }
-        let mut route_el = Element::new("route");
-        route_el.attributes.insert("id".to_string(), id.to_string());
-        for anchor in route.patch.iter() {
-            let mut anchor_el = if anchor.input { 
-                Element::new("input") 
-            } else {
-                Element::new("output")
-            };
-            anchor_el.attributes.insert("module".to_string(), anchor.module_id.to_string());
-            anchor_el.attributes.insert("index".to_string(), anchor.index.to_string());
-            route_el.children.push(anchor_el);
-        }
-        root.children.push(route_el);
+        doc.routes.push(RouteDoc {
+            id: *id,
+            anchors: route.patch.iter().map(|anchor| AnchorDoc {
+                module: anchor.module_id,
+                index: anchor.index,
+                input: anchor.input,
+            }).collect(),
+        });
     }
 
     // Make sure there's always a master route
-    if !has_master {
-        let mut master_route = Element::new("route");
-        master_route.attributes.insert("id".to_string(), "1".to_string());
-        root.children.push(master_route);
+    if !state.routes.contains_key(&1) {
+        doc.routes.push(RouteDoc { id: 1, anchors: vec![] });
     }
 
-    root
+    doc
 }
 
-pub fn read(mut doc: Element) -> PatchState {
-
-    let (mut doc, params) = param_map(&mut doc);
-    let (mut doc, marks) = mark_map(&mut doc);
+pub fn read(doc: PatchDoc) -> PatchState {
 
     let mut state = PatchState {
         routes: HashMap::new(),
@@ -48,53 +33,31 @@ pub fn read(mut doc: Element) -> PatchState {
         selected_anchor: None,
         selected_route: None,
         focus: (0,0),
+        refused: None,
+        route_levels: HashMap::new(),
+        anchor_levels: HashMap::new(),
     };
 
-    while let Some(mut route) = doc.take_child("route") {
-        let r_id: &str = route.attributes.get("id").unwrap();
-        let _r_id = r_id.parse::<u16>().unwrap();
-
+    for route in doc.routes.iter() {
         let mut current_route = Route {
-            id: _r_id,
+            id: route.id,
             patch: vec![]
         };
 
-        while let Some(mut anchor) = route.take_child("input") {
-            let a_id = anchor.attributes.get("index").unwrap();
-            let _a_id = a_id.parse::<u16>().unwrap();
-            let m_id = anchor.attributes.get("module").unwrap();
-            let _m_id = m_id.parse::<u16>().unwrap();
-
+        for anchor in route.anchors.iter() {
             let anchor = Anchor {
-                index: _a_id,
-                module_id: _m_id,
-                name: format!("In {}", a_id),
-                input: true,
+                index: anchor.index,
+                module_id: anchor.module,
+                name: format!("{} {}", if anchor.input { "In" } else { "Out" }, anchor.index),
+                input: anchor.input,
             };
 
             current_route.patch.push(anchor.clone());
             state.anchors.insert(anchor.index, anchor);
         }
 
-        while let Some(mut anchor) = route.take_child("output") {
-            let a_id = anchor.attributes.get("index").unwrap();
-            let _a_id = a_id.parse::<u16>().unwrap();
-            let m_id = anchor.attributes.get("module").unwrap();
-            let _m_id = m_id.parse::<u16>().unwrap();
-
-            let anchor = Anchor {
-                index: _a_id,
-                module_id: _m_id,
-                name: format!("Out {}", a_id),
-                input: false,
-            };
-
-            current_route.patch.push(anchor.clone());
-            state.anchors.insert(anchor.index, anchor);
-        }
-
-        state.routes.insert(_r_id, current_route);
+        state.routes.insert(route.id, current_route);
     }
     
     return state;
-}
\ No newline at end of file
+}
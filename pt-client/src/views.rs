@@ -38,4 +38,7 @@ mod project;
 pub use project::Project;
 
 mod plugin;
-pub use plugin::Plugin;
\ No newline at end of file
+pub use plugin::Plugin;
+
+mod input;
+pub use input::Input;
\ No newline at end of file
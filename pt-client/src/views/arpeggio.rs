@@ -1,11 +1,11 @@
 use std::io::Write;
 use termion::cursor;
-use xmltree::Element;
-use libcommon::{Action, Anchor, param_map, param_add};
+use libcommon::{Action, Anchor, Param, ModuleDoc, ArpeggioDoc};
 
 use crate::common::{MultiFocus, FocusType, ID, VOID_ID};
 use crate::common::{render_focii, focus_dispatch};
 use crate::common::{Screen, Window};
+use crate::common::{Learn, learn};
 use crate::views::{Layer};
 use crate::components::{popup, ivories, bigtext};
 
@@ -23,6 +23,7 @@ pub struct Arpeggio {
 pub struct ArpeggioState {
     length: f32,
     pattern: usize,
+    learn: Learn,
 }
 
 static VOID_RENDER: fn( &mut Screen, Window, ID, &ArpeggioState, bool) =
@@ -37,22 +38,25 @@ fn reduce(state: ArpeggioState, action: Action) -> ArpeggioState {
             _ => state.length
         },
         pattern: match action {
-            Action::SetParam(ref key, val) if key == "pattern" => val as usize,
+            Action::SetParam(ref key, val) if key == "pattern" => val.round() as usize,
             _ => state.pattern
         },
+        learn: learn::reduce(state.learn.clone(), &action),
     }
 }
 
 const TITLE: &str = "ARPEGGIOHYEAH";
 const NUM_PATTERNS: usize = 3;
+// Beats a controller spreads the loop over
+const LENGTH_RANGE: (Param, Param) = (0.25, 16.0);
 
 impl Arpeggio {
-    pub fn new(x: u16, y: u16, width: u16, height: u16, mut doc: Element) -> Self {
-        let (_, params) = param_map(&mut doc);
+    pub fn new(x: u16, y: u16, width: u16, height: u16, doc: ArpeggioDoc) -> Self {
         // Initialize State
         let initial_state: ArpeggioState = ArpeggioState {
-            length: *params.get("length").unwrap_or(&4.0),
-            pattern: *params.get("pattern").unwrap_or(&0.0) as usize,
+            length: doc.length,
+            pattern: doc.pattern,
+            learn: Learn { learning: None, bindings: doc.bindings },
         };
 
         Arpeggio {
@@ -77,6 +81,8 @@ impl Arpeggio {
                             x if x <= 1.0 => x / 2.0,
                             x => x - 1.0,
                         }),
+                    Action::Learn => learn::toggle(&state.learn, "length", 
+                        LENGTH_RANGE.0, LENGTH_RANGE.1),
                     _ => Action::Noop,
                 },
                 r: |mut out, window, id, state, focus| {
@@ -85,6 +91,10 @@ impl Arpeggio {
                         window.x + (window.w / 2) - (out_size / 2), 
                         window.y + 5, 
                         state.length.to_string());
+                    write!(out, "{}{}", cursor::Goto(
+                        window.x + (window.w / 2) - (out_size / 2), 
+                        window.y + 9, 
+                    ), learn::mark(&state.learn, "length"));
                 },
                 y_id: VOID_ID.clone(),
                 y_t: VOID_TRANSFORM,
@@ -101,6 +111,8 @@ impl Arpeggio {
                         "pattern".to_string(), 
                         ((state.pattern + 1) % NUM_PATTERNS) as f32
                     ),
+                    Action::Learn => learn::toggle(&state.learn, "pattern", 
+                        0.0, (NUM_PATTERNS - 1) as Param),
                     _ => Action::Noop,
                 },
                 b: |mut out, window, id, state, focus| {
@@ -113,6 +125,8 @@ impl Arpeggio {
                             2 => "SEQ".to_string(),
                             _ => "ERR".to_string(),
                         });
+                    write!(out, "{}{}", cursor::Goto(window.x + 5, window.y + 14), 
+                        learn::mark(&state.learn, "pattern"));
                 },
                 w_id: (FocusType::Button, 0),
                 w: |mut out, window, id, state, focus| {
@@ -140,6 +154,21 @@ impl Layer for Arpeggio {
     }
     fn dispatch(&mut self, action: Action) -> Action {
 
+        // Bound controllers move params whatever is in focus
+        match action {
+            Action::ControlChange(cc, value) => {
+                for moved in learn::moved(&self.state.learn, cc, value) {
+                    self.state = reduce(self.state.clone(), moved);
+                }
+                return Action::Noop;
+            },
+            a @ Action::BindParam(_, _, _, _) => {
+                self.state = reduce(self.state.clone(), a);
+                return Action::Noop;
+            },
+            _ => {}
+        }
+
         let (_, _action) = focus_dispatch((0,0),
                                           &mut self.focii, 
                                           &self.state, 
@@ -160,16 +189,19 @@ impl Layer for Arpeggio {
                 input: true,
             }]),
             a @ Action::SetParam(_,_) |
+            a @ Action::LearnParam(_,_,_) |
+            a @ Action::ForgetParam(_) |
             a @ Action::Up | 
             a @ Action::Down => a,
             _ => Action::Noop
         }
     }
     fn alpha(&self) -> bool { false }
-    fn save(&self) -> Option<Element> {
-        let mut root = Element::new("arpeggio");
-        param_add(&mut root, self.state.length, "length".to_string());
-        param_add(&mut root, self.state.pattern, "pattern".to_string());
-        return Some(root)
+    fn save(&self) -> Option<ModuleDoc> {
+        Some(ModuleDoc::Arpeggio(ArpeggioDoc {
+            length: self.state.length,
+            pattern: self.state.pattern,
+            bindings: self.state.learn.bindings.clone(),
+        }))
     }
 }
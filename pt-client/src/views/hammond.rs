@@ -1,9 +1,10 @@
 use std::io::{Write, Stdout};
-use xmltree::Element;
-use libcommon::{Action, Anchor, Param, param_map, param_add};
+use termion::cursor;
+use libcommon::{Action, Anchor, Param, ModuleDoc, HammondDoc, DRAWBARS};
 
 use crate::common::{MultiFocus, shift_focus, render_focii, focus_dispatch};
 use crate::common::{Screen, Direction, FocusType, Window};
+use crate::common::{Learn, learn};
 use crate::views::{Layer};
 use crate::components::{piano, slider, button};
 
@@ -21,6 +22,7 @@ pub struct HammondState {
     focus: (usize, usize),
     notes: Vec<Action>,
     eq: [Param; 9],
+    learn: Learn,
 }
 
 fn reduce(state: HammondState, action: Action) -> HammondState {
@@ -41,6 +43,7 @@ fn reduce(state: HammondState, action: Action) -> HammondState {
             },
             _ => state.notes.clone()
         },
+        learn: learn::reduce(state.learn.clone(), &action),
         eq: match action {
             Action::SetParam(key, val) => {
                 let mut new_eq = state.eq.clone();
@@ -67,29 +70,26 @@ fn reduce(state: HammondState, action: Action) -> HammondState {
 const SIZE: (u16, u16) = (70, 30);
 const EQ_STEP: Param = 0.001;
 const EQ_FACTOR: Param = 200.0;
+// A drawbar pulled all the way out
+const BAR_RANGE: (Param, Param) = (0.0, 1.0);
 
-impl Hammond {
-    pub fn new(x: u16, y: u16, width: u16, height: u16, mut doc: Element) -> Self {
-
-        let (_, params) = param_map(&mut doc);
+// A drawbar slider, with the controller moving it under its name
+fn drawbar(out: &mut Screen, x: u16, y: u16, name: &str, bar: usize, state: &HammondState) {
+    slider::render(out, x, y, name.to_string(), 
+        (state.eq[bar] * EQ_FACTOR) as i16, Direction::North);
+    write!(out, "{}{}", cursor::Goto(x, y + 2), 
+        learn::mark(&state.learn, DRAWBARS[bar])).unwrap();
+}
 
-        let initial_eq = [
-            *params.get("16").unwrap_or(&0.01),
-            *params.get("5.3").unwrap_or(&0.01),
-            *params.get("8").unwrap_or(&0.01),
-            *params.get("4").unwrap_or(&0.01),
-            *params.get("2.6").unwrap_or(&0.01),
-            *params.get("2").unwrap_or(&0.01),
-            *params.get("1.6").unwrap_or(&0.01),
-            *params.get("1.3").unwrap_or(&0.01),
-            *params.get("1").unwrap_or(&0.01),
-        ];
+impl Hammond {
+    pub fn new(x: u16, y: u16, width: u16, height: u16, doc: HammondDoc) -> Self {
 
         // Initialize State
         let initial_state: HammondState = HammondState {
             focus: (0,0),
             notes: vec![],
-            eq: initial_eq,
+            eq: doc.drawbars,
+            learn: Learn { learning: None, bindings: doc.bindings },
         };
 
         Hammond {
@@ -101,50 +101,50 @@ impl Hammond {
             focii: vec![vec![
                 MultiFocus::<HammondState> {
                     y: |mut out, window, id, state, focus| {
-                        slider::render(out, window.x+5, window.y+5, "16'".to_string(), 
-                            (state.eq[0] * EQ_FACTOR) as i16, Direction::North)
+                        drawbar(out, window.x+5, window.y+5, "16'", 0, state)
                     },
                     y_t: |action, id, state| match action {
                         Action::Up => { Action::SetParam("16".to_string(), 
                                                          state.eq[0] + EQ_STEP) },
                         Action::Down => { Action::SetParam("16".to_string(), 
                                                          state.eq[0] - EQ_STEP) },
+                        Action::Learn => learn::toggle(&state.learn, "16", BAR_RANGE.0, BAR_RANGE.1),
                         _ => Action::Noop
                     },
                     y_id: (FocusType::Button, 0),
                     b: |mut out, window, id, state, focus| {
-                        slider::render(out, window.x+10, window.y+5, "5⅓'".to_string(), 
-                            (state.eq[1] * EQ_FACTOR) as i16, Direction::North)
+                        drawbar(out, window.x+10, window.y+5, "5⅓'", 1, state)
                     },
                     b_t: |action, id, state| match action {
                         Action::Up => { Action::SetParam("5.3".to_string(), 
                                                          state.eq[1] + EQ_STEP) },
                         Action::Down => { Action::SetParam("5.3".to_string(), 
                                                          state.eq[1] - EQ_STEP) },
+                        Action::Learn => learn::toggle(&state.learn, "5.3", BAR_RANGE.0, BAR_RANGE.1),
                         _ => Action::Noop
                     },
                     b_id: (FocusType::Button, 0),
                     p: |mut out, window, id, state, focus| {
-                        slider::render(out, window.x+15, window.y+5, "8'".to_string(), 
-                            (state.eq[2] * EQ_FACTOR) as i16, Direction::North)
+                        drawbar(out, window.x+15, window.y+5, "8'", 2, state)
                     },
                     p_t: |action, id, state| match action { 
                         Action::Up => { Action::SetParam("8".to_string(), 
                                                          state.eq[2] + EQ_STEP) },
                         Action::Down => { Action::SetParam("8".to_string(), 
                                                          state.eq[2] - EQ_STEP) },
+                        Action::Learn => learn::toggle(&state.learn, "8", BAR_RANGE.0, BAR_RANGE.1),
                         _ => Action::Noop
                     },
                     p_id: (FocusType::Button, 0),
                     g: |mut out, window, id, state, focus| {
-                        slider::render(out, window.x+20, window.y+5, "4'".to_string(), 
-                            (state.eq[3] * EQ_FACTOR) as i16, Direction::North)
+                        drawbar(out, window.x+20, window.y+5, "4'", 3, state)
                     },
                     g_t: |action, id, state| match action { 
                         Action::Up => { Action::SetParam("4".to_string(), 
                                                          state.eq[3] + EQ_STEP) },
                         Action::Down => { Action::SetParam("4".to_string(), 
                                                          state.eq[3] - EQ_STEP) },
+                        Action::Learn => learn::toggle(&state.learn, "4", BAR_RANGE.0, BAR_RANGE.1),
                         _ => Action::Noop
                     },
                     g_id: (FocusType::Button, 0),
@@ -160,63 +160,63 @@ impl Hammond {
                     w_id: (FocusType::Void, 0),
 
                     y: |mut out, window, id, state, focus| {
-                        slider::render(out, window.x+25, window.y+5, "2⅔'".to_string(), 
-                            (state.eq[4] * EQ_FACTOR) as i16, Direction::North)
+                        drawbar(out, window.x+25, window.y+5, "2⅔'", 4, state)
                     },
                     y_t: |action, id, state| match action { 
                         Action::Up => { Action::SetParam("2.6".to_string(), 
                                                          state.eq[4] + EQ_STEP) },
                         Action::Down => { Action::SetParam("2.6".to_string(), 
                                                          state.eq[4] - EQ_STEP) },
+                        Action::Learn => learn::toggle(&state.learn, "2.6", BAR_RANGE.0, BAR_RANGE.1),
                         _ => Action::Noop
                     }, 
                     y_id: (FocusType::Button, 0),
 
                     b: |mut out, window, id, state, focus| {
-                        slider::render(out, window.x+30, window.y+5, "2'".to_string(), 
-                            (state.eq[5] * EQ_FACTOR) as i16, Direction::North)
+                        drawbar(out, window.x+30, window.y+5, "2'", 5, state)
                     },
                     b_t: |action, id, state| match action {
                         Action::Up => { Action::SetParam("2".to_string(), 
                                                          state.eq[5] + EQ_STEP) },
                         Action::Down => { Action::SetParam("2".to_string(), 
                                                          state.eq[5] - EQ_STEP) },
+                        Action::Learn => learn::toggle(&state.learn, "2", BAR_RANGE.0, BAR_RANGE.1),
                         _ => Action::Noop
                     },
                     b_id: (FocusType::Button, 0),
                     p: |mut out, window, id, state, focus| {
-                        slider::render(out, window.x+35, window.y+5, "1⅗'".to_string(), 
-                            (state.eq[6] * EQ_FACTOR) as i16, Direction::North)
+                        drawbar(out, window.x+35, window.y+5, "1⅗'", 6, state)
                     },
                     p_t: |action, id, state| match action {
                         Action::Up => { Action::SetParam("1.6".to_string(), 
                                                          state.eq[6] + EQ_STEP) },
                         Action::Down => { Action::SetParam("1.6".to_string(), 
                                                          state.eq[6] - EQ_STEP) },
+                        Action::Learn => learn::toggle(&state.learn, "1.6", BAR_RANGE.0, BAR_RANGE.1),
                         _ => Action::Noop
                     },
                     p_id: (FocusType::Button, 0),
                     g: |mut out, window, id, state, focus| {
-                        slider::render(out, window.x+40, window.y+5, "1⅓'".to_string(), 
-                            (state.eq[7] * EQ_FACTOR) as i16, Direction::North)
+                        drawbar(out, window.x+40, window.y+5, "1⅓'", 7, state)
                     },
                     g_t: |action, id, state| match action {
                         Action::Up => { Action::SetParam("1.3".to_string(), 
                                                          state.eq[7] + EQ_STEP) },
                         Action::Down => { Action::SetParam("1.3".to_string(), 
                                                          state.eq[7] - EQ_STEP) },
+                        Action::Learn => learn::toggle(&state.learn, "1.3", BAR_RANGE.0, BAR_RANGE.1),
                         _ => Action::Noop
                     },
                     g_id: (FocusType::Button, 0),
                     r: |mut out, window, id, state, focus| {
-                        slider::render(out, window.x+45, window.y+5, "1'".to_string(), 
-                            (state.eq[8] * EQ_FACTOR) as i16, Direction::North)
+                        drawbar(out, window.x+45, window.y+5, "1'", 8, state)
                     },
                     r_t: |action, id, state| match action { 
                         Action::Up => { Action::SetParam("1".to_string(), 
                                                          state.eq[8] + EQ_STEP) },
                         Action::Down => { Action::SetParam("1".to_string(), 
                                                          state.eq[8] - EQ_STEP) },
+                        Action::Learn => learn::toggle(&state.learn, "1", BAR_RANGE.0, BAR_RANGE.1),
                         _ => Action::Noop
                     }, 
                     r_id: (FocusType::Button, 0),
@@ -244,6 +244,21 @@ impl Layer for Hammond {
 
     fn dispatch(&mut self, action: Action) -> Action {
 
+        // Bound controllers move drawbars whatever is in focus
+        match action {
+            Action::ControlChange(cc, value) => {
+                for moved in learn::moved(&self.state.learn, cc, value) {
+                    self.state = reduce(self.state.clone(), moved);
+                }
+                return Action::Noop;
+            },
+            a @ Action::BindParam(_, _, _, _) => {
+                self.state = reduce(self.state.clone(), a);
+                return Action::Noop;
+            },
+            _ => {}
+        }
+
         // Intercept arrow actions to change focus
         let (focus, default) = focus_dispatch(self.state.focus, 
                                               &mut self.focii, 
@@ -272,23 +287,18 @@ impl Layer for Hammond {
                 },
                 a @ Action::Up | 
                 a @ Action::Down |
-                a @ Action::SetParam(_,_) => a,
+                a @ Action::SetParam(_,_) |
+                a @ Action::LearnParam(_,_,_) |
+                a @ Action::ForgetParam(_) => a,
                 _ => { Action::Noop }
             }
         } else { Action::Noop }
     }
     fn alpha(&self) -> bool { false }
-    fn save(&self) -> Option<Element> { 
-        let mut root = Element::new("hammond");
-        param_add(&mut root, self.state.eq[0], "16".to_string());
-        param_add(&mut root, self.state.eq[1], "5.3".to_string());
-        param_add(&mut root, self.state.eq[2], "8".to_string());
-        param_add(&mut root, self.state.eq[3], "4".to_string());
-        param_add(&mut root, self.state.eq[4], "2.6".to_string());
-        param_add(&mut root, self.state.eq[5], "2".to_string());
-        param_add(&mut root, self.state.eq[6], "1.6".to_string());
-        param_add(&mut root, self.state.eq[7], "1.3".to_string());
-        param_add(&mut root, self.state.eq[8], "1".to_string());
-        Some(root)
+    fn save(&self) -> Option<ModuleDoc> { 
+        Some(ModuleDoc::Hammond(HammondDoc {
+            drawbars: self.state.eq,
+            bindings: self.state.learn.bindings.clone(),
+        }))
     }
 }
@@ -1,7 +1,6 @@
 use std::io::{Write, Stdout};
 use termion::{cursor};
-use xmltree::Element;
-use libcommon::Action;
+use libcommon::{Action, ModuleDoc};
 
 use crate::common::Screen;
 use crate::views::{Layer};
@@ -72,5 +71,5 @@ impl Layer for Help {
         }
     }
     fn alpha(&self) -> bool { true }
-    fn save(&self) -> Option<Element> { None }
+    fn save(&self) -> Option<ModuleDoc> { None }
 }
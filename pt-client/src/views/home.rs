@@ -1,17 +1,17 @@
 use std::io::{self, Write, Stdout, BufReader};
 use std::io::prelude::*;
 use termion::{color, cursor};
-use xmltree::Element;
-use libcommon::{Action, Anchor};
+use libcommon::{Action, Anchor, ModuleDoc, read_header, config};
 
 use crate::common::Screen;
-use crate::common::{get_files, PALIT_PROJECTS};
+use crate::common::get_files;
 use crate::views::Layer;
 use crate::components::{logo, button, bigtext};
 
 const NUM_FOCII: usize = 1;
 const NUM_PROJECTS: usize = 4;
 const SIZE: (u16, u16) = (34, 10);
+const MAX_PROBLEMS: usize = 8;
 
 // Store for heavy, static vars
 pub struct Home {
@@ -27,6 +27,8 @@ pub struct Home {
 pub struct HomeState {
     motd: String,
     projects: Vec<String>,
+    broken: Vec<String>,
+    problems: Vec<String>,
     scroll_x: usize,
 }
 
@@ -41,6 +43,12 @@ fn reduce(state: HomeState, action: Action) -> HomeState {
                 new_projects
             },
             _ => state.projects.clone(),
+        },
+        broken: state.broken.clone(),
+        problems: match action.clone() {
+            Action::Error(message) => message.lines().map(|l| l.trim().to_string()).collect(),
+            Action::Left | Action::Right => vec![],
+            _ => state.problems.clone(),
         },
 	    scroll_x: match action {
             Action::Left => {
@@ -56,12 +64,20 @@ fn reduce(state: HomeState, action: Action) -> HomeState {
 impl Home {
     pub fn new(x: u16, y: u16, width: u16, height: u16) -> Self {
 
-        let projects = get_files(PALIT_PROJECTS, "xml", vec![]).unwrap();
+        let projects = get_files(&config().projects, "xml", vec![]).unwrap();
+
+        // Still listed so they can be opened to see what is wrong. Only
+        // ... headers are read here, projects are checked when opened.
+        let broken = projects.iter()
+            .filter(|p| read_header(p.to_string()).is_err())
+            .cloned().collect();
 
         // Initialize State
         let initial_state: HomeState = HomeState {
             motd: "It's Fun!".to_string(),
             projects,
+            broken,
+            problems: vec![],
 	        scroll_x: 0,
         };
 
@@ -97,11 +113,24 @@ impl Layer for Home {
                     3 => write!(out, "{}", color::Bg(color::Magenta)).unwrap(),
                     _ => write!(out, "{}", color::Bg(color::Reset)).unwrap(), 
                 }
-                write!(out, " {} ", project).unwrap();
+                if self.state.broken.contains(project) {
+                    write!(out, " !{} ", project).unwrap();
+                    col[row] += 1;
+                } else {
+                    write!(out, " {} ", project).unwrap();
+                }
                 col[row] += project.len() as u16 + 4;
             }
         }
 
+        // Why the last project could not be opened
+        write!(out, "{}{}", color::Bg(color::Reset), color::Fg(color::Red)).unwrap();
+        let max_len = self.width.saturating_sub(self.x) as usize;
+        for (i, problem) in self.state.problems.iter().take(MAX_PROBLEMS).enumerate() {
+            write!(out, "{}{}", cursor::Goto(self.x, self.y + SIZE.1 + i as u16),
+                problem.chars().take(max_len).collect::<String>()).unwrap();
+        }
+
         write!(out, "{}{}", color::Bg(color::Reset), color::Fg(color::Reset)).unwrap();
     }
 
@@ -136,5 +165,5 @@ impl Layer for Home {
         }
     }
     fn alpha(&self) -> bool { false }
-    fn save(&self) -> Option<Element> { None }
+    fn save(&self) -> Option<ModuleDoc> { None }
 }
@@ -0,0 +1,54 @@
+use std::io::Write;
+use termion::cursor;
+use libcommon::{Action, Anchor, ModuleDoc, InputDoc, config};
+
+use crate::common::{Screen, Window};
+use crate::views::{Layer};
+
+pub struct Input {
+    window: Window,
+    doc: InputDoc,
+}
+
+impl Input {
+    pub fn new(x: u16, y: u16, width: u16, height: u16, doc: InputDoc) -> Self {
+        Input {
+            window: Window { x, y, w: width, h: height },
+            doc,
+        }
+    }
+}
+
+impl Layer for Input {
+    fn render(&self, out: &mut Screen, target: bool) {
+        let win = self.window;
+        let device = if self.doc.device.is_empty() {
+            &config().audio_card
+        } else {
+            &self.doc.device
+        };
+        write!(out, "{}INPUT hw:{}", cursor::Goto(win.x, win.y), device).unwrap();
+        for (i, (name, _)) in self.doc.anchors().iter().enumerate() {
+            write!(out, "{}{}", cursor::Goto(win.x, win.y + 2 + i as u16), name).unwrap();
+        }
+    }
+    fn dispatch(&mut self, action: Action) -> Action {
+        match action {
+            // Inputs only have outputs, route them into a track to record
+            Action::Route => Action::ShowAnchors(self.doc.anchors().into_iter()
+                .enumerate().map(|(i, (name, _))| Anchor {
+                    index: i as u16,
+                    module_id: 0,
+                    name,
+                    input: false,
+                }).collect()),
+            a @ Action::Up |
+            a @ Action::Down => a,
+            _ => Action::Noop
+        }
+    }
+    fn alpha(&self) -> bool { false }
+    fn save(&self) -> Option<ModuleDoc> {
+        Some(ModuleDoc::Input(self.doc.clone()))
+    }
+}
@@ -1,7 +1,6 @@
 use std::io::Write;
 use termion::cursor;
-use xmltree::Element;
-use libcommon::{Action, Anchor, param_map};
+use libcommon::{Action, Anchor, Key, ModuleDoc, KeyboardDoc, KEYBED};
 
 use crate::common::{Screen, Window};
 use crate::views::{Layer};
@@ -18,6 +17,10 @@ pub struct Keyboard {
 
 #[derive(Clone, Debug)]
 pub struct KeyboardState {
+    // Where the notes come from, see KeyboardDoc
+    device: String,
+    // MIDI ports it could play from instead
+    ports: Vec<String>,
     keys_active: Vec<Action>,
     octave: usize,
     shift: i8,
@@ -26,6 +29,14 @@ pub struct KeyboardState {
 
 fn reduce(state: KeyboardState, action: Action) -> KeyboardState {
     KeyboardState {
+        device: match &action {
+            Action::SetDevice(device) => device.clone(),
+            _ => state.device.clone(),
+        },
+        ports: match &action {
+            Action::MidiPorts(ports) => ports.clone(),
+            _ => state.ports.clone(),
+        },
         octave: match action {
             Action::Octave(true) => state.octave + 1,
             Action::Octave(false) => state.octave - 1,
@@ -48,19 +59,20 @@ fn reduce(state: KeyboardState, action: Action) -> KeyboardState {
             _ => state.keys_active.clone()
         },
         shift: state.shift,
-        velocity: state.velocity
+        velocity: state.velocity,
     }
 }
 
 impl Keyboard {
-    pub fn new(x: u16, y: u16, width: u16, height: u16, mut doc: Element) -> Self {
-        let (_, params) = param_map(&mut doc);
+    pub fn new(x: u16, y: u16, width: u16, height: u16, doc: KeyboardDoc) -> Self {
         // Initialize State
         let initial_state: KeyboardState = KeyboardState {
+            device: doc.device,
+            ports: vec![],
             keys_active: vec![],
-            octave: *params.get("octave").unwrap_or(&3.0) as usize,
-            shift: *params.get("shift").unwrap_or(&0.0) as i8,
-            velocity: *params.get("velocity").unwrap_or(&0.3)
+            octave: doc.octave as usize,
+            shift: doc.shift,
+            velocity: doc.velocity
         };
 
         Keyboard {
@@ -87,6 +99,8 @@ impl Layer for Keyboard {
         write!(out, "{}OCT:{}", cursor::Goto(win.x, win.y), self.state.octave);
         write!(out, "{}SHIFT:{}", cursor::Goto(win.x, win.y+1), self.state.shift);
         write!(out, "{}VEL:{}", cursor::Goto(win.x, win.y+2), self.state.velocity);
+        write!(out, "{}IN:{}", cursor::Goto(win.x, win.y+3), 
+            if self.state.device.is_empty() { "screen" } else { &self.state.device });
     }
     fn dispatch(&mut self, action: Action) -> Action {
         self.state = reduce(self.state.clone(), action.clone());
@@ -97,11 +111,28 @@ impl Layer for Keyboard {
                 name: "Keys".to_string(),
                 input: false,
             }]),
+            // Cycle through the keybed, the screen and every MIDI port
+            Action::Left | Action::Right => {
+                let mut devices = vec![KEYBED.to_string(), String::new()];
+                devices.extend(self.state.ports.iter().cloned());
+                let current = devices.iter().position(|d| *d == self.state.device).unwrap_or(0);
+                let next = if action == Action::Left { current + devices.len() - 1 } else { current + 1 };
+                let a = Action::SetDevice(devices[next % devices.len()].clone());
+                self.state = reduce(self.state.clone(), a.clone());
+                a
+            },
             a @ Action::Up | 
             a @ Action::Down => a,
             _ => Action::Noop
         }
     }
     fn alpha(&self) -> bool { false }
-    fn save(&self) -> Option<Element> { None }
+    fn save(&self) -> Option<ModuleDoc> {
+        Some(ModuleDoc::Keyboard(KeyboardDoc {
+            device: self.state.device.clone(),
+            octave: self.state.octave as Key,
+            shift: self.state.shift,
+            velocity: self.state.velocity,
+        }))
+    }
 }
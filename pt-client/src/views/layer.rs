@@ -1,10 +1,9 @@
-use xmltree::Element;
-use libcommon::Action;
+use libcommon::{Action, ModuleDoc};
 use crate::common::Screen;
 
 pub trait Layer {
     fn render(&self, out: &mut Screen, target: bool);
     fn dispatch(&mut self, a: Action) -> Action;
     fn alpha(&self) -> bool;
-    fn save(&self) -> Option<Element>;
+    fn save(&self) -> Option<ModuleDoc>;
 }
\ No newline at end of file
@@ -1,21 +1,21 @@
 use std::io::Write;
 use termion::cursor;
-use xmltree::Element;
-use libcommon::{Action, Anchor, Module};
+use libcommon::{Action, Anchor, Module, ModuleDoc, config};
 
 use crate::common::{Screen, Direction, FocusType, Window};
 use crate::common::{MultiFocus, ID, focus_dispatch, render_focii};
-use crate::common::{get_files, PALIT_MODULES};
+use crate::common::get_files;
 use crate::components::{popup};
 use crate::views::{Layer};
 
 static PADDING: (u16, u16) = (3, 3);
 
-const CORE_MODULES: [&str; 4] = [
+const CORE_MODULES: [&str; 5] = [
     "timeline",
     "hammond",
     "arpeggio",
     "keyboard",
+    "input",
 ];
 
 pub struct Modules {
@@ -34,7 +34,7 @@ pub struct ModulesState {
 impl Modules {
     pub fn new(x: u16, y: u16, width: u16, height: u16) -> Self {
         let mut core: Vec<String> = CORE_MODULES.iter().map(|a| a.to_string()).collect();
-        let mut modules: Vec<String> = get_files(PALIT_MODULES, "dsp", core).unwrap();
+        let mut modules: Vec<String> = get_files(&config().modules, "dsp", core).unwrap();
         modules = modules.iter().filter_map(|m| {
             let parts: Vec<&str> = m.split(".").collect();
             if parts.len() < 2 {
@@ -178,5 +178,5 @@ impl Layer for Modules {
         } else { Action::Noop }
     }
     fn alpha(&self) -> bool { true }
-    fn save(&self) -> Option<Element> { None }
+    fn save(&self) -> Option<ModuleDoc> { None }
 }
\ No newline at end of file
@@ -1,8 +1,7 @@
 use std::io::{Write, Stdout};
 use std::collections::HashMap;
 use termion::cursor;
-use xmltree::Element;
-use libcommon::{Action, Anchor, Route};
+use libcommon::{Action, Anchor, Route, ModuleDoc, PatchDoc};
 
 use crate::common::{MultiFocus, FocusType, ID, VOID_ID};
 use crate::common::{shift_focus, render_focii, focus_dispatch};
@@ -19,6 +18,11 @@ pub struct PatchState {
     pub focus: (usize, usize),
     pub selected_route: Option<u16>,
     pub selected_anchor: Option<u16>,
+    // Why pt-sound refused the last patch
+    pub refused: Option<String>,
+    // Peaks from pt-sound, by route and by (module, anchor)
+    pub route_levels: HashMap<u16, f32>,
+    pub anchor_levels: HashMap<(u16, u16), f32>,
 }
 
 pub struct Patch {
@@ -197,8 +201,50 @@ fn reduce(state: PatchState, action: Action) -> PatchState {
                 }
                 new_routes
             },
+            // pt-sound never made this connection, take it back
+            Action::PatchRefused(m_id, a_id, r_id, _) => {
+                let mut new_routes = state.routes.clone();
+                if let Some(route) = new_routes.get_mut(&r_id) {
+                    route.patch.retain(|anchor| !(anchor.index == a_id && anchor.module_id == m_id));
+                }
+                new_routes
+            },
             _ => state.routes.clone()
         },
+        refused: match action {
+            Action::PatchRefused(_, _, _, ref reason) => Some(reason.clone()),
+            Action::PatchAnchor(_) |
+            Action::DelPatch(_,_,_) |
+            Action::PatchIn(_,_,_) |
+            Action::PatchOut(_,_,_) => None,
+            _ => state.refused.clone()
+        },
+        route_levels: match action {
+            Action::RouteLevel(r_id, peak) => {
+                let mut new_levels = state.route_levels.clone();
+                new_levels.insert(r_id, peak);
+                new_levels
+            },
+            Action::DelRoute(r_id) => {
+                let mut new_levels = state.route_levels.clone();
+                new_levels.remove(&r_id);
+                new_levels
+            },
+            _ => state.route_levels.clone()
+        },
+        anchor_levels: match action {
+            Action::AnchorLevel(m_id, a_id, peak) => {
+                let mut new_levels = state.anchor_levels.clone();
+                new_levels.insert((m_id, a_id), peak);
+                new_levels
+            },
+            Action::DelModule(m_id) => {
+                let mut new_levels = state.anchor_levels.clone();
+                new_levels.retain(|(id, _), _| *id != m_id);
+                new_levels
+            },
+            _ => state.anchor_levels.clone()
+        },
         focus: state.focus,
         selected_anchor: match action {
             Action::PatchAnchor(id) => {
@@ -242,11 +288,11 @@ fn reduce(state: PatchState, action: Action) -> PatchState {
 }
 
 impl Patch {
-    pub fn new(x: u16, y: u16, width: u16, height: u16, doc: Option<Element>) -> Self {
+    pub fn new(x: u16, y: u16, width: u16, height: u16, doc: Option<PatchDoc>) -> Self {
 
         // Initialize State
-        let mut initial_state: PatchState = if let Some(el) = doc {
-            patch::read(el)
+        let mut initial_state: PatchState = if let Some(doc) = doc {
+            patch::read(doc)
         } else { 
             let mut default_routes = HashMap::new();
             default_routes.insert(1, Route {
@@ -259,6 +305,9 @@ impl Patch {
                 selected_anchor: None,
                 selected_route: None,
                 focus: (0,0),
+                refused: None,
+                route_levels: HashMap::new(),
+                anchor_levels: HashMap::new(),
             }
         };
 
@@ -273,6 +322,13 @@ impl Patch {
     }
 }
 
+// One cell meter, eight steps of 6dB up to full scale
+fn level_glyph(peak: f32) -> &'static str {
+    if peak <= 0.0 { return " "; }
+    let step = ((20.0 * peak.log10() + 48.0) / 6.0).floor().max(0.0).min(7.0);
+    ["▁", "▂", "▃", "▄", "▅", "▆", "▇", "█"][step as usize]
+}
+
 fn render_patch(out: &mut Screen, 
     a: &Anchor, 
     r_id: u16, 
@@ -318,6 +374,14 @@ impl Layer for Patch {
                 n => format!("{}", n)
             }).unwrap();
 
+            // What the route mixes, above its name
+            if let Some(peak) = self.state.route_levels.get(&route.id) {
+                write!(out, "{}{}", cursor::Goto(
+                    PADDING.0 + win.x + route.id * 2 - 1,
+                    PADDING.1 + win.y - 2,
+                ), level_glyph(*peak)).unwrap();
+            }
+
             // Draw vertical line
             for y in 0..(win.h - PADDING.1 * 2 - 1) {
                 write!(out, "{}│", cursor::Goto(
@@ -336,6 +400,23 @@ impl Layer for Patch {
             }
         }
 
+        // What each output puts out, after its name
+        for (_, anchor) in self.state.anchors.iter().filter(|(_, a)| !a.input) {
+            if let Some(peak) = self.state.anchor_levels.get(&(anchor.module_id, anchor.index)) {
+                write!(out, "{}{}", cursor::Goto(
+                    anchor_x + PADDING.0 + 4 + anchor.name.chars().count() as u16,
+                    PADDING.1 + win.y + anchor.index * 2,
+                ), level_glyph(*peak)).unwrap();
+            }
+        }
+
+        if let Some(reason) = &self.state.refused {
+            write!(out, "{}Refused: {}", cursor::Goto(
+                PADDING.0 + win.x,
+                win.y + win.h - PADDING.1 - 1,
+            ), reason).unwrap();
+        }
+
         if let Some(a_id) = self.state.selected_anchor {
             let anchor_y = PADDING.1 + win.y + (a_id * 2);
             let anchor = self.state.anchors.get(&a_id).unwrap();
@@ -415,7 +496,7 @@ impl Layer for Patch {
         } else { Action::Noop }
     }
     fn alpha(&self) -> bool { true }
-    fn save(&self) -> Option<Element> { 
-        Some(patch::write(self.state.clone()))
+    fn save(&self) -> Option<ModuleDoc> { 
+        Some(ModuleDoc::Patch(patch::write(self.state.clone())))
     }
 }
@@ -1,12 +1,12 @@
 use std::io::Write;
 use termion::cursor;
-use xmltree::Element;
-use libcommon::{Action, Anchor, Param, param_map};
+use libcommon::{Action, Anchor, Param, ModuleDoc, PluginDoc};
 
 use crate::views::{Layer};
 use crate::components::{popup, ivories, slider};
 use crate::common::{Screen, Window, ID, VOID_ID, FocusType, Direction};
 use crate::common::{MultiFocus, render_focii, shift_focus};
+use crate::common::{Learn, learn};
 
 #[derive(Clone, Debug)]
 struct FaustParam {
@@ -34,6 +34,7 @@ pub struct PluginState {
     params: Vec<FaustParam>,
     faust_anchors: Vec<(String, bool)>, // name, input
     focus: (usize, usize),
+    learn: Learn,
 }
 
 const PADDING: (u16, u16) = (5,5);
@@ -87,6 +88,7 @@ fn generate_focii(params: &Vec<FaustParam>) -> Vec<Vec<MultiFocus<PluginState>>>
                     if target < param.min { target = param.min };
                     Action::SetParam(param.label.clone(), target)
                 },
+                Action::Learn => learn::toggle(&state.learn, &param.label, param.min, param.max),
                 _ => a
             }
         };
@@ -97,7 +99,8 @@ fn generate_focii(params: &Vec<FaustParam>) -> Vec<Vec<MultiFocus<PluginState>>>
                 slider::render(out,
                     PADDING.0 + window.x, 
                     PADDING.1 + window.y + id.1 * 3,
-                    format!("{} ({})", param.label, param.value),
+                    format!("{} ({}) {}", param.label, param.value, 
+                        learn::mark(&state.learn, &param.label)),
                     (param.value * factor) as i16, 
                     Direction::East);
 
@@ -156,18 +159,19 @@ fn reduce(state: PluginState, action: Action) -> PluginState {
             },
             _ => state.faust_anchors.clone()
         },
+        learn: learn::reduce(state.learn.clone(), &action),
         focus: state.focus,
     }
 }
 
 impl Plugin {
-    pub fn new(x: u16, y: u16, width: u16, height: u16, mut doc: Element) -> Self {
-        let (_, params) = param_map(&mut doc);
+    pub fn new(x: u16, y: u16, width: u16, height: u16, doc: PluginDoc) -> Self {
         // Initialize State
         let initial_state: PluginState = PluginState {
             params: vec![],
             faust_anchors: vec![],
             focus: (0, 0),
+            learn: Learn { learning: None, bindings: doc.bindings },
         };
 
         Plugin {
@@ -200,6 +204,21 @@ impl Layer for Plugin {
         write!(out, "{}{}", cursor::Goto(win.x + PADDING.0, win.y + 2), self.name);
     }
     fn dispatch(&mut self, action: Action) -> Action {
+        // Bound controllers move params whatever is in focus
+        match action {
+            Action::ControlChange(cc, value) => {
+                for moved in learn::moved(&self.state.learn, cc, value) {
+                    self.state = reduce(self.state.clone(), moved);
+                }
+                return Action::Noop;
+            },
+            a @ Action::BindParam(_, _, _, _) => {
+                self.state = reduce(self.state.clone(), a);
+                return Action::Noop;
+            },
+            _ => {}
+        }
+
         // Let the focus transform the action 
         let _action = {
             if let Some(multi_focus_row) = &mut self.focii.get_mut(self.state.focus.1) {
@@ -245,7 +264,9 @@ impl Layer for Plugin {
                     }
                 anchors
             }))),
-            a @ Action::SetParam(_,_) => (self.state.focus, Some(a)),
+            a @ Action::SetParam(_,_) |
+            a @ Action::LearnParam(_,_,_) |
+            a @ Action::ForgetParam(_) => (self.state.focus, Some(a)),
             _ => (self.state.focus, None)
         };
 
@@ -257,7 +278,11 @@ impl Layer for Plugin {
         }
     }
     fn alpha(&self) -> bool { false }
-    fn save(&self) -> Option<Element> { 
-        Some(Element::new(&self.name))
+    fn save(&self) -> Option<ModuleDoc> { 
+        Some(ModuleDoc::Plugin(PluginDoc {
+            name: self.name.clone(),
+            params: vec![],
+            bindings: self.state.learn.bindings.clone(),
+        }))
     }
 }
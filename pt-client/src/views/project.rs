@@ -1,7 +1,6 @@
 use std::io::Write;
 use termion::cursor;
-use xmltree::Element;
-use libcommon::{Action, Anchor, Module};
+use libcommon::{Action, Anchor, Module, ModuleDoc, Offset};
 
 use crate::common::{Screen, Direction, FocusType, Window};
 use crate::common::{MultiFocus, ID, focus_dispatch, render_focii};
@@ -19,6 +18,14 @@ pub struct ProjectState {
     title: String,
     modules: Vec<Module>,
     focus: (usize, usize),
+    // Bounce between the loop marks instead of seq_in and seq_out
+    looped: bool,
+    // Frames bounced and the total, while a bounce runs
+    progress: Option<(Offset, Offset)>,
+    // Rate, period, buffer and output latency reported by sound
+    audio: Option<(u32, u32, u32, Offset)>,
+    // Underruns and dropped actions since sound started
+    glitches: (u32, u32),
 }
 
 impl Project {
@@ -27,6 +34,10 @@ impl Project {
             focus: (0,0),
             title: "".to_string(),
             modules: vec![],
+            looped: false,
+            progress: None,
+            audio: None,
+            glitches: (0, 0),
         };
         return Project {
             window: Window {
@@ -53,10 +64,27 @@ fn reduce(state: ProjectState, action: Action) -> ProjectState {
             Action::ShowProject(title, _) => title,
             _ => state.title.clone()
         },
-        modules: match action {
+        modules: match action.clone() {
             Action::ShowProject(_, modules) => modules,
             _ => state.modules.clone()
         },
+        looped: match action {
+            Action::LoopMode(on) => on,
+            _ => state.looped,
+        },
+        progress: match action {
+            Action::Bounce(_, _, _) => Some((0, 0)),
+            Action::BounceProgress(done, total) => Some((done, total)),
+            _ => state.progress,
+        },
+        audio: match action {
+            Action::AudioStatus(rate, period, buffer, latency) => Some((rate, period, buffer, latency)),
+            _ => state.audio,
+        },
+        glitches: match action {
+            Action::Glitches(xruns, dropped) => (xruns, dropped),
+            _ => state.glitches,
+        },
     }
 }
 
@@ -123,9 +151,15 @@ fn generate_focii(modules: &Vec<Module>) -> Vec<Vec<MultiFocus<ProjectState>>> {
                 "Close Project"
             ),
         y_t: |_, _, _| Action::Close,
-        g_id: (FocusType::Void, 0), 
-        g: VOID_RENDER,
-        g_t: |_, _, _| Action::Noop,
+        g_id: (FocusType::Button, 0), 
+        g: |mut out, window, id, state, focus|
+            button::render(out,
+                PADDING.0 + window.x, 
+                window.y + window.h - PADDING.1 - 14,
+                window.w - (2 * PADDING.0),
+                "Bounce Mix"
+            ),
+        g_t: |_, _, state| Action::Bounce(state.title.clone(), false, state.looped),
         r_id:(FocusType::Button, 0), 
         r: |mut out, window, id, state, focus| 
             button::render(out,
@@ -135,12 +169,23 @@ fn generate_focii(modules: &Vec<Module>) -> Vec<Vec<MultiFocus<ProjectState>>> {
                 "Save Project"
             ),
         r_t: |_, _, _| Action::Save,
-        p_id:(FocusType::Void, 0), 
-        p: VOID_RENDER,
-        p_t: |_, _, _| Action::Noop,
-        b_id: (FocusType::Void, 0), 
-        b: VOID_RENDER,
-        b_t: |_, _, _| Action::Noop,
+        p_id:(FocusType::Button, 0), 
+        p: |mut out, window, id, state, focus| {
+            write!(out, "{}Bounce {}", cursor::Goto(
+                PADDING.0 + window.x,
+                window.y + window.h - PADDING.1 - 16,
+            ), if state.looped { "the loop" } else { "the whole song" }).unwrap();
+        },
+        p_t: |_, _, state| Action::LoopMode(!state.looped),
+        b_id: (FocusType::Button, 0), 
+        b: |mut out, window, id, state, focus|
+            button::render(out,
+                PADDING.0 + window.x, 
+                window.y + window.h - PADDING.1 - 10,
+                window.w - (2 * PADDING.0),
+                "Bounce Stems"
+            ),
+        b_t: |_, _, state| Action::Bounce(state.title.clone(), true, state.looped),
         active: None,
     }]);
 
@@ -158,6 +203,33 @@ impl Layer for Project {
         render_focii(out, self.window, 
             self.state.focus.clone(), 
             &self.focii, &self.state, true, !target);
+
+        if let Some((done, total)) = self.state.progress {
+            let status = if total > 0 && done >= total {
+                "Bounced".to_string()
+            } else if total > 0 {
+                format!("Bouncing {}%", u64::from(done) * 100 / u64::from(total))
+            } else {
+                "Bouncing".to_string()
+            };
+            write!(out, "{}{}", cursor::Goto(
+                PADDING.0 + self.window.x,
+                self.window.y + self.window.h - PADDING.1 - 18,
+            ), status).unwrap();
+        }
+
+        if let Some((rate, period, buffer, latency)) = self.state.audio {
+            let ms = |frames: u32| f64::from(frames) * 1000.0 / f64::from(rate.max(1));
+            write!(out, "{}{}Hz  {} / {} frames  {:.1}ms out", cursor::Goto(
+                PADDING.0 + self.window.x,
+                self.window.y + self.window.h - PADDING.1 - 20,
+            ), rate, period, buffer, ms(latency)).unwrap();
+            let (xruns, dropped) = self.state.glitches;
+            write!(out, "{}{} underruns  {} dropped", cursor::Goto(
+                PADDING.0 + self.window.x,
+                self.window.y + self.window.h - PADDING.1 - 19,
+            ), xruns, dropped).unwrap();
+        }
     }
 
     fn dispatch(&mut self, action: Action) -> Action {
@@ -179,11 +251,12 @@ impl Layer for Project {
                 Action::Back => Action::Cancel,
                 a @ Action::Close |
                 a @ Action::Save |
+                a @ Action::Bounce(_, _, _) |
                 a @ Action::DelModule(_) => a,
                 _ => Action::Noop,
             }
         } else { Action::Noop }
     }
     fn alpha(&self) -> bool { true }
-    fn save(&self) -> Option<Element> { None }
+    fn save(&self) -> Option<ModuleDoc> { None }
 }
\ No newline at end of file
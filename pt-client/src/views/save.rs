@@ -1,7 +1,6 @@
 use std::io::{Write, Stdout};
 use termion::{color, cursor};
-use xmltree::Element;
-use libcommon::Action;
+use libcommon::{Action, ModuleDoc};
 
 use crate::views::Layer;
 use crate::components::{popup, casette, button, bigtext};
@@ -19,6 +18,9 @@ pub struct Save {
 pub struct SaveState {
     title_str: String,
     last_char: u8,
+    // None saves the project alone, otherwise collect
+    // ... it and its assets into a folder or tar
+    bundle: Option<bool>,
 }
 
 const PADDING: (u16, u16) = (2, 2);
@@ -62,6 +64,14 @@ fn reduce(state: SaveState, action: Action) -> SaveState {
             Action::SelectG => NUM_MIN,
             _ => state.last_char,
         },
+        bundle: match action {
+            Action::SelectR => match state.bundle {
+                None => Some(false),
+                Some(false) => Some(true),
+                Some(true) => None,
+            },
+            _ => state.bundle,
+        },
     }
 }
 
@@ -72,6 +82,7 @@ impl Save {
         let initial_state: SaveState = SaveState {
             last_char: initial.chars().last().unwrap() as u8,
             title_str: initial[0..initial.len() - 1].to_string(),
+            bundle: None,
         };
 
         Save {
@@ -133,6 +144,15 @@ impl Layer for Save {
             self.x + PADDING.0, 
             self.y + PADDING.1 + 5)).unwrap();
 
+        write_bg(out, Color::Red);
+        write!(out, "{} {} ", cursor::Goto(
+            self.x + PADDING.0, 
+            self.y + PADDING.1 + 7), match self.state.bundle {
+                None => "Project only",
+                Some(false) => "Collect to folder",
+                Some(true) => "Collect to tar",
+            }).unwrap();
+
         write_bg(out, Color::Blue);
         button::render(out, 
             self.x + PADDING.0, 
@@ -146,11 +166,16 @@ impl Layer for Save {
 
         match action {
             Action::Back => Action::Cancel,
-            Action::SelectB => Action::SaveAs(
-                format!("{}{}", self.state.title_str, self.state.last_char as char)),
+            Action::SelectB => {
+                let title = format!("{}{}", self.state.title_str, self.state.last_char as char);
+                match self.state.bundle {
+                    None => Action::SaveAs(title),
+                    Some(archive) => Action::Collect(title, archive),
+                }
+            },
             _ => Action::Noop
         }
     }
     fn alpha(&self) -> bool { true }
-    fn save(&self) -> Option<Element> { None }
+    fn save(&self) -> Option<ModuleDoc> { None }
 }
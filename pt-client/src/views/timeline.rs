@@ -1,9 +1,8 @@
 use std::io::{Write, Stdout};
 use std::collections::HashMap;
 
-use xmltree::Element;
 use termion::cursor;
-use libcommon::{Action, Anchor, Note, Param, Offset};
+use libcommon::{Action, Anchor, Note, Param, Offset, ModuleDoc, TimelineDoc};
 
 use crate::components::{button, ruler, roll};
 use crate::components::{region_midi, track_header, region_audio, timeline_meter, timeline_nav};
@@ -35,8 +34,11 @@ pub struct TimelineState {
     pub assets: HashMap<u16, Asset>,
     pub regions: HashMap<u16, AudioRegion>,
     pub midi_regions: HashMap<u16, MidiRegion>,
+    // dB, shared by every timeline through pt-sound's master bus
+    pub master_gain: f32,
 
     // Ephemeral variables
+    pub master_levels: (f32, f32, f32, f32, f32),
     pub tick: bool,
     pub playhead: u32,
     pub scroll_mid: u16,
@@ -194,6 +196,14 @@ fn reduce(state: TimelineState, action: Action) -> TimelineState {
                     let track = new_tracks.get_mut(&id).unwrap();
                     track.monitor = is_on;
                 },
+                Action::SetTrackGain(id, db) => {
+                    let track = new_tracks.get_mut(&id).unwrap();
+                    track.gain = db;
+                },
+                Action::SetTrackPan(id, pan) => {
+                    let track = new_tracks.get_mut(&id).unwrap();
+                    track.pan = pan;
+                },
                 Action::AddTrack(id) => {
                     let mut new_index = new_tracks.iter().fold(0, |max, (_,t)| 
                         if t.index > max {t.index} else {max}) + 1;
@@ -204,6 +214,8 @@ fn reduce(state: TimelineState, action: Action) -> TimelineState {
                         monitor: false,
                         index: new_index,
                         id: id,
+                        gain: 0.0,
+                        pan: 0.0,
                     });
                 }
                 _ => {}
@@ -342,6 +354,15 @@ fn reduce(state: TimelineState, action: Action) -> TimelineState {
             Action::Tick => !state.tick,
             _ => state.tick
         },
+        master_gain: match action {
+            Action::SetMasterGain(db) => db,
+            _ => state.master_gain
+        },
+        master_levels: match action {
+            Action::MasterLevels(peak_l, peak_r, rms_l, rms_r, reduction) => 
+                (peak_l, peak_r, rms_l, rms_r, reduction),
+            _ => state.master_levels
+        },
         playhead: match action {
             Action::Goto(o) => o,
             _ => state.playhead
@@ -383,10 +404,10 @@ fn reduce(state: TimelineState, action: Action) -> TimelineState {
 }
 
 impl Timeline {
-    pub fn new(x: u16, y: u16, width: u16, height: u16, module: Element) -> Self {
+    pub fn new(x: u16, y: u16, width: u16, height: u16, doc: TimelineDoc) -> Self {
 
         // Initialize State
-        let mut initial_state: TimelineState = timeline::read(module); 
+        let mut initial_state: TimelineState = timeline::read(doc); 
 
         generate_waveforms(&mut initial_state.assets, initial_state.sample_rate,
                            initial_state.tempo, initial_state.zoom);
@@ -578,10 +599,13 @@ impl Layer for Timeline {
             a @ Action::LoopMode(_) |
             a @ Action::SetMeter(_,_) |
             a @ Action::SetTempo(_) |
+            a @ Action::SetMasterGain(_) |
             a @ Action::RecordTrack(_, _) |
             a @ Action::MuteTrack(_, _) |
             a @ Action::SoloTrack(_, _) |
             a @ Action::MonitorTrack(_, _) |
+            a @ Action::SetTrackGain(_, _) |
+            a @ Action::SetTrackPan(_, _) |
             a @ Action::Record |
             a @ Action::Play |
             a @ Action::Stop  => (self.state.focus, Some(a)),
@@ -596,7 +620,7 @@ impl Layer for Timeline {
         }
     }
     fn alpha(&self) -> bool { false }
-    fn save(&self) -> Option<Element> { 
-        Some(timeline::write(self.state.clone()))
+    fn save(&self) -> Option<ModuleDoc> { 
+        Some(ModuleDoc::Timeline(timeline::write(self.state.clone())))
     }
 }
@@ -1,4 +1,5 @@
 use crate::pcm::{Offset, Volume, Key, Note, Anchor, Module, Param};
+use std::fmt;
 use std::str::FromStr;
 
 /*
@@ -9,6 +10,12 @@ use std::str::FromStr;
     Actions are received via pipe and are delimited by a space. Multiple
     actions may be read by the event loop simultaneously 
 
+    String arguments are percent-escaped so that they may never contain
+    a delimiter: '%' ' ' ':' '@' and line breaks are written as %XX.
+    Lists are written as a count followed by the fields of each item,
+    e.g. "SHOW_ANCHORS:2:0:8:Out%200:0:1:8:In%201:1". Every variant has
+    its own verb, so that action.to_string().parse() == action
+
     n_id Node ID
     r_id Route ID
     a_id Anchor ID (Any input or output from a module)
@@ -17,7 +24,7 @@ use std::str::FromStr;
     t_id => Track ID
 */
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Action {
     Up,
     Down,
@@ -47,6 +54,7 @@ pub enum Action {
     VolumeDown,
     Help,
     Deselect,
+    Learn,
     Record,
     Route,
     Cancel,
@@ -54,11 +62,22 @@ pub enum Action {
     At(u16, Box<Action>), // A direct action
     NoteOn(Key, Volume),
     NoteOff(Key),
+    ControlChange(u8, u8), // Controller, value 0 to 127
+    PitchBend(f32), // -1 down to 1 up
+    ProgramChange(u8),
+    SetDevice(String), // The device a note source plays from
+    ListMidiPorts,
+    MidiPorts(Vec<String>), // Every MIDI port that can be played from
     Goto(Offset),
     Tick,
     Octave(bool), // true = up
     Volume(bool), 
     SetTempo(u16),
+    SetSampleRate(u32), // The rate the engine is running at
+    AudioStatus(u32, u32, u32, Offset), // Rate, period, buffer, output latency in frames
+    Glitches(u32, u32), // Underruns, actions dropped on the way to the client
+    SetMasterGain(f32), // dB
+    MasterLevels(f32, f32, f32, f32, f32), // Peak L, peak R, RMS L, RMS R, limiter reduction in dB
     AddNote(u16, Note), // Track ID, note
     Scrub(bool),
     SetLoop(Offset, Offset),
@@ -74,9 +93,14 @@ pub enum Action {
     CreateProject(String),
     SetParam(String, Param),
     DeclareParam(String, f32, f32, f32, f32),
+    LearnParam(String, Param, Param), // Param, min, max for the next controller moved
+    BindParam(u8, String, Param, Param), // Controller, param, min, max
+    ForgetParam(String), // Stop learning a param and unbind it
     DeclareAnchors(usize, usize),
     SoloTrack(u16, bool), // Track ID, is_on
     MuteTrack(u16, bool),
+    SetTrackGain(u16, Param), // Track ID, dB
+    SetTrackPan(u16, Param), // Track ID, -1 left to 1 right
     MonitorTrack(u16, bool),
     RecordTrack(u16, u8), // Track ID, mode (0 off, 1 midi, 2 audio)
     SetMeter(u16, u16),
@@ -89,6 +113,9 @@ pub enum Action {
     PatchOut(u16, u16, u16),
     PatchIn(u16, u16, u16),
     DelPatch(u16, u16, bool),
+    PatchRefused(u16, u16, u16, String), // Module ID, Anchor ID, Route ID, reason
+    RouteLevel(u16, f32), // Route ID, peak since the last report
+    AnchorLevel(u16, u16, f32), // Module ID, Anchor ID, peak since the last report
     Zoom(usize),
     // Track ID, Region ID, Asset ID, offset, duration, asset_in, source
     AddRegion(u16, u16, u16, Offset, Offset, Offset, String),
@@ -101,214 +128,526 @@ pub enum Action {
     AddTrack(u16),
     Save,
     SaveAs(String),
+    Collect(String, bool), // Title, as a tar archive
+    Bounce(String, bool, bool), // Project, one file per track, loop markers only
+    BounceProgress(Offset, Offset), // Frames rendered, total
     Noop,
     Error(String),
     Exit,
 }
 
-impl ToString for Action {
-    fn to_string(&self) -> String {
-        format!("{} ", match self {
-            Action::At(n_id, action) => format!("{}@{}", n_id, action.to_string()),
-            Action::NoteOn(key, vel) => format!("NOTE_ON:{}:{}", key, vel),
-            Action::NoteOff(key) => format!("NOTE_OFF:{}", key),
-            Action::AddNote(t_id, n) => format!("NOTE_ADD:{}:{}:{}:{}:{}:{}:{}",
-                t_id, n.id, n.note, n.vel, n.r_id, n.t_in, n.t_out),
-            Action::AddRegion(t_id, r_id, a_id, offset, duration, asset_in, source) => 
-                format!("REGION_ADD:{}:{}:{}:{}:{}:{}:{}",
-                    t_id, r_id, a_id, offset, duration, asset_in, source),
-            Action::AddMidiRegion(t_id, r_id, offset, duration) =>
-                format!("MIDI_REGION_ADD:{}:{}:{}:{}",
-                    t_id, r_id, offset, duration),
-            Action::Scrub(dir) => format!("SCRUB:{}",
-                if *dir { "1" } else { "0" }),
-            Action::MuteTrack(t_id, is_on) => format!("MUTE_TRACK:{}:{}", 
-                t_id, if *is_on { "1" } else { "0" }),
-            Action::RecordTrack(t_id, is_on) => format!("RECORD_TRACK:{}:{}", 
-                t_id, match *is_on {
-                    1 => "1",
-                    2 => "2",
-                    _ => "0",
+
+pub const DELIMITER: char = ' ';
+pub(crate) const SEPARATOR: char = ':';
+pub(crate) const DIRECT: char = '@';
+const ESCAPE: char = '%';
+
+// Percent-escape any character which would otherwise break the stream
+pub fn escape(raw: &str) -> String {
+    let mut escaped = String::with_capacity(raw.len());
+    for c in raw.chars() {
+        match c {
+            ESCAPE | DELIMITER | SEPARATOR | DIRECT | '\n' | '\r' | '\t' =>
+                escaped.push_str(&format!("%{:02X}", c as u8)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub fn unescape(escaped: &str) -> Option<String> {
+    let mut bytes: Vec<u8> = Vec::with_capacity(escaped.len());
+    let mut iter = escaped.bytes();
+    while let Some(b) = iter.next() {
+        if b == ESCAPE as u8 {
+            let hi = (iter.next()? as char).to_digit(16)?;
+            let lo = (iter.next()? as char).to_digit(16)?;
+            bytes.push((hi * 16 + lo) as u8);
+        } else {
+            bytes.push(b);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+fn flag(on: bool) -> String {
+    if on { "1" } else { "0" }.to_string()
+}
+
+impl Action {
+    // The verb and (already escaped) arguments of a single action
+    fn verb_args(&self) -> (&'static str, Vec<String>) {
+        match self {
+            Action::Up => ("UP", vec![]),
+            Action::Down => ("DN", vec![]),
+            Action::Left => ("LT", vec![]),
+            Action::Right => ("RT", vec![]),
+            Action::SelectR => ("SPC", vec![]),
+            Action::SelectB => ("V", vec![]),
+            Action::SelectG => ("I", vec![]),
+            Action::SelectY => ("R", vec![]),
+            Action::SelectP => ("M", vec![]),
+            Action::MidiEvent => ("MIDI_EVENT", vec![]),
+            Action::RotaryEvent => ("ROTARY_EVENT", vec![]),
+            Action::Modules => ("MODULES", vec![]),
+            Action::Project => ("PROJECT", vec![]),
+            Action::Undo => ("UNDO", vec![]),
+            Action::Redo => ("REDO", vec![]),
+            Action::Shift => ("SHIFT", vec![]),
+            Action::Back => ("BACK", vec![]),
+            Action::Play => ("PLAY", vec![]),
+            Action::Stop => ("STOP", vec![]),
+            Action::In => ("IN", vec![]),
+            Action::Out => ("OUT", vec![]),
+            Action::Edit => ("EDIT", vec![]),
+            Action::PitchUp => ("PITCH_UP", vec![]),
+            Action::PitchDown => ("PITCH_DOWN", vec![]),
+            Action::VolumeUp => ("VOLUME_UP", vec![]),
+            Action::VolumeDown => ("VOLUME_DOWN", vec![]),
+            Action::Help => ("HELP", vec![]),
+            Action::Deselect => ("DESELECT", vec![]),
+            Action::Learn => ("LEARN", vec![]),
+            Action::Record => ("RECORD", vec![]),
+            Action::Route => ("ROUTE", vec![]),
+            Action::Cancel => ("CANCEL", vec![]),
+            Action::Close => ("CLOSE", vec![]),
+            // Direct actions are prefixed instead, see Display
+            Action::At(_, _) => ("", vec![]),
+            Action::NoteOn(key, vel) => ("NOTE_ON", vec![key.to_string(), vel.to_string()]),
+            Action::NoteOff(key) => ("NOTE_OFF", vec![key.to_string()]),
+            Action::ControlChange(ctrl, value) => ("CONTROL_CHANGE", vec![ctrl.to_string(), value.to_string()]),
+            Action::PitchBend(bend) => ("PITCH_BEND", vec![bend.to_string()]),
+            Action::ProgramChange(program) => ("PROGRAM_CHANGE", vec![program.to_string()]),
+            Action::SetDevice(device) => ("SET_DEVICE", vec![escape(device)]),
+            Action::ListMidiPorts => ("LIST_MIDI_PORTS", vec![]),
+            Action::MidiPorts(ports) => {
+                let mut args = vec![ports.len().to_string()];
+                args.extend(ports.iter().map(|p| escape(p)));
+                ("MIDI_PORTS", args)
+            },
+            Action::Goto(playhead) => ("GOTO", vec![playhead.to_string()]),
+            Action::Tick => ("TICK", vec![]),
+            Action::Octave(up) => ("OCTAVE", vec![flag(*up)]),
+            Action::Volume(up) => ("VOLUME", vec![flag(*up)]),
+            Action::SetTempo(tempo) => ("SET_TEMPO", vec![tempo.to_string()]),
+            Action::SetSampleRate(rate) => ("SET_SAMPLE_RATE", vec![rate.to_string()]),
+            Action::AudioStatus(rate, period, buffer, latency) => ("AUDIO_STATUS", vec![
+                rate.to_string(),
+                period.to_string(),
+                buffer.to_string(),
+                latency.to_string(),
+            ]),
+            Action::Glitches(xruns, dropped) => ("GLITCHES", vec![xruns.to_string(), dropped.to_string()]),
+            Action::SetMasterGain(db) => ("SET_MASTER_GAIN", vec![db.to_string()]),
+            Action::MasterLevels(peak_l, peak_r, rms_l, rms_r, reduction) => ("MASTER_LEVELS", vec![
+                peak_l.to_string(), peak_r.to_string(), rms_l.to_string(), rms_r.to_string(), reduction.to_string()]),
+            Action::AddNote(t_id, n) => ("NOTE_ADD", vec![
+                t_id.to_string(),
+                n.id.to_string(),
+                n.note.to_string(),
+                n.vel.to_string(),
+                n.r_id.to_string(),
+                n.t_in.to_string(),
+                n.t_out.to_string()]),
+            Action::Scrub(dir) => ("SCRUB", vec![flag(*dir)]),
+            Action::SetLoop(l_in, l_out) => ("SET_LOOP", vec![l_in.to_string(), l_out.to_string()]),
+            Action::LoopMode(is_on) => ("LOOP_MODE", vec![flag(*is_on)]),
+            Action::LoopOff => ("LOOP_OFF", vec![]),
+            Action::Loop(l_in, l_out) => ("LOOP", vec![l_in.to_string(), l_out.to_string()]),
+            Action::AddModule(id, name) => ("ADD_MODULE", vec![id.to_string(), escape(name)]),
+            Action::TryoutModule(id) => ("TRYOUT_MODULE", vec![id.to_string()]),
+            Action::DelModule(id) => ("DEL_MODULE", vec![id.to_string()]),
+            Action::OpenProject(name) => ("OPEN_PROJECT", vec![escape(name)]),
+            Action::ShowProject(title, modules) => {
+                let mut args = vec![escape(title), modules.len().to_string()];
+                for module in modules.iter() {
+                    args.push(module.id.to_string());
+                    args.push(escape(&module.name));
+                }
+                ("SHOW_PROJECT", args)
+            },
+            Action::InputTitle => ("INPUT_TITLE", vec![]),
+            Action::CreateProject(title) => ("CREATE_PROJECT", vec![escape(title)]),
+            Action::SetParam(key, val) => ("SET_PARAM", vec![escape(key), val.to_string()]),
+            Action::DeclareParam(key, init, min, max, step) => ("DECLARE_PARAM", vec![
+                escape(key),
+                init.to_string(),
+                min.to_string(),
+                max.to_string(),
+                step.to_string()]),
+            Action::LearnParam(key, min, max) => ("LEARN_PARAM", vec![escape(key), min.to_string(), max.to_string()]),
+            Action::BindParam(ctrl, key, min, max) => ("BIND_PARAM", vec![
+                ctrl.to_string(),
+                escape(key),
+                min.to_string(),
+                max.to_string()]),
+            Action::ForgetParam(key) => ("FORGET_PARAM", vec![escape(key)]),
+            Action::DeclareAnchors(ins, outs) => ("DECLARE_ANCHORS", vec![ins.to_string(), outs.to_string()]),
+            Action::SoloTrack(t_id, is_on) => ("SOLO_TRACK", vec![t_id.to_string(), flag(*is_on)]),
+            Action::MuteTrack(t_id, is_on) => ("MUTE_TRACK", vec![t_id.to_string(), flag(*is_on)]),
+            Action::SetTrackGain(t_id, db) => ("SET_TRACK_GAIN", vec![t_id.to_string(), db.to_string()]),
+            Action::SetTrackPan(t_id, pan) => ("SET_TRACK_PAN", vec![t_id.to_string(), pan.to_string()]),
+            Action::MonitorTrack(t_id, is_on) => ("MONITOR_TRACK", vec![t_id.to_string(), flag(*is_on)]),
+            Action::RecordTrack(t_id, mode) => ("RECORD_TRACK", vec![t_id.to_string(), mode.to_string()]),
+            Action::SetMeter(beat, note) => ("SET_METER", vec![beat.to_string(), note.to_string()]),
+            Action::ShowAnchors(anchors) => {
+                let mut args = vec![anchors.len().to_string()];
+                for anchor in anchors.iter() {
+                    args.push(anchor.index.to_string());
+                    args.push(anchor.module_id.to_string());
+                    args.push(escape(&anchor.name));
+                    args.push(flag(anchor.input));
                 }
-            ),
-            Action::SoloTrack(t_id, is_on) => 
-                format!("SOLO_TRACK:{}:{}", t_id, if *is_on { "1" } else { "0" }),
-            Action::MonitorTrack(t_id, is_on) => 
-                format!("MONITOR_TRACK:{}:{}", t_id, if *is_on { "1" } else { "0" }),
-            Action::PatchOut(module_id, anchor_id, route_id) => 
-                format!("PATCH_OUT:{}:{}:{}", module_id, anchor_id, route_id),
-            Action::PatchIn(module_id, anchor_id, route_id) => 
-                format!("PATCH_IN:{}:{}:{}", module_id, anchor_id, route_id),
-            Action::DelPatch(module_id, anchor_id, is_input) => 
-                format!("DEL_PATCH:{}:{}:{}", module_id, anchor_id, 
-                    if *is_input {"1"} else {"2"}),
-            Action::DelRoute(route_id) => format!("DEL_ROUTE:{}", route_id),
-            Action::AddRoute(route_id) => format!("ADD_ROUTE:{}", route_id),
-            Action::SetParam(key, val) => format!("SET_PARAM:{}:{}", key, val),
-            Action::DeclareParam(key, init, min, max, step) => 
-                format!("DECLARE_PARAM:{}:{}:{}:{}:{}", key, init, min, max, step),
-            Action::DeclareAnchors(ins, outs) => 
-                format!("DECLARE_ANCHORS:{}:{}", ins, outs),
-            Action::SetMeter(beat, note) => format!("SET_METER:{}:{}", beat, note),
-            Action::SetTempo(tempo) => format!("SET_TEMPO:{}", tempo),
-            Action::SetLoop(l_in, l_out) => format!("SET_LOOP:{}:{}", l_in, l_out),
-            Action::LoopMode(is_on) => format!("LOOP_MODE:{}", 
-                if *is_on { "1" } else { "0" }
-            ),
-            Action::Goto(playhead) => format!("GOTO:{}", playhead),
-            Action::Tick => format!("TICK"),
-            Action::Play => format!("PLAY"),
-            Action::Record => format!("RECORD"),
-            Action::Stop => format!("STOP"),
-            Action::Exit => format!("EXIT"),
-            Action::OpenProject(name) => format!("OPEN_PROJECT:{}", name),
-            Action::AddModule(id, name) => format!("ADD_MODULE:{}:{}", id, name),
-            Action::DelModule(id) => format!("DEL_MODULE:{}", id),
-            Action::Zoom(factor) => format!("ZOOM:{}", factor),
-            Action::MoveRegion(t_id, r_id, offset) => format!("MOVE_REGION:{}:{}:{}",
-                t_id, r_id, offset),
-            Action::DelRegion(t_id, r_id) => format!("DEL_REGION:{}:{}", t_id, r_id),
-            Action::SplitRegion(t_id, r_id, offset) => format!("SPLIT_REGION:{}:{}:{}", t_id, r_id, offset),
-            Action::LoopRegion(t_id, r_id) => format!("LOOP_REGION:{}:{}", t_id, r_id),
-            Action::AddTrack(id) => format!("ADD_TRACK:{}", id),
-            _ => "NOOP".to_string()
-        })
+                ("SHOW_ANCHORS", args)
+            },
+            Action::PatchAnchor(a_id) => ("PATCH_ANCHOR", vec![a_id.to_string()]),
+            Action::PatchRoute(r_id) => ("PATCH_ROUTE", vec![r_id.to_string()]),
+            Action::AddRoute(r_id) => ("ADD_ROUTE", vec![r_id.to_string()]),
+            Action::DelRoute(r_id) => ("DEL_ROUTE", vec![r_id.to_string()]),
+            Action::FadePatch(r_id, level) => ("FADE_PATCH", vec![r_id.to_string(), level.to_string()]),
+            Action::PatchOut(module_id, anchor_id, route_id) => ("PATCH_OUT", vec![
+                module_id.to_string(), anchor_id.to_string(), route_id.to_string()]),
+            Action::PatchIn(module_id, anchor_id, route_id) => ("PATCH_IN", vec![
+                module_id.to_string(), anchor_id.to_string(), route_id.to_string()]),
+            Action::DelPatch(module_id, anchor_id, is_input) => ("DEL_PATCH", vec![
+                module_id.to_string(), anchor_id.to_string(), flag(*is_input)]),
+            Action::PatchRefused(module_id, anchor_id, route_id, reason) => ("PATCH_REFUSED", vec![
+                module_id.to_string(), anchor_id.to_string(), route_id.to_string(), escape(reason)]),
+            Action::RouteLevel(r_id, peak) => ("ROUTE_LEVEL", vec![r_id.to_string(), peak.to_string()]),
+            Action::AnchorLevel(module_id, anchor_id, peak) => ("ANCHOR_LEVEL", vec![
+                module_id.to_string(), anchor_id.to_string(), peak.to_string()]),
+            Action::Zoom(factor) => ("ZOOM", vec![factor.to_string()]),
+            Action::AddRegion(t_id, r_id, a_id, offset, duration, asset_in, source) => ("REGION_ADD", vec![
+                t_id.to_string(),
+                r_id.to_string(),
+                a_id.to_string(),
+                offset.to_string(),
+                duration.to_string(),
+                asset_in.to_string(),
+                escape(source)]),
+            Action::AddMidiRegion(t_id, r_id, offset, duration) => ("MIDI_REGION_ADD", vec![
+                t_id.to_string(), r_id.to_string(), offset.to_string(), duration.to_string()]),
+            Action::MoveRegion(t_id, r_id, offset) => ("MOVE_REGION", vec![
+                t_id.to_string(), r_id.to_string(), offset.to_string()]),
+            Action::DelRegion(t_id, r_id) => ("DEL_REGION", vec![t_id.to_string(), r_id.to_string()]),
+            Action::SplitRegion(t_id, r_id, offset) => ("SPLIT_REGION", vec![
+                t_id.to_string(), r_id.to_string(), offset.to_string()]),
+            Action::LoopRegion(t_id, r_id) => ("LOOP_REGION", vec![t_id.to_string(), r_id.to_string()]),
+            Action::AddTrack(id) => ("ADD_TRACK", vec![id.to_string()]),
+            Action::Save => ("SAVE", vec![]),
+            Action::SaveAs(title) => ("SAVE_AS", vec![escape(title)]),
+            Action::Collect(title, archive) => ("COLLECT", vec![escape(title), flag(*archive)]),
+            Action::Bounce(project, stems, looped) => ("BOUNCE", vec![escape(project), flag(*stems), flag(*looped)]),
+            Action::BounceProgress(done, total) => ("BOUNCE_PROGRESS", vec![done.to_string(), total.to_string()]),
+            Action::Noop => ("NOOP", vec![]),
+            Action::Error(message) => ("ERROR", vec![escape(message)]),
+            Action::Exit => ("EXIT", vec![]),
+        }
+    }
+
+    // A single action without its trailing delimiter
+    fn token(&self) -> String {
+        if let Action::At(n_id, action) = self {
+            return format!("{}{}{}", n_id, DIRECT, action.token());
+        }
+        let (verb, args) = self.verb_args();
+        let mut token = verb.to_string();
+        for arg in args.iter() {
+            token.push(SEPARATOR);
+            token.push_str(arg);
+        }
+        token
+    }
+}
+
+// Writes the action followed by its delimiter, ready for the pipe
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.token(), DELIMITER)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProtocolReason {
+    UnknownVerb,
+    MissingArgument,
+    InvalidArgument(String),
+    InvalidEscape(String),
+    UnexpectedArgument(String),
+    // Dropped without a delimiter after this many bytes
+    TooLong(usize),
+}
+
+// Describes why a single token could not be read as an Action. The 
+// ... index is that of the failed argument, where the verb is 0
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProtocolError {
+    pub verb: String,
+    pub index: usize,
+    pub reason: ProtocolReason,
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.reason {
+            ProtocolReason::UnknownVerb => 
+                write!(f, "unknown action {:?}", self.verb),
+            ProtocolReason::MissingArgument => 
+                write!(f, "{} argument {}: missing", self.verb, self.index),
+            ProtocolReason::InvalidArgument(arg) => 
+                write!(f, "{} argument {}: invalid value {:?}", self.verb, self.index, arg),
+            ProtocolReason::InvalidEscape(arg) => 
+                write!(f, "{} argument {}: invalid escape in {:?}", self.verb, self.index, arg),
+            ProtocolReason::UnexpectedArgument(arg) => 
+                write!(f, "{} argument {}: unexpected {:?}", self.verb, self.index, arg),
+            ProtocolReason::TooLong(max) => 
+                write!(f, "{} is longer than {} bytes", self.verb, max),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+// Reads arguments in order from a split action
+struct Args<'a> {
+    verb: &'a str,
+    argv: Vec<&'a str>,
+    index: usize,
+}
+
+impl<'a> Args<'a> {
+    fn error(&self, reason: ProtocolReason) -> ProtocolError {
+        ProtocolError {
+            verb: self.verb.to_string(),
+            index: self.index,
+            reason,
+        }
+    }
+    fn raw(&mut self) -> Result<&'a str, ProtocolError> {
+        self.index += 1;
+        match self.argv.get(self.index - 1) {
+            Some(arg) => Ok(arg),
+            None => Err(self.error(ProtocolReason::MissingArgument)),
+        }
+    }
+    fn num<T: FromStr>(&mut self) -> Result<T, ProtocolError> {
+        let arg = self.raw()?;
+        arg.parse().map_err(|_| self.error(ProtocolReason::InvalidArgument(arg.to_string())))
+    }
+    // A list's length, checked against the args left before anything is
+    // ... allocated for it
+    fn len(&mut self, fields: usize) -> Result<usize, ProtocolError> {
+        let arg = self.raw()?;
+        let left = self.argv.len() - self.index;
+        match arg.parse::<usize>().ok().filter(|len| len.checked_mul(fields).is_some_and(|n| n <= left)) {
+            Some(len) => Ok(len),
+            None => Err(self.error(ProtocolReason::InvalidArgument(arg.to_string()))),
+        }
+    }
+    fn string(&mut self) -> Result<String, ProtocolError> {
+        let arg = self.raw()?;
+        unescape(arg).ok_or_else(|| self.error(ProtocolReason::InvalidEscape(arg.to_string())))
+    }
+    fn flag(&mut self) -> Result<bool, ProtocolError> {
+        match self.raw()? {
+            "1" => Ok(true),
+            "0" => Ok(false),
+            arg => Err(self.error(ProtocolReason::InvalidArgument(arg.to_string()))),
+        }
+    }
+    fn finish(&mut self) -> Result<(), ProtocolError> {
+        if self.argv.len() > self.index {
+            self.index += 1;
+            let arg = self.argv[self.index - 1].to_string();
+            return Err(self.error(ProtocolReason::UnexpectedArgument(arg)));
+        }
+        Ok(())
     }
 }
 
 impl FromStr for Action {
-    type Err = String;
+    type Err = ProtocolError;
     fn from_str(raw: &str) -> Result<Self, Self::Err> {
-        let argv: Vec<&str> = raw.split(":").collect();
-        let argv0: Vec<&str> = argv[0].split("@").collect();
-        let is_direct = argv0.len() > 1;
-        let title = if is_direct { argv0[1] } else { argv0[0] };
+        let raw = raw.trim_end_matches(DELIMITER);
+
+        // Direct actions may be nested, e.g. "8@1@PLAY"
+        if let Some(head) = raw.split(SEPARATOR).next() {
+            if let Some(at) = head.find(DIRECT) {
+                let n_id: u16 = raw[..at].parse().map_err(|_| ProtocolError {
+                    verb: head.to_string(),
+                    index: 0,
+                    reason: ProtocolReason::InvalidArgument(raw[..at].to_string()),
+                })?;
+                let action: Action = raw[at+1..].parse()?;
+                return Ok(Action::At(n_id, Box::new(action)));
+            }
+        }
+
+        let mut split = raw.split(SEPARATOR);
+        let title = split.next().unwrap_or("");
+        let mut argv = Args { verb: title, argv: split.collect(), index: 0 };
         let action = match title {
-            "EXIT" => Action::Exit,
-            "ROUTE" => Action::Route,
-            "?" => Action::Noop,
-            "HELP" => Action::Help,
-            "BACK" => Action::Back,
-            "PLAY" => Action::Play,
-            "STOP" => Action::Stop,
-            "M" => Action::SelectP,
-            "R" => Action::SelectY,
-            "V" => Action::SelectB,
-            "I" => Action::SelectG,
-            "SPC" => Action::SelectR,
             "UP" => Action::Up,
             "DN" => Action::Down,
             "LT" => Action::Left,
             "RT" => Action::Right,
-            "RECORD" => Action::Record,
-            "DESELECT" => Action::Deselect,
+            "SPC" => Action::SelectR,
+            "V" => Action::SelectB,
+            "I" => Action::SelectG,
+            "R" => Action::SelectY,
+            "M" => Action::SelectP,
+            "MIDI_EVENT" => Action::MidiEvent,
+            "ROTARY_EVENT" => Action::RotaryEvent,
             "MODULES" => Action::Modules,
             "PROJECT" => Action::Project,
-            "RECORD_TRACK" => Action::RecordTrack(
-                argv[1].parse().unwrap(),
-                argv[2].parse().unwrap()),
-            "MUTE_TRACK" => Action::MuteTrack(
-                argv[1].parse().unwrap(),
-                argv[2] == "1"),
-            "SOLO_TRACK" => Action::SoloTrack(
-                argv[1].parse().unwrap(),
-                argv[2] == "1"),
-            "MONITOR_TRACK" => Action::MonitorTrack(
-                argv[1].parse().unwrap(),
-                argv[2] == "1"),
-            "NOTE_ON" => Action::NoteOn(
-                argv[1].parse().unwrap(), 
-                argv[2].parse().unwrap()),
-            "NOTE_OFF" => Action::NoteOff(argv[1].parse().unwrap()),
-            "OCTAVE" => Action::Octave(argv[1] == "1"),
-            "SCRUB" => Action::Scrub(argv[1] == "1"),
-            "OPEN_PROJECT" => Action::OpenProject(argv[1].to_string()),
-            "PATCH_OUT" => Action::PatchOut(
-                argv[1].parse().unwrap(),
-                argv[2].parse().unwrap(),
-                argv[3].parse().unwrap()),
-            "PATCH_IN" => Action::PatchIn(
-                argv[1].parse().unwrap(),
-                argv[2].parse().unwrap(),
-                argv[3].parse().unwrap()),
-            "DEL_PATCH" => Action::DelPatch(
-                argv[1].parse().unwrap(),
-                argv[2].parse().unwrap(),
-                argv[3] == "1"),
-            "DEL_ROUTE" => Action::DelRoute(argv[1].parse().unwrap()),
-            "ADD_ROUTE" => Action::AddRoute(argv[1].parse().unwrap()),
-            "SET_PARAM" => Action::SetParam(
-                argv[1].to_string(),
-                argv[2].parse().unwrap()),
-            "DECLARE_PARAM" => Action::DeclareParam(
-                argv[1].to_string(),
-                argv[2].parse().unwrap(),
-                argv[3].parse().unwrap(),
-                argv[4].parse().unwrap(),
-                argv[5].parse().unwrap()),
-            "DECLARE_ANCHORS" => Action::DeclareAnchors(
-                argv[1].parse().unwrap(),
-                argv[2].parse().unwrap()),
-            "GOTO" => Action::Goto(argv[1].parse().unwrap()),
-            "SET_TEMPO" => Action::SetTempo(argv[1].parse().unwrap()),
-            "SET_METER" => Action::SetMeter(
-                argv[1].parse().unwrap(),
-                argv[2].parse().unwrap()),
-            "LOOP_MODE" => Action::LoopMode(argv[1] == "1"),
-            "SET_LOOP" => Action::SetLoop(
-                argv[1].parse().unwrap(),
-                argv[2].parse().unwrap()),
-            "ADD_MODULE" => Action::AddModule(
-                argv[1].parse().unwrap(),
-                argv[2].to_string()),
-            "DEL_MODULE" => Action::DelModule(argv[1].parse().unwrap()),
+            "UNDO" => Action::Undo,
+            "REDO" => Action::Redo,
+            "SHIFT" => Action::Shift,
+            "BACK" => Action::Back,
+            "PLAY" => Action::Play,
+            "STOP" => Action::Stop,
+            "IN" => Action::In,
+            "OUT" => Action::Out,
+            "EDIT" => Action::Edit,
+            "PITCH_UP" => Action::PitchUp,
+            "PITCH_DOWN" => Action::PitchDown,
+            "VOLUME_UP" => Action::VolumeUp,
+            "VOLUME_DOWN" => Action::VolumeDown,
+            "HELP" => Action::Help,
+            "DESELECT" => Action::Deselect,
+            "LEARN" => Action::Learn,
+            "RECORD" => Action::Record,
+            "ROUTE" => Action::Route,
+            "CANCEL" => Action::Cancel,
+            "CLOSE" => Action::Close,
+            "NOTE_ON" => Action::NoteOn(argv.num()?, argv.num()?),
+            "NOTE_OFF" => Action::NoteOff(argv.num()?),
+            "CONTROL_CHANGE" => Action::ControlChange(argv.num()?, argv.num()?),
+            "PITCH_BEND" => Action::PitchBend(argv.num()?),
+            "PROGRAM_CHANGE" => Action::ProgramChange(argv.num()?),
+            "SET_DEVICE" => Action::SetDevice(argv.string()?),
+            "LIST_MIDI_PORTS" => Action::ListMidiPorts,
+            "MIDI_PORTS" => {
+                let len = argv.len(1)?;
+                let mut ports = Vec::with_capacity(len);
+                for _ in 0..len {
+                    ports.push(argv.string()?);
+                }
+                Action::MidiPorts(ports)
+            },
+            "GOTO" => Action::Goto(argv.num()?),
             "TICK" => Action::Tick,
-            "NOTE_ADD" => Action::AddNote(
-                argv[1].parse().unwrap(), 
-                Note {
-                    id: argv[2].parse().unwrap(),
-                    note: argv[3].parse().unwrap(),
-                    vel: argv[4].parse().unwrap(),
-                    r_id: argv[5].parse().unwrap(),
-                    t_in: argv[6].parse().unwrap(),
-                    t_out: argv[7].parse().unwrap(),
-                }),
+            "OCTAVE" => Action::Octave(argv.flag()?),
+            "VOLUME" => Action::Volume(argv.flag()?),
+            "SET_TEMPO" => Action::SetTempo(argv.num()?),
+            "SET_SAMPLE_RATE" => Action::SetSampleRate(argv.num()?),
+            "AUDIO_STATUS" => Action::AudioStatus(argv.num()?, argv.num()?, argv.num()?, argv.num()?),
+            "GLITCHES" => Action::Glitches(argv.num()?, argv.num()?),
+            "SET_MASTER_GAIN" => Action::SetMasterGain(argv.num()?),
+            "MASTER_LEVELS" => Action::MasterLevels(argv.num()?, argv.num()?, argv.num()?, argv.num()?, argv.num()?),
+            "NOTE_ADD" => Action::AddNote(argv.num()?, Note {
+                id: argv.num()?,
+                note: argv.num()?,
+                vel: argv.num()?,
+                r_id: argv.num()?,
+                t_in: argv.num()?,
+                t_out: argv.num()?,
+            }),
+            "SCRUB" => Action::Scrub(argv.flag()?),
+            "SET_LOOP" => Action::SetLoop(argv.num()?, argv.num()?),
+            "LOOP_MODE" => Action::LoopMode(argv.flag()?),
+            "LOOP_OFF" => Action::LoopOff,
+            "LOOP" => Action::Loop(argv.num()?, argv.num()?),
+            "ADD_MODULE" => Action::AddModule(argv.num()?, argv.string()?),
+            "TRYOUT_MODULE" => Action::TryoutModule(argv.num()?),
+            "DEL_MODULE" => Action::DelModule(argv.num()?),
+            "OPEN_PROJECT" => Action::OpenProject(argv.string()?),
+            "SHOW_PROJECT" => {
+                let title = argv.string()?;
+                let len = argv.len(2)?;
+                let mut modules = Vec::with_capacity(len);
+                for _ in 0..len {
+                    modules.push(Module {
+                        id: argv.num()?,
+                        name: argv.string()?,
+                    });
+                }
+                Action::ShowProject(title, modules)
+            },
+            "INPUT_TITLE" => Action::InputTitle,
+            "CREATE_PROJECT" => Action::CreateProject(argv.string()?),
+            "SET_PARAM" => Action::SetParam(argv.string()?, argv.num()?),
+            "DECLARE_PARAM" => Action::DeclareParam(
+                argv.string()?,
+                argv.num()?,
+                argv.num()?,
+                argv.num()?,
+                argv.num()?),
+            "LEARN_PARAM" => Action::LearnParam(argv.string()?, argv.num()?, argv.num()?),
+            "BIND_PARAM" => Action::BindParam(argv.num()?, argv.string()?, argv.num()?, argv.num()?),
+            "FORGET_PARAM" => Action::ForgetParam(argv.string()?),
+            "DECLARE_ANCHORS" => Action::DeclareAnchors(argv.num()?, argv.num()?),
+            "SOLO_TRACK" => Action::SoloTrack(argv.num()?, argv.flag()?),
+            "MUTE_TRACK" => Action::MuteTrack(argv.num()?, argv.flag()?),
+            "SET_TRACK_GAIN" => Action::SetTrackGain(argv.num()?, argv.num()?),
+            "SET_TRACK_PAN" => Action::SetTrackPan(argv.num()?, argv.num()?),
+            "MONITOR_TRACK" => Action::MonitorTrack(argv.num()?, argv.flag()?),
+            "RECORD_TRACK" => Action::RecordTrack(argv.num()?, argv.num()?),
+            "SET_METER" => Action::SetMeter(argv.num()?, argv.num()?),
+            "SHOW_ANCHORS" => {
+                let len = argv.len(4)?;
+                let mut anchors = Vec::with_capacity(len);
+                for _ in 0..len {
+                    anchors.push(Anchor {
+                        index: argv.num()?,
+                        module_id: argv.num()?,
+                        name: argv.string()?,
+                        input: argv.flag()?,
+                    });
+                }
+                Action::ShowAnchors(anchors)
+            },
+            "PATCH_ANCHOR" => Action::PatchAnchor(argv.num()?),
+            "PATCH_ROUTE" => Action::PatchRoute(argv.num()?),
+            "ADD_ROUTE" => Action::AddRoute(argv.num()?),
+            "DEL_ROUTE" => Action::DelRoute(argv.num()?),
+            "FADE_PATCH" => Action::FadePatch(argv.num()?, argv.num()?),
+            "PATCH_OUT" => Action::PatchOut(argv.num()?, argv.num()?, argv.num()?),
+            "PATCH_IN" => Action::PatchIn(argv.num()?, argv.num()?, argv.num()?),
+            "DEL_PATCH" => Action::DelPatch(argv.num()?, argv.num()?, argv.flag()?),
+            "PATCH_REFUSED" => Action::PatchRefused(argv.num()?, argv.num()?, argv.num()?, argv.string()?),
+            "ROUTE_LEVEL" => Action::RouteLevel(argv.num()?, argv.num()?),
+            "ANCHOR_LEVEL" => Action::AnchorLevel(argv.num()?, argv.num()?, argv.num()?),
+            "ZOOM" => Action::Zoom(argv.num()?),
             "REGION_ADD" => Action::AddRegion(
-                argv[1].parse().unwrap(),
-                argv[2].parse().unwrap(),
-                argv[3].parse().unwrap(),
-                argv[4].parse().unwrap(),
-                argv[5].parse().unwrap(),
-                argv[6].parse().unwrap(),
-                argv[7].to_string()),
+                argv.num()?,
+                argv.num()?,
+                argv.num()?,
+                argv.num()?,
+                argv.num()?,
+                argv.num()?,
+                argv.string()?),
             "MIDI_REGION_ADD" => Action::AddMidiRegion(
-                argv[1].parse().unwrap(),
-                argv[2].parse().unwrap(),
-                argv[3].parse().unwrap(),
-                argv[4].parse().unwrap()),
-            "ZOOM" => Action::Zoom(argv[1].parse().unwrap()),
-            "MOVE_REGION" => Action::MoveRegion(
-                argv[1].parse().unwrap(),
-                argv[2].parse().unwrap(),
-                argv[3].parse().unwrap()),
-            "DEL_REGION" => Action::DelRegion(
-                argv[1].parse().unwrap(),
-                argv[2].parse().unwrap()),
-            "SPLIT_REGION" => Action::SplitRegion(
-                argv[1].parse().unwrap(),
-                argv[2].parse().unwrap(),
-                argv[3].parse().unwrap()),
-            "LOOP_REGION" => Action::LoopRegion(
-                argv[1].parse().unwrap(),
-                argv[2].parse().unwrap()),
-            "ADD_TRACK" => Action::AddTrack(argv[1].parse().unwrap()),
-            _ => return Err(raw.to_string())
+                argv.num()?,
+                argv.num()?,
+                argv.num()?,
+                argv.num()?),
+            "MOVE_REGION" => Action::MoveRegion(argv.num()?, argv.num()?, argv.num()?),
+            "DEL_REGION" => Action::DelRegion(argv.num()?, argv.num()?),
+            "SPLIT_REGION" => Action::SplitRegion(argv.num()?, argv.num()?, argv.num()?),
+            "LOOP_REGION" => Action::LoopRegion(argv.num()?, argv.num()?),
+            "ADD_TRACK" => Action::AddTrack(argv.num()?),
+            "SAVE" => Action::Save,
+            "SAVE_AS" => Action::SaveAs(argv.string()?),
+            "COLLECT" => Action::Collect(argv.string()?, argv.flag()?),
+            "BOUNCE" => Action::Bounce(argv.string()?, argv.flag()?, argv.flag()?),
+            "BOUNCE_PROGRESS" => Action::BounceProgress(argv.num()?, argv.num()?),
+            "NOOP" | "?" => Action::Noop,
+            "ERROR" => Action::Error(argv.string()?),
+            "EXIT" => Action::Exit,
+            _ => return Err(argv.error(ProtocolReason::UnknownVerb))
         };
-        Ok(if is_direct { Action::At(
-            argv0[0].parse().unwrap(), 
-            Box::new(action)) 
-        } else { 
-            action 
-        })
+        argv.finish()?;
+        Ok(action)
     }
-}
\ No newline at end of file
+}
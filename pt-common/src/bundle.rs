@@ -0,0 +1,241 @@
+use std::{env, fmt, fs, io};
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use xmltree::Element;
+use crate::{Document, ModuleDoc};
+use crate::document::{document_element, write_element};
+use crate::config::config;
+use crate::model::wav_channels;
+
+/*
+    Asset srcs are stored relative to the project file, so a project can
+    be moved together with its assets. A bundle is a folder (or a tar of
+    one) holding the project and a copy of every asset it uses:
+
+        song/song.xml
+        song/assets/Who.wav
+*/
+
+pub const BUNDLE_ASSETS: &str = "assets";
+
+// An asset that was missing when its project was loaded
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relink {
+    pub asset: u16,
+    pub from: String,
+    pub to: Option<String>,
+}
+
+impl fmt::Display for Relink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.to {
+            Some(ref to) => write!(f, "asset {} relinked from {} to {}", self.asset, self.from, to),
+            None => write!(f, "asset {} is missing: {}", self.asset, self.from),
+        }
+    }
+}
+
+// Where missing assets are searched for
+pub fn asset_dirs() -> Vec<PathBuf> {
+    config().assets.clone()
+}
+
+// The directory srcs are relative to, empty for the working directory
+pub(crate) fn project_dir(doc_path: &Path) -> PathBuf {
+    let parent = doc_path.parent().unwrap_or_else(|| Path::new(""));
+    if parent.components().all(|c| c == Component::CurDir) {
+        PathBuf::new()
+    } else {
+        parent.to_path_buf()
+    }
+}
+
+fn assets_mut(doc: &mut Element) -> impl Iterator<Item=&mut Element> {
+    doc.get_mut_child("modules").into_iter()
+        .flat_map(|modules| modules.children.iter_mut().filter(|m| m.name == "timeline"))
+        .flat_map(|timeline| timeline.children.iter_mut().filter(|a| a.name == "asset"))
+}
+
+/*
+    Makes asset srcs in a parsed project usable from the working directory,
+    then looks for any that are missing by file name in the project's own
+    assets folder and in dirs. Assets saved without a channel count get
+    the one in the header of the file they resolved to.
+*/
+pub(crate) fn relink(doc: &mut Element, project: &Path, dirs: &[PathBuf]) -> Vec<Relink> {
+    let mut search = vec![project.join(BUNDLE_ASSETS)];
+    search.extend(dirs.iter().cloned());
+
+    let mut relinks = vec![];
+    for asset in assets_mut(doc) {
+        let src = match asset.attributes.get("src") {
+            Some(src) => Path::new(src),
+            None => continue,
+        };
+        let resolved = project.join(src);
+        if resolved.exists() {
+            probe_channels(asset, &resolved);
+            let resolved = resolved.to_string_lossy().to_string();
+            asset.attributes.insert("src".to_string(), resolved);
+            continue;
+        }
+
+        let found = src.file_name().and_then(|name| {
+            search.iter().map(|dir| dir.join(name)).find(|path| path.exists())
+        });
+        let relink = Relink {
+            asset: asset.attributes.get("id").and_then(|id| id.parse().ok()).unwrap_or(0),
+            from: src.to_string_lossy().to_string(),
+            to: found.map(|path| path.to_string_lossy().to_string()),
+        };
+        if let Some(ref to) = relink.to {
+            probe_channels(asset, Path::new(to));
+            asset.attributes.insert("src".to_string(), to.clone());
+        }
+        relinks.push(relink);
+    }
+    relinks
+}
+
+fn probe_channels(asset: &mut Element, path: &Path) {
+    if asset.attributes.contains_key("channels") { return; }
+    if let Some(channels) = wav_channels(path) {
+        asset.attributes.insert("channels".to_string(), channels.to_string());
+    }
+}
+
+// Inverse of relink, srcs inside project become relative to it. Both are
+// ... made absolute first, an empty project is the working directory
+pub(crate) fn relative_assets(doc: &mut Document, project: &Path) {
+    let cwd = env::current_dir().unwrap_or_default();
+    let project = cwd.join(project);
+    for (_, module) in doc.modules.iter_mut() {
+        if let ModuleDoc::Timeline(timeline) = module {
+            for asset in timeline.assets.iter_mut() {
+                if let Ok(relative) = cwd.join(&asset.src).strip_prefix(&project) {
+                    asset.src = relative.to_string_lossy().to_string();
+                }
+            }
+        }
+    }
+}
+
+/*
+    Collect and save: writes doc and copies of all of its assets into
+    dest as a folder, or as dest.tar when archive is set. Returns the path
+    that was written.
+*/
+pub fn collect(doc: &Document, dest: &Path, archive: bool) -> io::Result<PathBuf> {
+    let name = dest.file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "bundle needs a name"))?
+        .to_string_lossy().to_string();
+
+    // Assets that share a file name are told apart by their id
+    let mut bundled = doc.clone();
+    let mut files: Vec<(String, PathBuf)> = vec![];
+    for (_, module) in bundled.modules.iter_mut() {
+        if let ModuleDoc::Timeline(timeline) = module {
+            for asset in timeline.assets.iter_mut() {
+                let src = PathBuf::from(&asset.src);
+                let file = match files.iter().find(|(_, path)| *path == src) {
+                    Some((file, _)) => file.clone(),
+                    None => {
+                        let base = src.file_name()
+                            .map(|f| f.to_string_lossy().to_string())
+                            .unwrap_or_else(|| format!("{}.wav", asset.id));
+                        let file = if files.iter().any(|(f, _)| *f == base) {
+                            format!("{}_{}", asset.id, base)
+                        } else { base };
+                        files.push((file.clone(), src));
+                        file
+                    }
+                };
+                asset.src = format!("{}/{}", BUNDLE_ASSETS, file);
+            }
+        }
+    }
+
+    let mut xml: Vec<u8> = vec![];
+    write_element(&document_element(&bundled), &mut xml)?;
+
+    if archive {
+        // Titles may contain dots, which are not an extension to replace
+        let path = dest.with_file_name(format!("{}.tar", name));
+        let mut tar = Tar::new(io::BufWriter::new(fs::File::create(&path)?));
+        tar.add(&format!("{}/{}.xml", name, name), &xml)?;
+        for (file, src) in files.iter() {
+            let data = fs::read(src).map_err(|e| missing(src, e))?;
+            tar.add(&format!("{}/{}/{}", name, BUNDLE_ASSETS, file), &data)?;
+        }
+        tar.finish()?;
+        Ok(path)
+    } else {
+        fs::create_dir_all(dest.join(BUNDLE_ASSETS))?;
+        fs::write(dest.join(format!("{}.xml", name)), &xml)?;
+        for (file, src) in files.iter() {
+            fs::copy(src, dest.join(BUNDLE_ASSETS).join(file)).map_err(|e| missing(src, e))?;
+        }
+        Ok(dest.to_path_buf())
+    }
+}
+
+fn missing(src: &Path, e: io::Error) -> io::Error {
+    io::Error::new(e.kind(), format!("{}: {}", src.display(), e))
+}
+
+// Just enough of ustar to hold regular files, names that don't fit
+// ... in a header are written first as a GNU long name entry
+struct Tar<W: Write> {
+    out: W,
+}
+
+const BLOCK: usize = 512;
+const NAME_LEN: usize = 100;
+const LONG_NAME: &str = "././@LongLink";
+
+impl<W: Write> Tar<W> {
+    fn new(out: W) -> Self { Tar { out } }
+
+    fn header(&mut self, name: &str, size: usize, kind: u8) -> io::Result<()> {
+        let mut header = [0u8; BLOCK];
+        let mut field = |offset: usize, value: &[u8]| {
+            header[offset..offset + value.len()].copy_from_slice(value);
+        };
+        field(0, &name.as_bytes()[..name.len().min(NAME_LEN - 1)]);
+        field(100, b"0000644\0");
+        field(108, b"0000000\0");
+        field(116, b"0000000\0");
+        field(124, format!("{:011o}\0", size).as_bytes());
+        field(136, b"00000000000\0");
+        field(148, b"        ");
+        field(156, &[kind]);
+        field(257, b"ustar\0");
+        field(263, b"00");
+
+        let checksum: u32 = header.iter().map(|b| u32::from(*b)).sum();
+        header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+        self.out.write_all(&header)
+    }
+
+    fn data(&mut self, data: &[u8]) -> io::Result<()> {
+        self.out.write_all(data)?;
+        let padding = (BLOCK - data.len() % BLOCK) % BLOCK;
+        self.out.write_all(&vec![0u8; padding])
+    }
+
+    fn add(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        if name.len() >= NAME_LEN {
+            let mut long = name.as_bytes().to_vec();
+            long.push(0);
+            self.header(LONG_NAME, long.len(), b'L')?;
+            self.data(&long)?;
+        }
+        self.header(name, data.len(), b'0')?;
+        self.data(data)
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        self.out.write_all(&[0u8; BLOCK * 2])?;
+        self.out.flush()
+    }
+}
@@ -0,0 +1,356 @@
+use std::{env, error, fmt, fs, io};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use crate::{Action, Transport};
+
+/*
+    Runtime configuration shared by pt-sound, pt-client and pt-input.
+    Each setting is taken from, in increasing order of precedence:
+
+        the defaults below
+        the config file: --config <path>, $PALIT_CONFIG or ./palit.conf
+        PALIT_SOCKET, PALIT_SOUND_FIFO, PALIT_CLIENT_FIFO and PALIT_ASSETS
+        command line overrides: --<key>=<value> or --<key> <value>
+
+    Older scripts start every program with CARD_NAME SAMPLE_RATE BUF_SIZE,
+    which are still read as audio.card, audio.rate and audio.buffer.
+
+    The file holds one `key = value` per line, # starts a comment:
+
+        projects = ./
+        audio.card = PCH
+        audio.rate = 44100
+        keys.sound.KEY_A = NOTE_ON:60:1 / NOTE_OFF:60
+
+    Key bindings send their first actions on press and the ones after
+    the slash on release, to the client or to sound.
+*/
+
+pub const CONFIG_FILE: &str = "palit.conf";
+
+// Keys of the positional arguments older scripts pass first
+const LEGACY_ARGS: [&str; 3] = ["audio.card", "audio.rate", "audio.buffer"];
+
+const DEFAULT_KEYMAP: &str = "
+    keys.client.KEY_UP = UP
+    keys.client.KEY_DOWN = DN
+    keys.client.KEY_LEFT = LT
+    keys.client.KEY_RIGHT = RT
+    keys.client.KEY_Q = EXIT
+    keys.client.KEY_LEFTBRACE = PLAY
+    keys.client.KEY_RIGHTBRACE = STOP
+    keys.client.KEY_BACKSLASH = RECORD
+    keys.client.KEY_M = M / DESELECT
+    keys.client.KEY_R = R / DESELECT
+    keys.client.KEY_V = V / DESELECT
+    keys.client.KEY_I = I / DESELECT
+    keys.client.KEY_SPACE = SPC / DESELECT
+    keys.client.KEY_ENTER = DESELECT
+    keys.client.KEY_TAB = ROUTE
+    keys.client.KEY_B = LEARN
+    keys.client.KEY_KPPLUS = OCTAVE:1
+    keys.client.KEY_KPMINUS = OCTAVE:0
+    keys.sound.KEY_Q = EXIT
+    keys.sound.KEY_EQUAL = OCTAVE:1
+    keys.sound.KEY_MINUS = OCTAVE:0
+";
+
+// Keys that play notes, from middle C up
+const PIANO: [&str; 17] = [
+    "KEY_A", "KEY_W", "KEY_S", "KEY_E", "KEY_D", "KEY_F", "KEY_T", "KEY_G",
+    "KEY_Y", "KEY_H", "KEY_U", "KEY_J", "KEY_K", "KEY_O", "KEY_L", "KEY_P",
+    "KEY_SEMICOLON",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyTarget {
+    Client,
+    Sound,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyBinding {
+    pub key: String,
+    pub target: KeyTarget,
+    pub press: String,
+    pub release: String,
+}
+
+// How assets at another sample rate are converted on load
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResampleQuality {
+    Fast,
+    Good,
+    Best,
+}
+
+impl ResampleQuality {
+    pub fn name(self) -> &'static str {
+        match self {
+            ResampleQuality::Fast => "fast",
+            ResampleQuality::Good => "good",
+            ResampleQuality::Best => "best",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub projects: PathBuf,
+    pub modules: PathBuf,
+    pub assets: Vec<PathBuf>,
+    // ALSA card, opened as hw:<card>
+    pub audio_card: String,
+    pub sample_rate: u32,
+    pub buffer_size: u32,
+    // Frames computed per cycle, a few periods make up the buffer
+    pub period_size: u32,
+    // TPDF dither when converting to integer samples
+    pub dither: bool,
+    pub resample: ResampleQuality,
+    // Keep converted copies of assets next to the originals
+    pub resample_cache: bool,
+    // MIDI ports always read from, by part of their name
+    pub midi_inputs: Vec<String>,
+    pub transport: Transport,
+    pub keymap: Vec<KeyBinding>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Invalid { source: String, key: String, reason: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "could not read {}: {}", path.display(), e),
+            ConfigError::Invalid { source, key, reason } =>
+                write!(f, "{}: {}: {}", source, key, reason),
+        }
+    }
+}
+
+impl error::Error for ConfigError {}
+
+impl Default for Config {
+    fn default() -> Self {
+        let mut config = Config {
+            projects: PathBuf::from("./"),
+            modules: PathBuf::from("./modules/"),
+            assets: vec![PathBuf::from("assets")],
+            audio_card: "0".to_string(),
+            sample_rate: 48000,
+            buffer_size: 256,
+            period_size: 64,
+            dither: true,
+            resample: ResampleQuality::Good,
+            resample_cache: true,
+            midi_inputs: vec![],
+            transport: Transport::default(),
+            keymap: vec![],
+        };
+        config.parse(DEFAULT_KEYMAP, "defaults").unwrap();
+        for (i, key) in PIANO.iter().enumerate() {
+            let note = 60 + i;
+            for target in ["client", "sound"].iter() {
+                let binding = format!("NOTE_ON:{}:1 / NOTE_OFF:{}", note, note);
+                config.set(&format!("keys.{}.{}", target, key), &binding).unwrap();
+            }
+        }
+        config
+    }
+}
+
+fn flag(value: &str) -> Result<bool, String> {
+    match value {
+        "true" | "on" | "yes" | "1" => Ok(true),
+        "false" | "off" | "no" | "0" => Ok(false),
+        _ => Err(format!("expected on or off, found \"{}\"", value)),
+    }
+}
+
+fn list(value: &str, separator: char) -> Vec<String> {
+    value.split(separator).map(str::trim).filter(|v| !v.is_empty()).map(String::from).collect()
+}
+
+// Comments start at a # after whitespace, or at the start of the line,
+// ... so values may still hold one
+pub(crate) fn strip_comment(line: &str) -> &str {
+    let mut after_space = true;
+    for (i, c) in line.char_indices() {
+        if c == '#' && after_space {
+            return &line[..i];
+        }
+        after_space = c.is_whitespace();
+    }
+    line
+}
+
+fn number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("expected a number, found \"{}\"", value))
+}
+
+impl Config {
+    // Reads the config file and environment, then applies args
+    pub fn load(args: &[String]) -> Result<Config, ConfigError> {
+        let mut config = Config::default();
+
+        let explicit = args.iter().enumerate().find_map(|(i, a)| match a.strip_prefix("--config") {
+            Some("") => Some(args.get(i + 1).map(PathBuf::from).unwrap_or_default()),
+            Some(path) => path.strip_prefix('=').map(PathBuf::from),
+            None => None,
+        }).or_else(|| env::var_os("PALIT_CONFIG").map(PathBuf::from));
+        match explicit {
+            Some(path) => config.read(&path)?,
+            None if Path::new(CONFIG_FILE).exists() => config.read(Path::new(CONFIG_FILE))?,
+            None => {},
+        };
+
+        for (var, key) in [
+            ("PALIT_SOCKET", "ipc.socket"),
+            ("PALIT_SOUND_FIFO", "ipc.sound_fifo"),
+            ("PALIT_CLIENT_FIFO", "ipc.client_fifo"),
+            ("PALIT_ASSETS", "assets"),
+        ].iter() {
+            if let Ok(value) = env::var(var) {
+                config.set(key, &value).map_err(|reason| ConfigError::Invalid {
+                    source: var.to_string(), key: key.to_string(), reason,
+                })?;
+            }
+        }
+
+        config.apply_args(args)?;
+        Ok(config)
+    }
+
+    pub fn read(&mut self, path: &Path) -> Result<(), ConfigError> {
+        let text = fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+        self.parse(&text, &path.display().to_string())
+    }
+
+    // A # starts a comment at the start of a line or after whitespace,
+    // ... anywhere else it belongs to the value
+    pub fn parse(&mut self, text: &str, source: &str) -> Result<(), ConfigError> {
+        for (n, line) in text.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() { continue; }
+            let invalid = |key: &str, reason: String| ConfigError::Invalid {
+                source: format!("{}:{}", source, n + 1), key: key.to_string(), reason,
+            };
+            let (key, value) = match line.find('=') {
+                Some(i) => (line[..i].trim(), line[i + 1..].trim()),
+                None => return Err(invalid(line, "expected key = value".to_string())),
+            };
+            self.set(key, value).map_err(|reason| invalid(key, reason))?;
+        }
+        Ok(())
+    }
+
+    // Everything after the program name: leading positional arguments,
+    // ... then --key, skipping anything else
+    pub fn apply_args(&mut self, args: &[String]) -> Result<(), ConfigError> {
+        let mut args = args.iter().skip(1).peekable();
+        for key in LEGACY_ARGS.iter() {
+            let value = match args.next_if(|a| !a.starts_with("--")) {
+                Some(value) => value,
+                None => break,
+            };
+            self.set(key, value).map_err(|reason| ConfigError::Invalid {
+                source: "command line".to_string(), key: key.to_string(), reason,
+            })?;
+        }
+        while let Some(arg) = args.next() {
+            let arg = match arg.strip_prefix("--") {
+                Some(arg) => arg,
+                None => continue,
+            };
+            let (key, value) = match arg.find('=') {
+                Some(i) => (&arg[..i], arg[i + 1..].to_string()),
+                None => (arg, args.next().cloned().unwrap_or_default()),
+            };
+            if key == "config" { continue; }
+            self.set(key, &value).map_err(|reason| ConfigError::Invalid {
+                source: "command line".to_string(), key: key.to_string(), reason,
+            })?;
+        }
+        Ok(())
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "projects" => self.projects = PathBuf::from(value),
+            "modules" => self.modules = PathBuf::from(value),
+            "assets" => self.assets = env::split_paths(value).collect(),
+            "audio.card" => self.audio_card = value.to_string(),
+            "audio.rate" => self.sample_rate = number(value)?,
+            "audio.buffer" => self.buffer_size = number(value)?,
+            "audio.period" => self.period_size = number(value)?,
+            "audio.dither" => self.dither = flag(value)?,
+            "audio.resample" => self.resample = match value {
+                "fast" => ResampleQuality::Fast,
+                "good" => ResampleQuality::Good,
+                "best" => ResampleQuality::Best,
+                _ => return Err(format!("expected fast, good or best, found \"{}\"", value)),
+            },
+            "audio.resample_cache" => self.resample_cache = flag(value)?,
+            "midi.inputs" => self.midi_inputs = list(value, ','),
+            "ipc.socket" => self.transport.socket = match value {
+                "" => None,
+                path => Some(PathBuf::from(path)),
+            },
+            "ipc.sound_fifo" => self.transport.sound_fifo = PathBuf::from(value),
+            "ipc.client_fifo" => self.transport.client_fifo = PathBuf::from(value),
+            key if key.starts_with("keys.") => {
+                let mut parts = key.splitn(3, '.').skip(1);
+                let target = match parts.next() {
+                    Some("client") => KeyTarget::Client,
+                    Some("sound") => KeyTarget::Sound,
+                    _ => return Err("expected keys.client.<KEY> or keys.sound.<KEY>".to_string()),
+                };
+                let name = match parts.next() {
+                    Some(name) if !name.is_empty() => name.to_string(),
+                    _ => return Err("missing key name".to_string()),
+                };
+                let (press, release) = match value.find('/') {
+                    Some(i) => (value[..i].trim(), value[i + 1..].trim()),
+                    None => (value, ""),
+                };
+                // Bindings are sent as they are, so they have to be actions
+                for token in press.split_whitespace().chain(release.split_whitespace()) {
+                    token.parse::<Action>().map_err(|e| e.to_string())?;
+                }
+                self.keymap.retain(|b| !(b.key == name && b.target == target));
+                if !press.is_empty() || !release.is_empty() {
+                    self.keymap.push(KeyBinding {
+                        key: name,
+                        target,
+                        press: press.to_string(),
+                        release: release.to_string(),
+                    });
+                }
+            },
+            _ => return Err("unknown setting".to_string()),
+        }
+        Ok(())
+    }
+
+    pub fn binding(&self, target: KeyTarget, key: &str) -> Option<&KeyBinding> {
+        self.keymap.iter().find(|b| b.target == target && b.key == key)
+    }
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+// Makes config the one returned by config(), each binary does this once at startup
+// ... before anything reads it
+pub fn init(config: Config) -> &'static Config {
+    assert!(CONFIG.set(config).is_ok(), "config::init called twice, or after config() was read");
+    CONFIG.get().unwrap()
+}
+
+// The process wide config, defaults until init is called
+pub fn config() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
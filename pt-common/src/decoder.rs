@@ -0,0 +1,126 @@
+use std::io::{self, Read};
+use crate::action::{Action, ProtocolError, ProtocolReason, DELIMITER, DIRECT, SEPARATOR};
+
+/*
+    A non-blocking pipe may hand us any number of bytes, so an action
+    can be cut anywhere, even in the middle of a multibyte character.
+    The decoder keeps whatever follows the last delimiter until the
+    next read completes it, and only yields whole actions. A token
+    that grows past MAX_TOKEN is dropped up to its delimiter and
+    reported as an error in its place.
+*/
+
+const CHUNK_SIZE: usize = 4096;
+
+// Longer than any action we send, even a project with every module
+pub const MAX_TOKEN: usize = 1 << 16;
+
+// How much of a dropped token names it in the error
+const VERB_LEN: usize = 32;
+
+#[derive(Debug, Default)]
+pub struct Decoder {
+    buffer: Vec<u8>,
+    // Throwing away a token that was too long, up to its delimiter
+    skipping: bool,
+    // Where the dropped token was in the buffer, and why
+    dropped: Option<(usize, ProtocolError)>,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Decoder { buffer: vec![], skipping: false, dropped: None }
+    }
+
+    pub fn feed(&mut self, mut bytes: &[u8]) {
+        if self.skipping {
+            match bytes.iter().position(|b| *b == DELIMITER as u8) {
+                Some(end) => {
+                    self.skipping = false;
+                    bytes = &bytes[end..];
+                },
+                None => return,
+            }
+        }
+        self.buffer.extend_from_slice(bytes);
+
+        let start = self.buffer.iter().rposition(|b| *b == DELIMITER as u8).map_or(0, |i| i + 1);
+        if self.buffer.len() - start > MAX_TOKEN {
+            let token = &self.buffer[start..];
+            let verb = token.iter()
+                .position(|b| *b == SEPARATOR as u8 || *b == DIRECT as u8)
+                .unwrap_or(token.len())
+                .min(VERB_LEN);
+            let error = ProtocolError {
+                verb: String::from_utf8_lossy(&token[..verb]).into_owned(),
+                index: 0,
+                reason: ProtocolReason::TooLong(MAX_TOKEN),
+            };
+            self.buffer.truncate(start);
+            self.skipping = true;
+            // One at a time, the reader hears about the first
+            if self.dropped.is_none() {
+                self.dropped = Some((start, error));
+            }
+        }
+    }
+
+    // Reads until the source would block or is closed, returning
+    // ... the number of bytes read. Ok(0) means nothing was waiting.
+    pub fn read_from<R: Read>(&mut self, mut src: R) -> io::Result<usize> {
+        let mut chunk = [0u8; CHUNK_SIZE];
+        let mut total = 0;
+        loop {
+            match src.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => {
+                    self.feed(&chunk[..n]);
+                    total += n;
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(total)
+    }
+
+    // Bytes of an incomplete action still waiting for its delimiter
+    pub fn pending(&self) -> usize {
+        self.buffer.len()
+    }
+
+    // Next complete token, or the error for one dropped in its place
+    fn take(&mut self) -> Option<Result<String, ProtocolError>> {
+        loop {
+            if let Some((0, _)) = self.dropped {
+                return self.dropped.take().map(|(_, e)| Err(e));
+            }
+            let end = self.buffer.iter().position(|b| *b == DELIMITER as u8)?;
+            let token: Vec<u8> = self.buffer.drain(..=end).collect();
+            if let Some((ref mut at, _)) = self.dropped {
+                *at -= token.len();
+            }
+            // Repeated delimiters are harmless
+            if token.len() == 1 { continue; }
+            return Some(Ok(String::from_utf8_lossy(&token[..end]).into_owned()));
+        }
+    }
+
+    // Next complete token without parsing it, used for handshakes
+    pub fn next_token(&mut self) -> Option<String> {
+        loop {
+            if let Ok(token) = self.take()? {
+                return Some(token);
+            }
+        }
+    }
+}
+
+impl Iterator for Decoder {
+    type Item = Result<Action, ProtocolError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.take().map(|raw| raw.and_then(|raw| raw.parse::<Action>()))
+    }
+}
@@ -1,36 +1,157 @@
-use std::collections::HashMap;
-use std::fs;
-use xmltree::{Element, EmitterConfig};
+use std::collections::{HashMap, HashSet};
+use std::{error, fmt, fs, io};
+use std::str::FromStr;
+use std::path::PathBuf;
+use xmltree::{Element, EmitterConfig, ParseError};
 use crate::{Note, Key, Volume, Param, Offset};
+use crate::model::{ModuleDoc, read_module, write_module};
+use crate::migration::{Migration, FORMAT_VERSION, format_version, migrate};
+use crate::bundle::{Relink, asset_dirs, project_dir, relink, relative_assets};
+use crate::config::config;
 
 #[derive(Clone, Debug)]
 pub struct Document {
     pub title: String,
     pub src: String,
     pub sample_rate: u32,
-    pub modules: Vec<(u16, Element)>,
+    // Integer bits per sample for recordings and bounces
+    pub bit_depth: u16,
+    pub modules: Vec<(u16, ModuleDoc)>,
+    // Upgrades applied while loading, empty for current projects
+    pub migrations: Vec<Migration>,
+    // Assets that had moved or could not be found while loading
+    pub relinks: Vec<Relink>,
 }
 
-pub const PALIT_ROOT: &str = "./";
+impl Document {
+    // The directory asset srcs are relative to, empty for the working directory
+    pub fn dir(&self) -> PathBuf {
+        project_dir(&config().projects.join(&self.src))
+    }
+}
+
+// What a project is listed by, read without the rest of it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Header {
+    pub title: String,
+    pub sample_rate: u32,
+    pub bit_depth: u16,
+}
+
+// Something wrong with one element or attribute of a project
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    pub path: String,
+    pub attribute: Option<String>,
+    pub expected: String,
+    pub found: Option<String>,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.path)?;
+        if let Some(ref attribute) = self.attribute {
+            write!(f, "@{}", attribute)?;
+        }
+        match self.found {
+            Some(ref found) => write!(f, ": expected {}, found \"{}\"", self.expected, found),
+            None => write!(f, ": missing {}", self.expected),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DocumentError {
+    Io(io::Error),
+    Xml(ParseError),
+    Invalid(Vec<Problem>),
+    Version(u32),
+}
+
+impl fmt::Display for DocumentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DocumentError::Io(e) => write!(f, "could not read project: {}", e),
+            DocumentError::Xml(e) => write!(f, "could not parse project: {}", e),
+            DocumentError::Invalid(problems) => {
+                write!(f, "{} problem(s) in project", problems.len())?;
+                for problem in problems.iter() {
+                    write!(f, "\n  {}", problem)?;
+                }
+                Ok(())
+            },
+            DocumentError::Version(v) => write!(f,
+                "project format v{} is newer than this build (v{})", v, FORMAT_VERSION),
+        }
+    }
+}
+
+impl error::Error for DocumentError {}
+
+impl From<io::Error> for DocumentError {
+    fn from(e: io::Error) -> Self { DocumentError::Io(e) }
+}
+
+impl From<ParseError> for DocumentError {
+    fn from(e: ParseError) -> Self { DocumentError::Xml(e) }
+}
+
+pub(crate) fn attr<T: FromStr>(el: &Element, name: &str) -> Option<T> {
+    el.attributes.get(name).and_then(|v| v.parse::<T>().ok())
+}
 
+// Malformed params, marks and notes are skipped here,
+// ... validate_document reports them
 pub fn param_map(doc: &mut Element) -> (&mut Element, HashMap<String, Param>) {
     let mut params: HashMap<String, Param> = HashMap::new();
     while let Some(param) = doc.take_child("param") {
-        let key = param.attributes.get("name").unwrap();
-        let val = param.attributes.get("value").unwrap();
-        params.insert(key.to_string(), val.parse::<Param>().unwrap());
+        if let (Some(key), Some(val)) = (param.attributes.get("name"), attr::<Param>(&param, "value")) {
+            params.insert(key.to_string(), val);
+        }
     }
-    return (doc, params);
+    (doc, params)
 }
 
 pub fn mark_map(doc: &mut Element) -> (&mut Element, HashMap<String, Offset>) {
     let mut marks: HashMap<String, Offset> = HashMap::new();
-    while let Some(param) = doc.take_child("mark") {
-        let key = param.attributes.get("name").unwrap();
-        let val = param.attributes.get("value").unwrap();
-        marks.insert(key.to_string(), val.parse::<Offset>().unwrap());
+    while let Some(mark) = doc.take_child("mark") {
+        if let (Some(key), Some(val)) = (mark.attributes.get("name"), attr::<Offset>(&mark, "value")) {
+            marks.insert(key.to_string(), val);
+        }
+    }
+    (doc, marks)
+}
+
+fn parse_header(doc: &Element) -> Header {
+    let mut header = Header {
+        title: "Untitled".to_string(),
+        sample_rate: 48000,
+        bit_depth: 16,
+    };
+    if let Some(text) = doc.get_child("title").and_then(|title| title.text.clone()) {
+        header.title = text;
+    }
+    if let Some(meta) = doc.get_child("meta") {
+        if let Some(rate) = attr::<u32>(meta, "samplerate") {
+            header.sample_rate = rate;
+        }
+        if let Some(bits) = attr::<u16>(meta, "bitdepth") {
+            header.bit_depth = bits;
+        }
+    }
+    header
+}
+
+// Only checks that the project parses and isn't too new. Everything
+// ... else is validated and relinked by read_document when it's opened.
+pub fn read_header(filename: String) -> Result<Header, DocumentError> {
+    let doc_str: String = fs::read_to_string(config().projects.join(&filename))?;
+    let doc: Element = Element::parse(doc_str.as_bytes())?;
+    match format_version(&doc) {
+        Some(version) if version > FORMAT_VERSION => Err(DocumentError::Version(version)),
+        Some(_) => Ok(parse_header(&doc)),
+        None => Err(DocumentError::Invalid(validate_document(&doc))),
     }
-    return (doc, marks);
 }
 
 /* 
@@ -38,61 +159,222 @@ pub fn mark_map(doc: &mut Element) -> (&mut Element, HashMap<String, Offset>) {
     ids, as well as set the project title and sample and bit rates
 */
 
-pub fn read_document(filename: String) -> Document {
+pub fn read_document(filename: String) -> Result<Document, DocumentError> {
 
-    let doc_path: String = format!("{}{}", PALIT_ROOT, filename);
-    let doc_str: String = fs::read_to_string(doc_path).unwrap();
-    let mut doc: Element = Element::parse(doc_str.as_bytes()).unwrap();
-    let mut patch: Option<(u16, Element)> = None;
+    let doc_path = config().projects.join(&filename);
+    let doc_str: String = fs::read_to_string(&doc_path)?;
+    let mut doc: Element = Element::parse(doc_str.as_bytes())?;
+    let mut patch: Option<(u16, ModuleDoc)> = None;
 
-    let mut result = Document {
-        src: filename,
-        title: "Untitled".to_string(),
-        sample_rate: 48000,
-        modules: vec![],
+    let version = match format_version(&doc) {
+        Some(version) => version,
+        None => return Err(DocumentError::Invalid(validate_document(&doc))),
     };
-
-    if let Some(title) = doc.take_child("title") {
-        result.title = title.text.unwrap().to_string();
+    if version > FORMAT_VERSION {
+        return Err(DocumentError::Version(version));
     }
+    let migrations = migrate(&mut doc, version);
 
-    if let Some(meta) = doc.take_child("meta") {
-        if let Some(rate_str) = meta.attributes.get("samplerate") {
-            result.sample_rate = rate_str.parse::<u32>().unwrap();
-        }
+    let problems = validate_document(&doc);
+    if !problems.is_empty() {
+        return Err(DocumentError::Invalid(problems));
     }
 
+    let relinks = relink(&mut doc, &project_dir(&doc_path), &asset_dirs());
+    let header = parse_header(&doc);
+
+    let mut result = Document {
+        src: filename,
+        title: header.title,
+        sample_rate: header.sample_rate,
+        bit_depth: header.bit_depth,
+        modules: vec![],
+        migrations,
+        relinks,
+    };
+
     if let Some(modules) = doc.take_child("modules") {
         for module in modules.children.iter() {
-            if let Some(i) = module.attributes.get("id") {
+            if let Some(i) = attr::<u16>(module, "id") {
                 // Make sure patch is the last module in the result
                 if module.name == "patch" {
-                    patch = Some((i.parse::<u16>().unwrap(), module.to_owned()));
+                    patch = Some((i, read_module(module)));
                     continue;
                 }
-                result.modules.push((i.parse::<u16>().unwrap(), module.to_owned()));
-            } else {
-                panic!("Module missing ID");
+                result.modules.push((i, read_module(module)));
+            }
+        }
+    }
+
+    // No need for a patch, sound will add a master route
+    if let Some(p) = patch {
+        result.modules.push(p);
+    }
+
+    Ok(result)
+}
+
+/*
+    Reports every problem in a parsed project instead of stopping at the
+    first one. Paths name modules and params by id or name where they
+    have one, otherwise by their position among siblings of the same name:
+
+        /project/modules/timeline[@id=3]/param[@name=tempo]@value
+*/
+
+pub fn validate_document(doc: &Element) -> Vec<Problem> {
+    let mut problems: Vec<Problem> = vec![];
+    let root = format!("/{}", doc.name);
+
+    if doc.name != "project" {
+        problems.push(Problem {
+            path: root.clone(),
+            attribute: None,
+            expected: "project element".to_string(),
+            found: Some(doc.name.clone()),
+        });
+    }
+
+    if let Some(meta) = doc.get_child("meta") {
+        check::<u32>(meta, &format!("{}/meta", root), "samplerate", false, &mut problems);
+        check::<u32>(meta, &format!("{}/meta", root), "version", false, &mut problems);
+        let bits = check::<u16>(meta, &format!("{}/meta", root), "bitdepth", false, &mut problems);
+        if let Some(bits) = bits.filter(|b| ![16, 24, 32].contains(b)) {
+            problems.push(Problem {
+                path: format!("{}/meta", root),
+                attribute: Some("bitdepth".to_string()),
+                expected: "16, 24 or 32".to_string(),
+                found: Some(bits.to_string()),
+            });
+        }
+    }
+
+    let modules = match doc.get_child("modules") {
+        Some(modules) => modules,
+        None => {
+            problems.push(Problem {
+                path: root,
+                attribute: None,
+                expected: "modules element".to_string(),
+                found: None,
+            });
+            return problems;
+        }
+    };
+
+    let modules_path = format!("{}/modules", root);
+    let mut ids: HashSet<u16> = HashSet::new();
+    for (i, module) in modules.children.iter().enumerate() {
+        let path = format!("{}/{}", modules_path, segment(&modules.children, i));
+        if let Some(id) = check::<u16>(module, &path, "id", true, &mut problems) {
+            if !ids.insert(id) {
+                problems.push(Problem {
+                    path: path.clone(),
+                    attribute: Some("id".to_string()),
+                    expected: "unique module id".to_string(),
+                    found: Some(id.to_string()),
+                });
+            }
+        }
+        validate_element(module, &path, &mut problems);
+    }
+
+    problems
+}
+
+fn validate_element(el: &Element, path: &str, problems: &mut Vec<Problem>) {
+    match &el.name[..] {
+        "param" => {
+            check::<String>(el, path, "name", true, problems);
+            check::<Param>(el, path, "value", true, problems);
+        },
+        "mark" => {
+            check::<String>(el, path, "name", true, problems);
+            check::<Offset>(el, path, "value", true, problems);
+        },
+        "note" => {
+            check::<u16>(el, path, "id", true, problems);
+            check::<Key>(el, path, "key", true, problems);
+            check::<Offset>(el, path, "t_in", true, problems);
+            check::<Offset>(el, path, "t_out", true, problems);
+            check::<Volume>(el, path, "vel", true, problems);
+        },
+        "cc" => {
+            check::<u8>(el, path, "number", true, problems);
+            check::<String>(el, path, "param", true, problems);
+            check::<Param>(el, path, "min", true, problems);
+            check::<Param>(el, path, "max", true, problems);
+        },
+        _ => {}
+    }
+    for i in 0..el.children.len() {
+        let child_path = format!("{}/{}", path, segment(&el.children, i));
+        validate_element(&el.children[i], &child_path, problems);
+    }
+}
+
+// Parses an attribute, noting why it could not be
+fn check<T: FromStr>(
+    el: &Element, 
+    path: &str, 
+    name: &str, 
+    required: bool, 
+    problems: &mut Vec<Problem>) -> Option<T> {
+
+    let expected = std::any::type_name::<T>().rsplit("::").next().unwrap_or("value");
+    match el.attributes.get(name) {
+        Some(raw) => {
+            let value = raw.parse::<T>().ok();
+            if value.is_none() {
+                problems.push(Problem {
+                    path: path.to_string(),
+                    attribute: Some(name.to_string()),
+                    expected: expected.to_string(),
+                    found: Some(raw.clone()),
+                });
+            }
+            value
+        },
+        None => {
+            if required {
+                problems.push(Problem {
+                    path: path.to_string(),
+                    attribute: Some(name.to_string()),
+                    expected: expected.to_string(),
+                    found: None,
+                });
             }
+            None
         }
-    } else {
-        panic!("No modules defined!");
     }
+}
 
-    match patch {
-        Some(p) => { result.modules.push(p); },
-        None => {} // No need to panic, sound will add a master route
+fn segment(siblings: &[Element], i: usize) -> String {
+    let el = &siblings[i];
+    for key in ["id", "name"].iter() {
+        if let Some(value) = el.attributes.get(*key) {
+            return format!("{}[@{}={}]", el.name, key, value);
+        }
     }
+    let nth = siblings[..i].iter().filter(|s| s.name == el.name).count() + 1;
+    format!("{}[{}]", el.name, nth)
+}
 
-    result
+pub fn write_document(doc: &Document) -> io::Result<()> {
+    let doc_path = config().projects.join(format!("{}.xml", doc.src));
+    let mut relative = doc.clone();
+    relative_assets(&mut relative, &project_dir(&doc_path));
+    write_element(&document_element(&relative), fs::File::create(doc_path)?)
 }
 
-pub fn write_document(doc: &mut Document) {
+pub(crate) fn document_element(doc: &Document) -> Element {
     let mut root = Element::new("project");
 
-    // Metadata declaration (sample rate, bitrate, project-wide)
+    // Metadata declaration (format version, sample rate, project-wide)
     let mut meta = Element::new("meta");
     meta.attributes.insert("samplerate".to_string(), doc.sample_rate.to_string());
+    meta.attributes.insert("bitdepth".to_string(), doc.bit_depth.to_string());
+    meta.attributes.insert("version".to_string(), FORMAT_VERSION.to_string());
     root.children.push(meta);
 
     // Title declaration
@@ -102,34 +384,44 @@ pub fn write_document(doc: &mut Document) {
 
     // Modules declaration
     let mut modules = Element::new("modules");
-    for (id, module_el) in doc.modules.iter_mut() { 
+    for (id, module) in doc.modules.iter() {
+        let mut module_el = write_module(module);
         module_el.attributes.insert("id".to_string(), id.to_string());
-        modules.children.push(module_el.to_owned());
-    } 
+        modules.children.push(module_el);
+    }
     root.children.push(modules);
+    root
+}
 
-    let doc_path: String = format!("{}{}.xml", PALIT_ROOT, doc.src);
+pub(crate) fn write_element<W: io::Write>(root: &Element, out: W) -> io::Result<()> {
     root.write_with_config(
-        fs::File::create(doc_path).unwrap(), 
+        out,
         EmitterConfig::new()
             .line_separator("\r\n")
             .perform_indent(true)
-            .normalize_empty_elements(true));
+            .normalize_empty_elements(true))
+        .map_err(|e| io::Error::other(e.to_string()))
+}
+
+pub(crate) fn read_note(note: &Element, r_id: u16) -> Option<Note> {
+    Some(Note {
+        id: attr(note, "id")?,
+        r_id,
+        note: attr(note, "key")?,
+        t_in: attr(note, "t_in")?,
+        t_out: attr(note, "t_out")?,
+        vel: attr(note, "vel")?,
+    })
 }
 
 pub fn note_list(doc: &mut Element, r_id: u16) -> (&mut Element, Vec<Note>) {
     let mut notes: Vec<Note> = vec![];
     while let Some(note) = doc.take_child("note") {
-        notes.push(Note {
-            id: note.attributes.get("id").unwrap().parse::<u16>().unwrap(),
-            r_id,
-            note: note.attributes.get("key").unwrap().parse::<Key>().unwrap(),
-            t_in: note.attributes.get("t_in").unwrap().parse::<Offset>().unwrap(),
-            t_out: note.attributes.get("t_out").unwrap().parse::<Offset>().unwrap(),
-            vel: note.attributes.get("vel").unwrap().parse::<Volume>().unwrap(),
-        });
+        if let Some(note) = read_note(&note, r_id) {
+            notes.push(note);
+        }
     }
-    return (doc, notes);
+    (doc, notes)
 }
 
 pub fn param_add<T>(el: &mut Element, value: T, name: String)
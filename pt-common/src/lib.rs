@@ -1,7 +1,30 @@
 extern crate xmltree;
+extern crate libc;
 
 pub mod action;
 pub use action::Action;
+pub use action::ProtocolError;
+pub use action::ProtocolReason;
+
+pub mod decoder;
+pub use decoder::Decoder;
+
+pub mod config;
+pub use config::Config;
+pub use config::config;
+
+pub mod transport;
+pub use transport::Transport;
+pub use transport::Server;
+pub use transport::Link;
+pub use transport::Event;
+pub use transport::PROTOCOL_VERSION;
+
+pub mod ring;
+
+pub mod script;
+pub use script::read_script;
+pub use script::ScriptError;
 
 pub mod pcm;
 pub use pcm::Note;
@@ -18,9 +41,45 @@ pub mod document;
 pub use document::mark_map;
 pub use document::param_map;
 pub use document::read_document;
+pub use document::read_header;
+pub use document::Header;
+pub use document::validate_document;
+pub use document::DocumentError;
+pub use document::Problem;
 pub use document::write_document;
 pub use document::mark_add;
 pub use document::param_add;
 pub use document::Document;
 pub use document::note_list;
-pub use document::PALIT_ROOT;
\ No newline at end of file
+
+pub mod migration;
+pub use migration::Migration;
+pub use migration::FORMAT_VERSION;
+
+pub mod bundle;
+pub use bundle::Relink;
+pub use bundle::collect;
+
+pub mod model;
+pub use model::ModuleDoc;
+pub use model::TimelineDoc;
+pub use model::TrackDoc;
+pub use model::AssetDoc;
+pub use model::AudioRegionDoc;
+pub use model::MidiRegionDoc;
+pub use model::PatchDoc;
+pub use model::RouteDoc;
+pub use model::AnchorDoc;
+pub use model::HammondDoc;
+pub use model::ArpeggioDoc;
+pub use model::KeyboardDoc;
+pub use model::ChordDoc;
+pub use model::InputDoc;
+pub use model::PluginDoc;
+pub use model::BindingDoc;
+pub use model::bind;
+pub use model::read_module;
+pub use model::write_module;
+pub use model::REGIONS_PER_TRACK;
+pub use model::KEYBED;
+pub use model::DRAWBARS;
@@ -0,0 +1,91 @@
+use std::fmt;
+use xmltree::Element;
+use crate::Offset;
+use crate::document::attr;
+
+/*
+    Projects carry the format they were written in as <meta version="n">,
+    files from before versioning are version 0. Each step upgrades a
+    project by exactly one version, so loading an old file walks the
+    chain until it reaches FORMAT_VERSION. Add a step to MIGRATIONS and
+    bump FORMAT_VERSION whenever the schema changes.
+*/
+
+pub const FORMAT_VERSION: u32 = 1;
+
+// One step of the chain that was applied to a project
+#[derive(Debug, Clone, PartialEq)]
+pub struct Migration {
+    pub from: u32,
+    pub to: u32,
+    pub summary: &'static str,
+}
+
+impl fmt::Display for Migration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "v{} -> v{}: {}", self.from, self.to, self.summary)
+    }
+}
+
+type Step = (&'static str, fn(&mut Element));
+
+// MIGRATIONS[n] upgrades version n to n + 1
+const MIGRATIONS: [Step; FORMAT_VERSION as usize] = [
+    ("timeline duration marks become seq_out", duration_to_seq_out),
+];
+
+// Version of a parsed project, None if the attribute is malformed
+pub fn format_version(doc: &Element) -> Option<u32> {
+    match doc.get_child("meta").and_then(|meta| meta.attributes.get("version")) {
+        Some(version) => version.parse().ok(),
+        None => Some(0),
+    }
+}
+
+// Upgrades a project in place, returning the steps that were applied
+pub fn migrate(doc: &mut Element, from: u32) -> Vec<Migration> {
+    let mut applied = vec![];
+    for version in from..FORMAT_VERSION {
+        let (summary, step) = MIGRATIONS[version as usize];
+        step(doc);
+        applied.push(Migration { from: version, to: version + 1, summary });
+    }
+
+    if let Some(meta) = doc.get_mut_child("meta") {
+        meta.attributes.insert("version".to_string(), FORMAT_VERSION.to_string());
+    }
+    applied
+}
+
+fn modules<'a>(doc: &'a mut Element, name: &'a str) -> impl Iterator<Item=&'a mut Element> + 'a {
+    doc.get_mut_child("modules").into_iter()
+        .flat_map(move |modules| modules.children.iter_mut().filter(move |m| m.name == name))
+}
+
+fn mark(el: &Element, name: &str) -> Option<Offset> {
+    el.children.iter()
+        .find(|c| c.name == "mark" && c.attributes.get("name").is_some_and(|n| n == name))
+        .and_then(|c| attr(c, "value"))
+}
+
+// Version 0 timelines stored their length as a duration mark, and
+// ... usually left seq_out at zero
+fn duration_to_seq_out(doc: &mut Element) {
+    for timeline in modules(doc, "timeline") {
+        let duration = match mark(timeline, "duration") {
+            Some(duration) => duration,
+            None => continue,
+        };
+        let seq_in = mark(timeline, "seq_in").unwrap_or(0);
+        let seq_out = mark(timeline, "seq_out").unwrap_or(0);
+
+        timeline.children.retain(|c| !(c.name == "mark" &&
+            c.attributes.get("name").is_some_and(|n| n == "duration" || n == "seq_out")));
+
+        let mut mark = Element::new("mark");
+        mark.attributes.insert("name".to_string(), "seq_out".to_string());
+        let value = if seq_out > seq_in { seq_out } else { seq_in + duration };
+        mark.attributes.insert("value".to_string(), value.to_string());
+        timeline.children.push(mark);
+    }
+}
@@ -0,0 +1,585 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use xmltree::Element;
+use crate::{Note, Key, Param, Offset};
+use crate::document::{attr, read_note, param_add, mark_add};
+
+/*
+    Typed modules of a project. Both pt-client and pt-sound read these
+    instead of walking xml, so they can't disagree about defaults. The
+    readers never fail: anything malformed falls back to its default,
+    validate_document is where problems are reported.
+*/
+
+// Regions and assets are numbered per track, 1000 to a track
+pub const REGIONS_PER_TRACK: u16 = 1000;
+
+// The computer keyboard pt-input reads, the device keyboards play from
+// ... unless they name another one
+pub const KEYBED: &str = "keys";
+
+pub const DRAWBARS: [&str; 9] = ["16", "5.3", "8", "4", "2.6", "2", "1.6", "1.3", "1"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssetDoc {
+    pub id: u16,
+    pub src: String,
+    pub size: Offset,
+    pub channels: u16,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioRegionDoc {
+    pub id: u16,
+    pub asset: u16,
+    pub asset_in: Offset,
+    pub offset: Offset,
+    pub duration: Offset,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MidiRegionDoc {
+    pub id: u16,
+    pub offset: Offset,
+    pub duration: Offset,
+    pub notes: Vec<Note>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackDoc {
+    pub id: u16,
+    pub gain: Param, // dB
+    pub pan: Param,  // -1 left to 1 right
+    pub audio_regions: Vec<AudioRegionDoc>,
+    pub midi_regions: Vec<MidiRegionDoc>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimelineDoc {
+    pub bpm: u16,
+    pub meter_beat: u16,
+    pub meter_note: u16,
+    pub seq_in: Offset,
+    pub seq_out: Offset,
+    pub loop_in: Offset,
+    pub loop_out: Offset,
+    pub assets: Vec<AssetDoc>,
+    pub tracks: Vec<TrackDoc>,
+}
+
+impl TimelineDoc {
+    // Asset ids are global, region asset ids are local to their track.
+    // ... Both come from the project file, so too large ones find nothing
+    pub fn asset(&self, track: u16, asset: u16) -> Option<&AssetDoc> {
+        let global_id = track.checked_mul(REGIONS_PER_TRACK)?.checked_add(asset)?;
+        self.assets.iter().find(|a| a.id == global_id)
+    }
+}
+
+impl Default for TimelineDoc {
+    fn default() -> Self {
+        TimelineDoc {
+            bpm: 127,
+            meter_beat: 4,
+            meter_note: 4,
+            seq_in: 0,
+            seq_out: 48000,
+            loop_in: 0,
+            loop_out: 0,
+            assets: vec![],
+            tracks: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnchorDoc {
+    pub module: u16,
+    pub index: u16,
+    pub input: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteDoc {
+    pub id: u16,
+    pub anchors: Vec<AnchorDoc>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PatchDoc {
+    pub routes: Vec<RouteDoc>,
+}
+
+// A MIDI controller moving a module param, its 0 to 127 is spread
+// ... over min to max
+#[derive(Debug, Clone, PartialEq)]
+pub struct BindingDoc {
+    pub cc: u8,
+    pub param: String,
+    pub min: Param,
+    pub max: Param,
+}
+
+impl BindingDoc {
+    pub fn scale(&self, value: u8) -> Param {
+        self.min + (self.max - self.min) * Param::from(value.min(127)) / 127.0
+    }
+}
+
+// A controller moves one param of a module and a param follows one
+// ... controller, whatever was bound to either is replaced
+pub fn bind(bindings: &mut Vec<BindingDoc>, binding: BindingDoc) {
+    bindings.retain(|b| b.cc != binding.cc && b.param != binding.param);
+    bindings.push(binding);
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HammondDoc {
+    pub drawbars: [Param; 9],
+    pub bindings: Vec<BindingDoc>,
+}
+
+impl Default for HammondDoc {
+    fn default() -> Self {
+        HammondDoc { drawbars: [0.1; 9], bindings: vec![] }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArpeggioDoc {
+    pub length: Param,
+    pub pattern: usize,
+    pub bindings: Vec<BindingDoc>,
+}
+
+impl Default for ArpeggioDoc {
+    fn default() -> Self {
+        ArpeggioDoc { length: 4.0, pattern: 0, bindings: vec![] }
+    }
+}
+
+// A note source, notes from its device go out of its only anchor.
+// ... An empty device is only played on screen.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyboardDoc {
+    pub device: String,
+    pub octave: Key,
+    pub shift: i8,
+    pub velocity: Param,
+}
+
+impl Default for KeyboardDoc {
+    fn default() -> Self {
+        KeyboardDoc { device: KEYBED.to_string(), octave: 3, shift: 0, velocity: 0.3 }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChordDoc {
+    pub notes: Vec<Note>,
+}
+
+// Faust modules are named after their .dsp file
+#[derive(Debug, Clone, PartialEq)]
+pub struct PluginDoc {
+    pub name: String,
+    pub params: Vec<(String, Param)>,
+    pub bindings: Vec<BindingDoc>,
+}
+
+// A capture device, an empty device is the configured audio card
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputDoc {
+    pub device: String,
+    pub channels: u16,
+    // One anchor per pair of channels instead of one per channel
+    pub stereo: bool,
+}
+
+impl Default for InputDoc {
+    fn default() -> Self {
+        InputDoc { device: String::new(), channels: 2, stereo: false }
+    }
+}
+
+impl InputDoc {
+    // Output anchors in order, with the hardware channels each one carries
+    pub fn anchors(&self) -> Vec<(String, Vec<u16>)> {
+        let width = if self.stereo { 2 } else { 1 };
+        (0..self.channels).step_by(width).map(|first| {
+            let last = (first + width as u16).min(self.channels);
+            let name = if last - first > 1 {
+                format!("In {}-{}", first + 1, last)
+            } else {
+                format!("In {}", first + 1)
+            };
+            (name, (first..last).collect())
+        }).collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ModuleDoc {
+    Timeline(TimelineDoc),
+    Patch(PatchDoc),
+    Hammond(HammondDoc),
+    Arpeggio(ArpeggioDoc),
+    Keyboard(KeyboardDoc),
+    Chord(ChordDoc),
+    Input(InputDoc),
+    Plugin(PluginDoc),
+}
+
+impl ModuleDoc {
+    // An empty module of the given kind
+    pub fn new(name: &str) -> Self {
+        match name {
+            "timeline" => ModuleDoc::Timeline(TimelineDoc::default()),
+            "patch" => ModuleDoc::Patch(PatchDoc::default()),
+            "hammond" => ModuleDoc::Hammond(HammondDoc::default()),
+            "arpeggio" => ModuleDoc::Arpeggio(ArpeggioDoc::default()),
+            "keyboard" => ModuleDoc::Keyboard(KeyboardDoc::default()),
+            "chord" => ModuleDoc::Chord(ChordDoc::default()),
+            "input" => ModuleDoc::Input(InputDoc::default()),
+            plugin => ModuleDoc::Plugin(PluginDoc {
+                name: plugin.to_string(),
+                params: vec![],
+                bindings: vec![],
+            }),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            ModuleDoc::Timeline(_) => "timeline",
+            ModuleDoc::Patch(_) => "patch",
+            ModuleDoc::Hammond(_) => "hammond",
+            ModuleDoc::Arpeggio(_) => "arpeggio",
+            ModuleDoc::Keyboard(_) => "keyboard",
+            ModuleDoc::Chord(_) => "chord",
+            ModuleDoc::Input(_) => "input",
+            ModuleDoc::Plugin(plugin) => &plugin.name,
+        }
+    }
+
+    // The device a note source plays from, if the module is one
+    pub fn device(&self) -> Option<&str> {
+        match self {
+            ModuleDoc::Keyboard(keyboard) if !keyboard.device.is_empty() => Some(&keyboard.device),
+            _ => None,
+        }
+    }
+
+    // Controllers bound to params, for the modules that have any
+    pub fn bindings(&self) -> &[BindingDoc] {
+        match self {
+            ModuleDoc::Hammond(hammond) => &hammond.bindings,
+            ModuleDoc::Arpeggio(arpeggio) => &arpeggio.bindings,
+            ModuleDoc::Plugin(plugin) => &plugin.bindings,
+            _ => &[],
+        }
+    }
+}
+
+fn children<'a>(el: &'a Element, name: &'a str) -> impl Iterator<Item=&'a Element> + 'a {
+    el.children.iter().filter(move |c| c.name == name)
+}
+
+// Non-destructive param_map and mark_map
+fn values<T: std::str::FromStr>(el: &Element, tag: &str) -> HashMap<String, T> {
+    children(el, tag).filter_map(|c| {
+        Some((c.attributes.get("name")?.to_string(), attr::<T>(c, "value")?))
+    }).collect()
+}
+
+fn set<T: ToString>(el: &mut Element, name: &str, value: T) {
+    el.attributes.insert(name.to_string(), value.to_string());
+}
+
+fn read_bindings(el: &Element) -> Vec<BindingDoc> {
+    children(el, "cc").filter_map(|c| {
+        Some(BindingDoc {
+            cc: attr(c, "number")?,
+            param: c.attributes.get("param")?.to_string(),
+            min: attr(c, "min")?,
+            max: attr(c, "max")?,
+        })
+    }).collect()
+}
+
+fn write_bindings(el: &mut Element, bindings: &[BindingDoc]) {
+    for binding in bindings.iter() {
+        let mut cc = Element::new("cc");
+        set(&mut cc, "number", binding.cc);
+        set(&mut cc, "param", &binding.param);
+        set(&mut cc, "min", binding.min);
+        set(&mut cc, "max", binding.max);
+        el.children.push(cc);
+    }
+}
+
+pub fn read_module(el: &Element) -> ModuleDoc {
+    match &el.name[..] {
+        "timeline" => ModuleDoc::Timeline(read_timeline(el)),
+        "patch" => ModuleDoc::Patch(read_patch(el)),
+        "hammond" => {
+            let params = values::<Param>(el, "param");
+            let mut doc = HammondDoc::default();
+            for (bar, name) in doc.drawbars.iter_mut().zip(DRAWBARS.iter()) {
+                if let Some(value) = params.get(*name) { *bar = *value; }
+            }
+            doc.bindings = read_bindings(el);
+            ModuleDoc::Hammond(doc)
+        },
+        "arpeggio" => {
+            let params = values::<Param>(el, "param");
+            let default = ArpeggioDoc::default();
+            ModuleDoc::Arpeggio(ArpeggioDoc {
+                length: *params.get("length").unwrap_or(&default.length),
+                pattern: params.get("pattern").map_or(default.pattern, |p| *p as usize),
+                bindings: read_bindings(el),
+            })
+        },
+        "keyboard" => {
+            let params = values::<Param>(el, "param");
+            let default = KeyboardDoc::default();
+            ModuleDoc::Keyboard(KeyboardDoc {
+                device: el.attributes.get("device").cloned().unwrap_or(default.device),
+                octave: params.get("octave").map_or(default.octave, |p| *p as Key),
+                shift: params.get("shift").map_or(default.shift, |p| *p as i8),
+                velocity: *params.get("velocity").unwrap_or(&default.velocity),
+            })
+        },
+        "chord" => ModuleDoc::Chord(ChordDoc {
+            notes: children(el, "note").filter_map(|n| read_note(n, 0)).collect(),
+        }),
+        "input" => {
+            let params = values::<Param>(el, "param");
+            let default = InputDoc::default();
+            ModuleDoc::Input(InputDoc {
+                device: el.attributes.get("device").cloned().unwrap_or(default.device),
+                channels: params.get("channels").map_or(default.channels, |p| *p as u16),
+                stereo: params.get("stereo").map_or(default.stereo, |p| *p != 0.0),
+            })
+        },
+        plugin => {
+            let mut params: Vec<(String, Param)> = values::<Param>(el, "param")
+                .into_iter().collect();
+            params.sort_by(|a, b| a.0.cmp(&b.0));
+            ModuleDoc::Plugin(PluginDoc { name: plugin.to_string(), params, bindings: read_bindings(el) })
+        },
+    }
+}
+
+pub fn write_module(module: &ModuleDoc) -> Element {
+    let mut root = Element::new(module.name());
+    match module {
+        ModuleDoc::Timeline(timeline) => write_timeline(&mut root, timeline),
+        ModuleDoc::Patch(patch) => {
+            for route in patch.routes.iter() {
+                let mut route_el = Element::new("route");
+                set(&mut route_el, "id", route.id);
+                for anchor in route.anchors.iter() {
+                    let mut anchor_el = Element::new(if anchor.input { "input" } else { "output" });
+                    set(&mut anchor_el, "module", anchor.module);
+                    set(&mut anchor_el, "index", anchor.index);
+                    route_el.children.push(anchor_el);
+                }
+                root.children.push(route_el);
+            }
+        },
+        ModuleDoc::Hammond(hammond) => {
+            for (bar, name) in hammond.drawbars.iter().zip(DRAWBARS.iter()) {
+                param_add(&mut root, bar, name.to_string());
+            }
+            write_bindings(&mut root, &hammond.bindings);
+        },
+        ModuleDoc::Arpeggio(arpeggio) => {
+            param_add(&mut root, arpeggio.length, "length".to_string());
+            param_add(&mut root, arpeggio.pattern, "pattern".to_string());
+            write_bindings(&mut root, &arpeggio.bindings);
+        },
+        ModuleDoc::Keyboard(keyboard) => {
+            if keyboard.device != KEYBED {
+                set(&mut root, "device", &keyboard.device);
+            }
+            param_add(&mut root, keyboard.octave, "octave".to_string());
+            param_add(&mut root, keyboard.shift, "shift".to_string());
+            param_add(&mut root, keyboard.velocity, "velocity".to_string());
+        },
+        ModuleDoc::Chord(chord) => {
+            for note in chord.notes.iter() {
+                root.children.push(write_note(note));
+            }
+        },
+        ModuleDoc::Input(input) => {
+            if !input.device.is_empty() {
+                set(&mut root, "device", &input.device);
+            }
+            param_add(&mut root, input.channels, "channels".to_string());
+            param_add(&mut root, input.stereo as u8, "stereo".to_string());
+        },
+        ModuleDoc::Plugin(plugin) => {
+            for (name, value) in plugin.params.iter() {
+                param_add(&mut root, value, name.to_string());
+            }
+            write_bindings(&mut root, &plugin.bindings);
+        },
+    }
+    root
+}
+
+fn read_timeline(el: &Element) -> TimelineDoc {
+    let params = values::<Param>(el, "param");
+    let marks = values::<Offset>(el, "mark");
+    let default = TimelineDoc::default();
+
+    let mut tracks: Vec<TrackDoc> = children(el, "track").filter_map(|track| {
+        let track_params = values::<Param>(track, "param");
+        Some(TrackDoc {
+            id: attr(track, "id")?,
+            gain: *track_params.get("gain").unwrap_or(&0.0),
+            pan: *track_params.get("pan").unwrap_or(&0.0),
+            audio_regions: children(track, "audio").filter_map(|region| {
+                Some(AudioRegionDoc {
+                    id: attr(region, "id")?,
+                    asset: attr(region, "asset")?,
+                    asset_in: attr(region, "in")?,
+                    offset: attr(region, "offset")?,
+                    duration: attr(region, "duration")?,
+                })
+            }).collect(),
+            midi_regions: children(track, "midi").filter_map(|region| {
+                let id = attr(region, "id")?;
+                Some(MidiRegionDoc {
+                    id,
+                    offset: attr(region, "offset")?,
+                    duration: attr(region, "duration")?,
+                    notes: children(region, "note").filter_map(|n| read_note(n, id)).collect(),
+                })
+            }).collect(),
+        })
+    }).collect();
+
+    // The engine connects tracks to anchors in this order
+    tracks.sort_by_key(|t| t.id);
+
+    TimelineDoc {
+        bpm: params.get("bpm").map_or(default.bpm, |p| *p as u16),
+        meter_beat: params.get("meter_beat").map_or(default.meter_beat, |p| *p as u16),
+        meter_note: params.get("meter_note").map_or(default.meter_note, |p| *p as u16),
+        seq_in: *marks.get("seq_in").unwrap_or(&default.seq_in),
+        seq_out: *marks.get("seq_out").unwrap_or(&default.seq_out),
+        loop_in: *marks.get("loop_in").unwrap_or(&default.loop_in),
+        loop_out: *marks.get("loop_out").unwrap_or(&default.loop_out),
+        assets: children(el, "asset").filter_map(|asset| {
+            // relink fills in channels from the file when they are missing
+            Some(AssetDoc {
+                id: attr(asset, "id")?,
+                src: attr(asset, "src")?,
+                size: attr(asset, "size")?,
+                channels: attr(asset, "channels").unwrap_or(2),
+            })
+        }).collect(),
+        tracks,
+    }
+}
+
+fn write_timeline(root: &mut Element, timeline: &TimelineDoc) {
+    param_add(root, timeline.bpm, "bpm".to_string());
+    param_add(root, timeline.meter_beat, "meter_beat".to_string());
+    param_add(root, timeline.meter_note, "meter_note".to_string());
+    mark_add(root, timeline.seq_in, "seq_in".to_string());
+    mark_add(root, timeline.seq_out, "seq_out".to_string());
+    mark_add(root, timeline.loop_in, "loop_in".to_string());
+    mark_add(root, timeline.loop_out, "loop_out".to_string());
+
+    for asset in timeline.assets.iter() {
+        let mut asset_el = Element::new("asset");
+        set(&mut asset_el, "id", asset.id);
+        set(&mut asset_el, "size", asset.size);
+        set(&mut asset_el, "src", &asset.src);
+        set(&mut asset_el, "channels", asset.channels);
+        root.children.push(asset_el);
+    }
+
+    for track in timeline.tracks.iter() {
+        let mut track_el = Element::new("track");
+        set(&mut track_el, "id", track.id);
+        param_add(&mut track_el, track.gain, "gain".to_string());
+        param_add(&mut track_el, track.pan, "pan".to_string());
+        for region in track.audio_regions.iter() {
+            let mut audio_el = Element::new("audio");
+            set(&mut audio_el, "id", region.id);
+            set(&mut audio_el, "asset", region.asset);
+            set(&mut audio_el, "in", region.asset_in);
+            set(&mut audio_el, "duration", region.duration);
+            set(&mut audio_el, "offset", region.offset);
+            track_el.children.push(audio_el);
+        }
+        for region in track.midi_regions.iter() {
+            let mut midi_el = Element::new("midi");
+            set(&mut midi_el, "id", region.id);
+            set(&mut midi_el, "offset", region.offset);
+            set(&mut midi_el, "duration", region.duration);
+            for note in region.notes.iter() {
+                midi_el.children.push(write_note(note));
+            }
+            track_el.children.push(midi_el);
+        }
+        root.children.push(track_el);
+    }
+}
+
+fn read_patch(el: &Element) -> PatchDoc {
+    PatchDoc {
+        routes: children(el, "route").filter_map(|route| {
+            Some(RouteDoc {
+                id: attr(route, "id")?,
+                anchors: route.children.iter().filter_map(|anchor| {
+                    Some(AnchorDoc {
+                        module: attr(anchor, "module")?,
+                        index: attr(anchor, "index")?,
+                        input: match &anchor.name[..] {
+                            "input" => true,
+                            "output" => false,
+                            _ => return None,
+                        },
+                    })
+                }).collect(),
+            })
+        }).collect(),
+    }
+}
+
+fn write_note(note: &Note) -> Element {
+    let mut note_el = Element::new("note");
+    set(&mut note_el, "id", note.id);
+    set(&mut note_el, "key", note.note);
+    set(&mut note_el, "vel", note.vel);
+    set(&mut note_el, "t_in", note.t_in);
+    set(&mut note_el, "t_out", note.t_out);
+    note_el
+}
+
+// Reads the channel count from a wav header without decoding it
+pub(crate) fn wav_channels(src: &Path) -> Option<u16> {
+    let mut file = File::open(src).ok()?;
+    let mut riff = [0u8; 12];
+    file.read_exact(&mut riff).ok()?;
+    if &riff[0..4] != b"RIFF" || &riff[8..12] != b"WAVE" { return None; }
+    loop {
+        let mut chunk = [0u8; 8];
+        file.read_exact(&mut chunk).ok()?;
+        let size = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+        if &chunk[0..4] == b"fmt " {
+            let mut format = [0u8; 4];
+            file.read_exact(&mut format).ok()?;
+            return Some(u16::from_le_bytes([format[2], format[3]]));
+        }
+        // Chunks are padded to an even length
+        file.seek(SeekFrom::Current(i64::from(size) + i64::from(size & 1))).ok()?;
+    }
+}
@@ -3,13 +3,13 @@ pub type Volume = f64;
 pub type Offset = u32;
 pub type Param = f32;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Route {
     pub id: u16,
     pub patch: Vec<Anchor>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Anchor {
     pub index: u16,
     pub module_id: u16,
@@ -17,13 +17,13 @@ pub struct Anchor {
     pub input: bool,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Module {
     pub id: u16,
     pub name: String,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Note {
     pub id: u16,
     pub r_id: u16,
@@ -0,0 +1,109 @@
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/*
+    A bounded queue between exactly one producer and one consumer, for
+    talking to the audio thread. Every slot is allocated when the ring
+    is made, so push and pop never allocate, lock or wait: push hands
+    the value back when the ring is full and pop returns None when it
+    is empty. Values still queued are dropped with the ring, by
+    whichever half goes last.
+*/
+
+struct Ring<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    // Slots read and written so far, they only ever count up
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+// A slot is only touched by the producer before tail passes it, and
+// ... only by the consumer after, until head passes it again
+unsafe impl<T: Send> Send for Ring<T> {}
+unsafe impl<T: Send> Sync for Ring<T> {}
+
+impl<T> Ring<T> {
+    fn len(&self) -> usize {
+        self.tail.load(Ordering::Acquire).wrapping_sub(self.head.load(Ordering::Acquire))
+    }
+}
+
+impl<T> Drop for Ring<T> {
+    fn drop(&mut self) {
+        let (head, tail) = (*self.head.get_mut(), *self.tail.get_mut());
+        let mut i = head;
+        while i != tail {
+            let slot = &mut self.slots[i % self.slots.len()];
+            unsafe { slot.get_mut().as_mut_ptr().drop_in_place(); }
+            i = i.wrapping_add(1);
+        }
+    }
+}
+
+pub struct Producer<T> {
+    ring: Arc<Ring<T>>,
+}
+
+pub struct Consumer<T> {
+    ring: Arc<Ring<T>>,
+}
+
+pub fn ring<T>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    assert!(capacity > 0, "a ring needs at least one slot");
+    let ring = Arc::new(Ring {
+        slots: (0..capacity).map(|_| UnsafeCell::new(MaybeUninit::uninit())).collect(),
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+    });
+    (Producer { ring: ring.clone() }, Consumer { ring })
+}
+
+impl<T> Producer<T> {
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        let ring = &*self.ring;
+        let tail = ring.tail.load(Ordering::Relaxed);
+        let head = ring.head.load(Ordering::Acquire);
+        if tail.wrapping_sub(head) == ring.slots.len() {
+            return Err(value);
+        }
+        let slot = &ring.slots[tail % ring.slots.len()];
+        unsafe { (*slot.get()).as_mut_ptr().write(value); }
+        ring.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.ring.len() == self.ring.slots.len()
+    }
+
+    // The consumer is gone, nothing pushed will ever be read
+    pub fn is_abandoned(&self) -> bool {
+        Arc::strong_count(&self.ring) == 1
+    }
+}
+
+impl<T> Consumer<T> {
+    pub fn pop(&mut self) -> Option<T> {
+        let ring = &*self.ring;
+        let head = ring.head.load(Ordering::Relaxed);
+        let tail = ring.tail.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+        let slot = &ring.slots[head % ring.slots.len()];
+        let value = unsafe { (*slot.get()).as_ptr().read() };
+        ring.head.store(head.wrapping_add(1), Ordering::Release);
+        Some(value)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ring.len() == 0
+    }
+
+    // The producer is gone, nothing more will arrive after what's queued
+    pub fn is_abandoned(&self) -> bool {
+        Arc::strong_count(&self.ring) == 1
+    }
+}
@@ -0,0 +1,57 @@
+use std::{error, fmt};
+use crate::{Action, Offset};
+use crate::config::strip_comment;
+
+/*
+    A script of timestamped actions, used to drive the engine without
+    a client or a sound card. Each line holds a frame offset followed by
+    the actions to dispatch at that frame, as they would travel over IPC:
+
+        # one bar of middle C
+        0       OPEN_PROJECT:one PLAY
+        24000   NOTE_ON:60:1
+        48000   NOTE_OFF:60 EXIT  # done
+
+    As in the config, a # only starts a comment after whitespace, so
+    arguments like OPEN_PROJECT:take#2 are read whole.
+*/
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptError {
+    pub line: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+impl error::Error for ScriptError {}
+
+// Actions in the order they are dispatched, lines may be out of order
+pub fn read_script(text: &str) -> Result<Vec<(Offset, Action)>, ScriptError> {
+    let mut script = vec![];
+    for (n, line) in text.lines().enumerate() {
+        let error = |reason: String| ScriptError { line: n + 1, reason };
+        let mut tokens = strip_comment(line).split_whitespace();
+        let time = match tokens.next() {
+            Some(time) => time.parse::<Offset>()
+                .map_err(|_| error(format!("expected a frame offset, found {:?}", time)))?,
+            None => continue,
+        };
+        let mut empty = true;
+        for token in tokens {
+            let action: Action = token.parse().map_err(|e| error(format!("{}", e)))?;
+            script.push((time, action));
+            empty = false;
+        }
+        if empty {
+            return Err(error(format!("no actions at {}", time)));
+        }
+    }
+    // Stable, so actions at the same frame keep their order
+    script.sort_by_key(|(time, _)| *time);
+    Ok(script)
+}
@@ -0,0 +1,526 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::action::{Action, ProtocolError, DELIMITER};
+use crate::decoder::Decoder;
+
+/*
+    pt-sound and pt-client talk either over the two named pipes made by
+    `make ipc`, or over a unix socket served by pt-sound. The socket
+    takes any number of clients, each of which opens with a handshake:
+
+        client: PALIT:<version>
+        sound:  PALIT:<version>, or ERROR:<reason> and a hang up
+
+    Everything pt-sound sends goes to every connected client. Input
+    devices still write to the pipes, so in socket mode each side also
+    reads its own pipe when one exists.
+*/
+
+pub const PROTOCOL_VERSION: u16 = 1;
+
+const HELLO: &str = "PALIT";
+const SOUND_FIFO: &str = "/tmp/pt-sound";
+const CLIENT_FIFO: &str = "/tmp/pt-client";
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(2);
+const RETRY_PERIOD: Duration = Duration::from_millis(100);
+const CHUNK_SIZE: usize = 4096;
+
+// A client this far behind has stopped reading and is dropped, the
+// ... pipe instead loses what it missed
+const MAX_BACKLOG: usize = 1 << 20;
+
+// The pipe peer, which never says hello
+const FIFO_PEER: u16 = 0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transport {
+    pub socket: Option<PathBuf>,
+    pub sound_fifo: PathBuf,
+    pub client_fifo: PathBuf,
+}
+
+impl Default for Transport {
+    fn default() -> Self {
+        Transport {
+            socket: None,
+            sound_fifo: PathBuf::from(SOUND_FIFO),
+            client_fifo: PathBuf::from(CLIENT_FIFO),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    Connected(u16),
+    Received(u16, Result<Action, ProtocolError>),
+    Disconnected(u16),
+}
+
+fn hello() -> String {
+    format!("{}:{}{}", HELLO, PROTOCOL_VERSION, DELIMITER)
+}
+
+// Checks a greeting, returning why it was refused
+fn check_hello(token: &str) -> Result<(), String> {
+    let mut parts = token.splitn(2, ':');
+    match (parts.next(), parts.next().map(|v| v.parse::<u16>())) {
+        (Some(HELLO), Some(Ok(PROTOCOL_VERSION))) => Ok(()),
+        (Some(HELLO), Some(Ok(version))) => Err(format!(
+            "protocol version {} is not supported, expected {}", version, PROTOCOL_VERSION)),
+        _ => Err(format!("expected {}:{} handshake", HELLO, PROTOCOL_VERSION)),
+    }
+}
+
+fn is_fifo(path: &Path) -> bool {
+    fs::metadata(path).map(|m| m.file_type().is_fifo()).unwrap_or(false)
+}
+
+fn open_reader(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .custom_flags(libc::O_NONBLOCK)
+        .read(true)
+        .open(path)
+}
+
+// Holding the write end too means the pipe never reports a hang up,
+// ... so input devices can come and go
+fn open_inbox(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .custom_flags(libc::O_NONBLOCK)
+        .read(true)
+        .write(true)
+        .open(path)
+}
+
+// Waits for somebody to open the other end, writes never block
+fn open_writer(path: &Path) -> io::Result<File> {
+    loop {
+        match try_open_writer(path)? {
+            Some(file) => return Ok(file),
+            None => thread::sleep(RETRY_PERIOD),
+        }
+    }
+}
+
+// Nothing while nobody is reading
+fn try_open_writer(path: &Path) -> io::Result<Option<File>> {
+    match OpenOptions::new().custom_flags(libc::O_NONBLOCK).write(true).open(path) {
+        Ok(file) => Ok(Some(file)),
+        Err(ref e) if e.raw_os_error() == Some(libc::ENXIO) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+enum Channel {
+    // Output is reopened from its path whenever the reader goes away
+    Pipe { input: Option<File>, output: Option<(File, PathBuf)> },
+    Socket(UnixStream),
+}
+
+impl Channel {
+    fn as_raw_fd(&self) -> Option<RawFd> {
+        match self {
+            Channel::Pipe { input, .. } => input.as_ref().map(|f| f.as_raw_fd()),
+            Channel::Socket(stream) => Some(stream.as_raw_fd()),
+        }
+    }
+}
+
+impl Read for Channel {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Channel::Pipe { input: Some(f), .. } => f.read(buf),
+            Channel::Pipe { input: None, .. } => Err(io::ErrorKind::WouldBlock.into()),
+            Channel::Socket(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Channel {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Channel::Pipe { output: Some((file, path)), .. } => {
+                match file.write(buf) {
+                    // The reader is gone, keep the backlog for the next one
+                    Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                        match try_open_writer(path)? {
+                            Some(reopened) => *file = reopened,
+                            None => return Err(io::ErrorKind::WouldBlock.into()),
+                        }
+                        file.write(buf)
+                    },
+                    result => result,
+                }
+            },
+            // Input-only pipes have nobody to answer
+            Channel::Pipe { output: None, .. } => Ok(buf.len()),
+            Channel::Socket(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Channel::Pipe { output: Some((file, _)), .. } => file.flush(),
+            Channel::Pipe { output: None, .. } => Ok(()),
+            Channel::Socket(stream) => stream.flush(),
+        }
+    }
+}
+
+// One end of a channel, buffered in both directions so that a slow
+// ... reader never blocks the audio loop
+struct Connection {
+    channel: Channel,
+    decoder: Decoder,
+    backlog: Vec<u8>,
+}
+
+impl Connection {
+    fn new(channel: Channel) -> Self {
+        Connection { channel, decoder: Decoder::new(), backlog: vec![] }
+    }
+
+    // Reads whatever is waiting, false once the other end has hung up
+    fn fill(&mut self) -> io::Result<bool> {
+        let mut chunk = [0u8; CHUNK_SIZE];
+        loop {
+            match self.channel.read(&mut chunk) {
+                Ok(0) => return Ok(false),
+                Ok(n) => self.decoder.feed(&chunk[..n]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(true),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn queue(&mut self, bytes: &[u8]) {
+        self.backlog.extend_from_slice(bytes);
+    }
+
+    // Writes as much of the backlog as the other end will take
+    fn flush(&mut self) -> io::Result<()> {
+        while !self.backlog.is_empty() {
+            match self.channel.write(&self.backlog) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => { self.backlog.drain(..n); },
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        if self.backlog.len() > MAX_BACKLOG {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "peer stopped reading"));
+        }
+        Ok(())
+    }
+}
+
+struct Peer {
+    id: u16,
+    conn: Connection,
+    greeted: bool,
+    closed: bool,
+    // When it connected, it has HANDSHAKE_TIMEOUT to say hello
+    since: Instant,
+}
+
+impl Peer {
+    // Collects this peer's events, false once it should be dropped
+    fn poll(&mut self, events: &mut Vec<Event>) -> bool {
+        // Writers come and go on a pipe, only a failed write means the
+        // ... client is gone
+        let open = self.conn.fill().unwrap_or(false) || self.id == FIFO_PEER;
+
+        if !self.greeted {
+            match self.conn.decoder.next_token().map(|t| check_hello(&t)) {
+                Some(Ok(())) => {
+                    self.greeted = true;
+                    self.conn.queue(hello().as_bytes());
+                    events.push(Event::Connected(self.id));
+                },
+                Some(Err(reason)) => return self.refuse(reason),
+                None if self.since.elapsed() > HANDSHAKE_TIMEOUT => {
+                    return self.refuse("handshake timed out".to_string());
+                },
+                None => return open && !self.closed,
+            }
+        }
+
+        let id = self.id;
+        events.extend((&mut self.conn.decoder).map(|a| Event::Received(id, a)));
+
+        if !self.flush() || !open || self.closed {
+            events.push(Event::Disconnected(self.id));
+            return false;
+        }
+        true
+    }
+
+    // Tells a client that never got connected why, before hanging up
+    fn refuse(&mut self, reason: String) -> bool {
+        self.conn.queue(Action::Error(reason).to_string().as_bytes());
+        let _ = self.conn.flush();
+        false
+    }
+
+    fn send(&mut self, bytes: &[u8]) {
+        if !self.greeted || self.closed { return; }
+        self.conn.queue(bytes);
+        if !self.flush() { self.closed = true; }
+    }
+
+    // False once this peer should be dropped. The pipe peer is kept and
+    // ... its backlog thrown away, the delimiter ending whatever action
+    // ... was cut short.
+    fn flush(&mut self) -> bool {
+        match self.conn.flush() {
+            Ok(()) => true,
+            Err(_) if self.id == FIFO_PEER => {
+                self.conn.backlog.clear();
+                self.conn.queue(DELIMITER.to_string().as_bytes());
+                true
+            },
+            Err(_) => false,
+        }
+    }
+}
+
+// pt-sound's end, reading from every client and answering all of them
+pub struct Server {
+    listener: Option<(UnixListener, PathBuf)>,
+    peers: Vec<Peer>,
+    next_id: u16,
+}
+
+impl Server {
+    pub fn bind(transport: &Transport) -> io::Result<Self> {
+        let mut server = Server { listener: None, peers: vec![], next_id: FIFO_PEER + 1 };
+
+        match transport.socket {
+            None => {
+                // Same order as the client, or both ends block forever
+                let output = open_writer(&transport.client_fifo)?;
+                let input = open_reader(&transport.sound_fifo)?;
+                server.add_pipe(Channel::Pipe {
+                    input: Some(input),
+                    output: Some((output, transport.client_fifo.clone())),
+                });
+            },
+            Some(ref path) => {
+                let listener = listen(path)?;
+                listener.set_nonblocking(true)?;
+                server.listener = Some((listener, path.to_owned()));
+                if is_fifo(&transport.sound_fifo) {
+                    let input = open_inbox(&transport.sound_fifo)?;
+                    server.add_pipe(Channel::Pipe { input: Some(input), output: None });
+                }
+            }
+        }
+        Ok(server)
+    }
+
+    fn add_pipe(&mut self, channel: Channel) {
+        self.peers.push(Peer {
+            id: FIFO_PEER,
+            conn: Connection::new(channel),
+            greeted: true,
+            closed: false,
+            since: Instant::now(),
+        });
+    }
+
+    fn accept(&mut self) {
+        let listener = match self.listener {
+            Some((ref listener, _)) => listener,
+            None => return,
+        };
+        while let Ok((stream, _)) = listener.accept() {
+            if stream.set_nonblocking(true).is_err() { continue; }
+            self.peers.push(Peer {
+                id: self.next_id,
+                conn: Connection::new(Channel::Socket(stream)),
+                greeted: false,
+                closed: false,
+                since: Instant::now(),
+            });
+            // Skip the pipe's id when wrapping
+            self.next_id = self.next_id.checked_add(1).unwrap_or(FIFO_PEER + 1);
+        }
+    }
+
+    // Accepts new clients and collects everything that arrived since
+    // ... the last call. Never blocks.
+    pub fn recv(&mut self) -> Vec<Event> {
+        self.accept();
+        let mut events = vec![];
+        let mut i = 0;
+        while i < self.peers.len() {
+            if self.peers[i].poll(&mut events) {
+                i += 1;
+            } else {
+                self.peers.remove(i);
+            }
+        }
+        events
+    }
+
+    // Sends to every client
+    pub fn send(&mut self, action: &Action) {
+        let bytes = action.to_string();
+        for peer in self.peers.iter_mut() {
+            peer.send(bytes.as_bytes());
+        }
+    }
+
+    // Sends to the client a received action came from
+    pub fn reply(&mut self, id: u16, action: &Action) {
+        let bytes = action.to_string();
+        for peer in self.peers.iter_mut().filter(|p| p.id == id) {
+            peer.send(bytes.as_bytes());
+        }
+    }
+
+    pub fn clients(&self) -> usize {
+        self.peers.iter().filter(|p| p.greeted && p.id != FIFO_PEER).count()
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        if let Some((_, ref path)) = self.listener {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+// A socket left behind by a crashed server is replaced, a live one is not
+fn listen(path: &Path) -> io::Result<UnixListener> {
+    match UnixListener::bind(path) {
+        Err(ref e) if e.kind() == io::ErrorKind::AddrInUse => {
+            if UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(io::ErrorKind::AddrInUse,
+                    format!("pt-sound is already serving {}", path.display())));
+            }
+            fs::remove_file(path)?;
+            UnixListener::bind(path)
+        },
+        result => result,
+    }
+}
+
+// A client's end, talking to pt-sound
+pub struct Link {
+    sound: Connection,
+    inbox: Option<Connection>,
+}
+
+impl Link {
+    // Blocks until pt-sound answers
+    pub fn connect(transport: &Transport) -> io::Result<Self> {
+        match transport.socket {
+            None => {
+                let input = open_reader(&transport.client_fifo)?;
+                let output = open_writer(&transport.sound_fifo)?;
+                Ok(Link {
+                    sound: Connection::new(Channel::Pipe {
+                        input: Some(input),
+                        output: Some((output, transport.sound_fifo.clone())),
+                    }),
+                    inbox: None,
+                })
+            },
+            Some(ref path) => {
+                let mut sound = Connection::new(Channel::Socket(wait_for(path)?));
+                greet(&mut sound)?;
+                let inbox = if is_fifo(&transport.client_fifo) {
+                    let input = open_inbox(&transport.client_fifo)?;
+                    Some(Connection::new(Channel::Pipe { input: Some(input), output: None }))
+                } else { None };
+                Ok(Link { sound, inbox })
+            }
+        }
+    }
+
+    pub fn send(&mut self, action: &Action) -> io::Result<()> {
+        self.sound.queue(action.to_string().as_bytes());
+        self.sound.flush()
+    }
+
+    // Collects everything that arrived, failing once pt-sound hangs up
+    pub fn recv(&mut self) -> io::Result<Vec<Result<Action, ProtocolError>>> {
+        let mut actions = vec![];
+        if let Some(ref mut inbox) = self.inbox {
+            inbox.fill()?;
+            actions.extend(&mut inbox.decoder);
+        }
+        let open = self.sound.fill()?;
+        actions.extend(&mut self.sound.decoder);
+        self.sound.flush()?;
+        if !open {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "pt-sound hung up"));
+        }
+        Ok(actions)
+    }
+
+    // File descriptors to poll for incoming actions
+    pub fn as_raw_fds(&self) -> Vec<RawFd> {
+        self.inbox.iter().chain(Some(&self.sound))
+            .filter_map(|c| c.channel.as_raw_fd())
+            .collect()
+    }
+}
+
+// Like opening a pipe, waits for pt-sound to show up
+fn wait_for(path: &Path) -> io::Result<UnixStream> {
+    loop {
+        match UnixStream::connect(path) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound
+                || e.kind() == io::ErrorKind::ConnectionRefused => thread::sleep(RETRY_PERIOD),
+            result => return result,
+        }
+    }
+}
+
+fn greet(conn: &mut Connection) -> io::Result<()> {
+    let stream = match conn.channel {
+        Channel::Socket(ref stream) => stream,
+        _ => return Ok(()),
+    };
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    conn.queue(hello().as_bytes());
+    conn.flush()?;
+
+    // Anything after the greeting stays in the decoder
+    let mut chunk = [0u8; CHUNK_SIZE];
+    let token = loop {
+        if let Some(token) = conn.decoder.next_token() { break token; }
+        match conn.channel.read(&mut chunk) {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                "pt-sound hung up during handshake")),
+            Ok(n) => conn.decoder.feed(&chunk[..n]),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    };
+
+    if let Err(reason) = check_hello(&token) {
+        return Err(match token.parse::<Action>() {
+            Ok(Action::Error(message)) => io::Error::new(io::ErrorKind::ConnectionRefused, message),
+            _ => io::Error::new(io::ErrorKind::InvalidData, reason),
+        });
+    }
+
+    if let Channel::Socket(ref stream) = conn.channel {
+        stream.set_read_timeout(None)?;
+        stream.set_nonblocking(true)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,184 @@
+extern crate libcommon;
+
+use libcommon::{Action, Anchor, Module, Note, ProtocolError, ProtocolReason};
+
+// Strings which would break the stream unless every delimiter is escaped
+const HARD: &str = "100% take 2: final@home\nmix\r\tB %41";
+
+fn every_action() -> Vec<Action> {
+    let note = Note { id: 4, r_id: 1, t_in: 100, t_out: 200, note: 64, vel: 0.25 };
+    let anchor = |index: u16, input: bool| Anchor {
+        index,
+        module_id: 8,
+        name: HARD.to_string(),
+        input,
+    };
+    vec![
+        Action::Up,
+        Action::Down,
+        Action::Left,
+        Action::Right,
+        Action::SelectR,
+        Action::SelectB,
+        Action::SelectG,
+        Action::SelectY,
+        Action::SelectP,
+        Action::MidiEvent,
+        Action::RotaryEvent,
+        Action::Modules,
+        Action::Project,
+        Action::Undo,
+        Action::Redo,
+        Action::Shift,
+        Action::Back,
+        Action::Play,
+        Action::Stop,
+        Action::In,
+        Action::Out,
+        Action::Edit,
+        Action::PitchUp,
+        Action::PitchDown,
+        Action::VolumeUp,
+        Action::VolumeDown,
+        Action::Help,
+        Action::Deselect,
+        Action::Record,
+        Action::Route,
+        Action::Cancel,
+        Action::Close,
+        Action::At(3, Box::new(Action::SaveAs(HARD.to_string()))),
+        Action::At(1, Box::new(Action::At(2, Box::new(Action::Play)))),
+        Action::NoteOn(60, 0.5),
+        Action::NoteOff(60),
+        Action::Goto(48000),
+        Action::Tick,
+        Action::Octave(true),
+        Action::Volume(false),
+        Action::SetTempo(127),
+        Action::AddNote(2, note),
+        Action::Scrub(true),
+        Action::SetLoop(10, 20),
+        Action::LoopMode(true),
+        Action::LoopOff,
+        Action::Loop(30, 40),
+        Action::AddModule(5, HARD.to_string()),
+        Action::TryoutModule(5),
+        Action::DelModule(5),
+        Action::OpenProject(HARD.to_string()),
+        Action::ShowProject(HARD.to_string(), vec![
+            Module { id: 1, name: "tape".to_string() },
+            Module { id: 2, name: HARD.to_string() },
+        ]),
+        Action::ShowProject(String::new(), vec![]),
+        Action::InputTitle,
+        Action::CreateProject(HARD.to_string()),
+        Action::SetParam(HARD.to_string(), -0.125),
+        Action::DeclareParam(HARD.to_string(), 0.5, -1.0, 1.0, 0.01),
+        Action::DeclareAnchors(2, 3),
+        Action::SoloTrack(1, true),
+        Action::MuteTrack(1, false),
+        Action::MonitorTrack(1, true),
+        Action::RecordTrack(1, 2),
+        Action::SetMeter(3, 4),
+        Action::ShowAnchors(vec![anchor(0, false), anchor(1, true)]),
+        Action::PatchAnchor(9),
+        Action::PatchRoute(9),
+        Action::AddRoute(9),
+        Action::DelRoute(9),
+        Action::FadePatch(9, 0.75),
+        Action::PatchOut(8, 0, 2),
+        Action::PatchIn(8, 1, 2),
+        Action::DelPatch(8, 1, true),
+        Action::Zoom(4),
+        Action::AddRegion(1, 2, 3, 48000, 96000, 0, HARD.to_string()),
+        Action::AddMidiRegion(1, 2, 48000, 96000),
+        Action::MoveRegion(1, 2, 100),
+        Action::DelRegion(1, 2),
+        Action::SplitRegion(1, 2, 50),
+        Action::LoopRegion(1, 2),
+        Action::AddTrack(7),
+        Action::Save,
+        Action::SaveAs(" ".to_string()),
+        Action::Collect(HARD.to_string(), true),
+        Action::Bounce(HARD.to_string(), true, false),
+        Action::BounceProgress(48000, 96000),
+        Action::SetSampleRate(44100),
+        Action::AudioStatus(48000, 256, 1024, 512),
+        Action::Glitches(3, 7),
+        Action::PatchRefused(8, 1, 2, HARD.to_string()),
+        Action::SetMasterGain(-6.5),
+        Action::MasterLevels(0.5, 0.25, 0.125, 0.0625, -3.0),
+        Action::SetTrackGain(1, -12.0),
+        Action::SetTrackPan(1, -0.5),
+        Action::RouteLevel(9, 0.75),
+        Action::AnchorLevel(8, 1, 0.5),
+        Action::ControlChange(7, 127),
+        Action::PitchBend(-0.5),
+        Action::ProgramChange(12),
+        Action::SetDevice(HARD.to_string()),
+        Action::SetDevice(String::new()),
+        Action::ListMidiPorts,
+        Action::MidiPorts(vec![HARD.to_string(), "Keystation 49".to_string()]),
+        Action::MidiPorts(vec![]),
+        Action::Learn,
+        Action::LearnParam(HARD.to_string(), -1.0, 1.0),
+        Action::BindParam(74, HARD.to_string(), 0.0, 0.5),
+        Action::ForgetParam(HARD.to_string()),
+        Action::Noop,
+        Action::Error(HARD.to_string()),
+        Action::Exit,
+    ]
+}
+
+#[test]
+fn every_variant_round_trips() {
+    for action in every_action() {
+        let encoded = action.to_string();
+        let parsed: Action = encoded.parse().unwrap();
+        assert_eq!(parsed, action, "{:?}", encoded);
+    }
+}
+
+#[test]
+fn encoded_actions_are_single_tokens() {
+    for action in every_action() {
+        let encoded = action.to_string();
+        let token = encoded.strip_suffix(' ').unwrap();
+        assert!(!token.contains(|c: char| c.is_whitespace()), "{:?}", encoded);
+    }
+}
+
+#[test]
+fn hard_strings_are_escaped() {
+    let encoded = Action::SaveAs(HARD.to_string()).to_string();
+    assert_eq!(encoded, "SAVE_AS:100%25%20take%202%3A%20final%40home%0Amix%0D%09B%20%2541 ");
+    assert_eq!(libcommon::action::unescape(&libcommon::action::escape(HARD)).unwrap(), HARD);
+}
+
+#[test]
+fn stream_splits_on_delimiter() {
+    let actions = every_action();
+    let stream: String = actions.iter().map(|a| a.to_string()).collect();
+    let parsed: Vec<Action> = stream.split(' ')
+        .filter(|token| !token.is_empty())
+        .map(|token| token.parse().unwrap())
+        .collect();
+    assert_eq!(parsed, actions);
+}
+
+#[test]
+fn list_lengths_must_fit_the_args() {
+    let huge = "18446744073709551615";
+    for verb in ["MIDI_PORTS", "SHOW_ANCHORS"].iter() {
+        let err = format!("{}:{}", verb, huge).parse::<Action>().unwrap_err();
+        assert_eq!(err, ProtocolError {
+            verb: verb.to_string(),
+            index: 1,
+            reason: ProtocolReason::InvalidArgument(huge.to_string()),
+        });
+    }
+    let err = "SHOW_PROJECT:x:4000000000000000:1:tape".parse::<Action>().unwrap_err();
+    assert_eq!(err.index, 2);
+    assert!("MIDI_PORTS:2:a".parse::<Action>().is_err());
+    assert!("MIDI_PORTS:1:a:b".parse::<Action>().is_err());
+}
@@ -0,0 +1,124 @@
+extern crate libcommon;
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use libcommon::{collect, read_document, write_document, ModuleDoc, Document};
+
+const PROJECT: &str = r#"
+<project>
+    <meta samplerate="48000" version="1" />
+    <title>Moved</title>
+    <modules>
+        <timeline id="1">
+            <asset id="1001" size="336000" src="/home/someone/else/Loop.wav" />
+            <asset id="1002" size="336000" src="./assets/Nowhere.wav" />
+            <track id="1">
+                <audio id="1" asset="1" in="0" duration="336000" offset="0" />
+            </track>
+        </timeline>
+    </modules>
+</project>
+"#;
+
+// A project in its own folder whose assets folder holds Loop.wav
+fn moved_project(name: &str) -> String {
+    let dir = format!("target/pt-test-{}-{}", name, std::process::id());
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(format!("{}/assets", dir)).unwrap();
+    fs::copy("../storage/assets/Loop.wav", format!("{}/assets/Loop.wav", dir)).unwrap();
+    fs::write(format!("{}/moved.xml", dir), PROJECT).unwrap();
+    dir
+}
+
+fn srcs(doc: &Document) -> Vec<String> {
+    doc.modules.iter().flat_map(|(_, m)| match m {
+        ModuleDoc::Timeline(t) => t.assets.iter().map(|a| a.src.clone()).collect(),
+        _ => vec![],
+    }).collect()
+}
+
+#[test]
+fn missing_assets_are_relinked() {
+    let dir = moved_project("relink");
+    let doc = read_document(format!("{}/moved.xml", dir)).unwrap();
+
+    assert_eq!(doc.relinks.len(), 2);
+    assert_eq!(doc.relinks[0].asset, 1001);
+    assert!(Path::new(doc.relinks[0].to.as_ref().unwrap()).exists());
+    assert_eq!(doc.relinks[1].to, None);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn bundles_are_self_contained() {
+    let dir = moved_project("collect");
+    let mut doc = read_document(format!("{}/moved.xml", dir)).unwrap();
+    // Bundling fails while anything is missing
+    assert!(collect(&doc, Path::new(&format!("{}/song", dir)), false).is_err());
+
+    if let ModuleDoc::Timeline(ref mut t) = doc.modules[0].1 {
+        t.assets.retain(|a| a.id == 1001);
+    }
+    let bundle = collect(&doc, Path::new(&format!("{}/song", dir)), false).unwrap();
+    assert!(bundle.join("assets/Loop.wav").exists());
+    let xml = fs::read_to_string(bundle.join("song.xml")).unwrap();
+    assert!(xml.contains(r#"src="assets/Loop.wav""#), "{}", xml);
+
+    let bundled = read_document(format!("{}/song/song.xml", dir)).unwrap();
+    assert!(bundled.relinks.is_empty());
+    assert!(srcs(&bundled).iter().all(|src| Path::new(src).exists()));
+
+    let archive = collect(&doc, Path::new(&format!("{}/song", dir)), true).unwrap();
+    let listing = Command::new("tar").arg("-tf").arg(&archive).output().unwrap();
+    assert!(listing.status.success());
+    assert_eq!(String::from_utf8_lossy(&listing.stdout).lines().collect::<Vec<_>>(),
+        vec!["song/song.xml", "song/assets/Loop.wav"]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn archive_keeps_dotted_names() {
+    let dir = moved_project("dotted");
+    let mut doc = read_document(format!("{}/moved.xml", dir)).unwrap();
+    if let ModuleDoc::Timeline(ref mut t) = doc.modules[0].1 {
+        t.assets.retain(|a| a.id == 1001);
+    }
+    let archive = collect(&doc, Path::new(&format!("{}/song v1.2", dir)), true).unwrap();
+    assert_eq!(archive, Path::new(&format!("{}/song v1.2.tar", dir)));
+    assert!(archive.exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn archive_keeps_long_names() {
+    let dir = moved_project("long");
+    let mut doc = read_document(format!("{}/moved.xml", dir)).unwrap();
+    if let ModuleDoc::Timeline(ref mut t) = doc.modules[0].1 {
+        t.assets.retain(|a| a.id == 1001);
+    }
+    let name = "a song with a title long enough to overflow a tar header";
+    let archive = collect(&doc, Path::new(&format!("{}/{}", dir, name)), true).unwrap();
+    let listing = Command::new("tar").arg("-tf").arg(&archive).output().unwrap();
+    assert!(listing.status.success());
+    assert_eq!(String::from_utf8_lossy(&listing.stdout).lines().collect::<Vec<_>>(),
+        vec![format!("{}/{}.xml", name, name), format!("{}/assets/Loop.wav", name)]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn absolute_srcs_are_saved_relative() {
+    let dir = moved_project("absolute");
+    let mut doc = read_document(format!("{}/moved.xml", dir)).unwrap();
+    if let ModuleDoc::Timeline(ref mut t) = doc.modules[0].1 {
+        t.assets.retain(|a| a.id == 1001);
+        let src = fs::canonicalize(&t.assets[0].src).unwrap();
+        t.assets[0].src = src.to_string_lossy().to_string();
+    }
+    doc.src = format!("{}/saved", dir);
+    write_document(&doc).unwrap();
+    let xml = fs::read_to_string(format!("{}/saved.xml", dir)).unwrap();
+    assert!(xml.contains(r#"src="assets/Loop.wav""#), "{}", xml);
+    fs::remove_dir_all(&dir).unwrap();
+}
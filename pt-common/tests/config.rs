@@ -0,0 +1,118 @@
+extern crate libcommon;
+
+use std::path::PathBuf;
+
+use libcommon::{Action, Config};
+use libcommon::config::{ConfigError, KeyTarget, ResampleQuality};
+
+fn args(list: &[&str]) -> Vec<String> {
+    list.iter().map(|a| a.to_string()).collect()
+}
+
+#[test]
+fn file_settings() {
+    let mut config = Config::default();
+    config.parse("
+        # Studio laptop
+        projects = /srv/palit/
+        audio.card = PCH
+        audio.rate = 44100   # the card can't do 48k
+        midi.inputs = Keystation, nanoKONTROL2
+        ipc.socket = /tmp/palit.sock
+    ", "test").unwrap();
+
+    assert_eq!(config.projects, PathBuf::from("/srv/palit/"));
+    assert_eq!(config.audio_card, "PCH");
+    assert_eq!(config.sample_rate, 44100);
+    assert_eq!(config.buffer_size, Config::default().buffer_size);
+    assert_eq!(config.period_size, Config::default().period_size);
+    assert_eq!(config.midi_inputs, vec!["Keystation", "nanoKONTROL2"]);
+    assert_eq!(config.transport.socket, Some(PathBuf::from("/tmp/palit.sock")));
+}
+
+#[test]
+fn command_line_wins() {
+    let mut config = Config::default();
+    config.parse("audio.rate = 44100\naudio.buffer = 512\naudio.period = 128\naudio.dither = on\naudio.resample = fast", "test").unwrap();
+    config.apply_args(&args(&["pt-sound", "--audio.rate=96000", "--audio.buffer", "128", "--audio.dither=off", "--audio.resample=best"])).unwrap();
+    assert_eq!((config.sample_rate, config.buffer_size, config.dither), (96000, 128, false));
+    assert_eq!(config.resample, ResampleQuality::Best);
+    assert_eq!(config.period_size, 128);
+}
+
+#[test]
+fn positional_arguments() {
+    let mut config = Config::default();
+    config.apply_args(&args(&["pt-client", "PCH", "44100", "256", "--audio.period=64"])).unwrap();
+    assert_eq!(config.audio_card, "PCH");
+    assert_eq!((config.sample_rate, config.buffer_size, config.period_size), (44100, 256, 64));
+
+    let mut config = Config::default();
+    config.apply_args(&args(&["pt-input", "USB", "--config", "studio.conf", "96000"])).unwrap();
+    assert_eq!(config.audio_card, "USB");
+    assert_eq!(config.sample_rate, Config::default().sample_rate);
+
+    match config.apply_args(&args(&["pt-sound", "PCH", "fast"])) {
+        Err(e) => assert_eq!(e.to_string(), "command line: audio.rate: expected a number, found \"fast\""),
+        r => panic!("expected error, got {:?}", r),
+    }
+}
+
+#[test]
+fn comments_need_whitespace() {
+    let mut config = Config::default();
+    config.parse("
+        #projects = /nowhere/
+        audio.card = hw:CARD#2 # the second one
+        midi.inputs = Keys#1,Pads #1
+        ipc.socket = /tmp/palit#a.sock#not a comment
+    ", "test").unwrap();
+    assert_eq!(config.projects, Config::default().projects);
+    assert_eq!(config.audio_card, "hw:CARD#2");
+    assert_eq!(config.midi_inputs, vec!["Keys#1", "Pads"]);
+    assert_eq!(config.transport.socket, Some(PathBuf::from("/tmp/palit#a.sock#not a comment")));
+}
+
+#[test]
+fn keymap() {
+    let mut config = Config::default();
+    let a = config.binding(KeyTarget::Sound, "KEY_A").unwrap();
+    assert_eq!((&a.press[..], &a.release[..]), ("NOTE_ON:60:1", "NOTE_OFF:60"));
+
+    config.parse("
+        keys.sound.KEY_A = NOTE_ON:48:1 / NOTE_OFF:48
+        keys.client.KEY_UP =
+        keys.client.KEY_F1 = SAVE
+    ", "test").unwrap();
+    assert_eq!(config.binding(KeyTarget::Sound, "KEY_A").unwrap().press, "NOTE_ON:48:1");
+    assert!(config.binding(KeyTarget::Client, "KEY_UP").is_none());
+    assert_eq!(config.binding(KeyTarget::Client, "KEY_F1").unwrap().release, "");
+}
+
+#[test]
+fn default_bindings_are_actions() {
+    for binding in Config::default().keymap.iter() {
+        for token in binding.press.split_whitespace().chain(binding.release.split_whitespace()) {
+            assert!(token.parse::<Action>().is_ok(), "{} sends {}", binding.key, token);
+        }
+    }
+}
+
+#[test]
+fn errors_name_the_line() {
+    let mut config = Config::default();
+    let errors = [
+        ("audio.rate = fast", "test:1: audio.rate: expected a number, found \"fast\""),
+        ("\nvolume = 11", "test:2: volume: unknown setting"),
+        ("audio.resample = perfect", "test:1: audio.resample: expected fast, good or best, found \"perfect\""),
+        ("projects", "test:1: projects: expected key = value"),
+        ("keys.midi.KEY_A = PLAY", "test:1: keys.midi.KEY_A: expected keys.client.<KEY> or keys.sound.<KEY>"),
+        ("keys.client.KEY_1 = PLAY / 1", "test:1: keys.client.KEY_1: unknown action \"1\""),
+    ];
+    for (text, message) in errors.iter() {
+        match config.parse(text, "test") {
+            Err(e @ ConfigError::Invalid { .. }) => assert_eq!(e.to_string(), *message),
+            r => panic!("expected error for {:?}, got {:?}", text, r),
+        }
+    }
+}
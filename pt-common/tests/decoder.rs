@@ -0,0 +1,188 @@
+extern crate libcommon;
+
+use std::io::{self, Read};
+use libcommon::{Action, Anchor, Decoder, Note, ProtocolError, ProtocolReason};
+
+fn actions() -> Vec<Action> {
+    vec![
+        Action::Play,
+        Action::NoteOn(60, 0.5),
+        Action::AddRegion(1, 2, 3, 48000, 96000, 0, "./assets/my take: 1.wav".to_string()),
+        Action::AddNote(2, Note { id: 4, r_id: 1, t_in: 100, t_out: 200, note: 64, vel: 0.25 }),
+        Action::At(8, Box::new(Action::SaveAs("Song Garden ♫".to_string()))),
+        Action::ShowAnchors(vec![Anchor {
+            index: 0,
+            module_id: 8,
+            name: "Track 1".to_string(),
+            input: false,
+        }]),
+        Action::PatchRefused(8, 0, 2, "Track 1 to route 2: would make a cycle".to_string()),
+        Action::AnchorLevel(8, 1, 0.5),
+        Action::At(3, Box::new(Action::SetDevice("Launchkey MIDI 1".to_string()))),
+        Action::MidiPorts(vec!["Midi Through Port-0".to_string(), "Launchkey: 1".to_string()]),
+        Action::ControlChange(74, 127),
+        Action::At(5, Box::new(Action::BindParam(74, "5.3".to_string(), 0.0, 0.5))),
+        Action::Stop,
+    ]
+}
+
+fn encode(actions: &[Action]) -> Vec<u8> {
+    actions.iter().map(|a| a.to_string()).collect::<String>().into_bytes()
+}
+
+fn decode_chunks(bytes: &[u8], boundaries: &[usize]) -> Vec<Action> {
+    let mut decoder = Decoder::new();
+    let mut decoded = vec![];
+    let mut start = 0;
+    for end in boundaries.iter().chain(Some(&bytes.len())) {
+        decoder.feed(&bytes[start..*end]);
+        start = *end;
+        for action in &mut decoder {
+            decoded.push(action.unwrap());
+        }
+    }
+    assert_eq!(decoder.pending(), 0);
+    decoded
+}
+
+#[test]
+fn every_chunk_size() {
+    let expected = actions();
+    let bytes = encode(&expected);
+    for size in 1..=bytes.len() {
+        let boundaries: Vec<usize> = (1..).map(|i| i * size)
+            .take_while(|b| *b < bytes.len()).collect();
+        assert_eq!(decode_chunks(&bytes, &boundaries), expected, "chunk size {}", size);
+    }
+}
+
+#[test]
+fn arbitrary_boundaries() {
+    let expected = actions();
+    let bytes = encode(&expected);
+    // Deterministic pseudo-random cuts
+    let mut seed: u32 = 2020;
+    for _ in 0..500 {
+        let mut boundaries = vec![];
+        let mut cut = 0;
+        loop {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            cut += (seed >> 16) as usize % 24;
+            if cut >= bytes.len() { break; }
+            boundaries.push(cut);
+        }
+        assert_eq!(decode_chunks(&bytes, &boundaries), expected);
+    }
+}
+
+#[test]
+fn hard_escapes_round_trip() {
+    let expected = vec![
+        Action::SaveAs("100% take 2: final@home\nmix\r\tB".to_string()),
+        Action::At(3, Box::new(Action::At(7, Box::new(
+            Action::SetDevice("a:b@c %d\ne".to_string()))))),
+        Action::At(1, Box::new(Action::At(2, Box::new(Action::Play)))),
+        Action::SaveAs("%%25@@::  ".to_string()),
+    ];
+    let bytes = encode(&expected);
+    assert!(!bytes.contains(&b'\n'));
+    let boundaries: Vec<usize> = (1..bytes.len()).collect();
+    assert_eq!(decode_chunks(&bytes, &boundaries), expected);
+    assert_eq!(decode_chunks(&bytes, &[]), expected);
+}
+
+#[test]
+fn incomplete_action_is_kept() {
+    let mut decoder = Decoder::new();
+    decoder.feed(b"PLAY REGION_ADD:1:2:3:4");
+    assert_eq!(decoder.next(), Some(Ok(Action::Play)));
+    assert_eq!(decoder.next(), None);
+    decoder.feed(b":5:6:a.wav STOP ");
+    assert_eq!(decoder.next(), Some(Ok(Action::AddRegion(1, 2, 3, 4, 5, 6, "a.wav".to_string()))));
+    assert_eq!(decoder.next(), Some(Ok(Action::Stop)));
+    assert_eq!(decoder.next(), None);
+}
+
+#[test]
+fn malformed_action_is_skipped() {
+    let mut decoder = Decoder::new();
+    decoder.feed(b"NOTE_ON:abc  PLAY ");
+    assert!(decoder.next().unwrap().is_err());
+    assert_eq!(decoder.next(), Some(Ok(Action::Play)));
+}
+
+fn refused(token: &str) -> ProtocolError {
+    let mut decoder = Decoder::new();
+    decoder.feed(format!("{} ", token).as_bytes());
+    decoder.next().unwrap().unwrap_err()
+}
+
+#[test]
+fn errors_name_verb_argument_and_reason() {
+    let error = |verb: &str, index, reason| ProtocolError { verb: verb.to_string(), index, reason };
+    assert_eq!(refused("NOTE_ON:60:loud"),
+        error("NOTE_ON", 2, ProtocolReason::InvalidArgument("loud".to_string())));
+    assert_eq!(refused("NOTE_ON:60"), error("NOTE_ON", 2, ProtocolReason::MissingArgument));
+    assert_eq!(refused("PLAY:1"), error("PLAY", 1, ProtocolReason::UnexpectedArgument("1".to_string())));
+    assert_eq!(refused("SAVE_AS:50%"), error("SAVE_AS", 1, ProtocolReason::InvalidEscape("50%".to_string())));
+    assert_eq!(refused("NOPE:1"), error("NOPE", 0, ProtocolReason::UnknownVerb));
+    assert_eq!(refused("x@PLAY"), error("x@PLAY", 0, ProtocolReason::InvalidArgument("x".to_string())));
+    assert_eq!(refused("NOTE_ON:60:loud").to_string(), "NOTE_ON argument 2: invalid value \"loud\"");
+}
+
+// Hands out a few bytes per read, then blocks like an empty fifo
+struct Trickle<'a> {
+    bytes: &'a [u8],
+    step: usize,
+}
+
+impl<'a> Read for Trickle<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.bytes.is_empty() {
+            return Err(io::Error::from(io::ErrorKind::WouldBlock));
+        }
+        let n = self.step.min(self.bytes.len()).min(buf.len());
+        buf[..n].copy_from_slice(&self.bytes[..n]);
+        self.bytes = &self.bytes[n..];
+        Ok(n)
+    }
+}
+
+#[test]
+fn read_until_would_block() {
+    let expected = actions();
+    let bytes = encode(&expected);
+    let (head, tail) = bytes.split_at(bytes.len() / 2);
+    let mut decoder = Decoder::new();
+
+    assert_eq!(decoder.read_from(Trickle { bytes: head, step: 3 }).unwrap(), head.len());
+    let mut decoded: Vec<Action> = (&mut decoder).map(|a| a.unwrap()).collect();
+    assert!(decoded.len() < expected.len());
+
+    assert_eq!(decoder.read_from(Trickle { bytes: tail, step: 7 }).unwrap(), tail.len());
+    decoded.extend((&mut decoder).map(|a| a.unwrap()));
+    assert_eq!(decoded, expected);
+}
+
+#[test]
+fn endless_tokens_are_dropped() {
+    let max = libcommon::decoder::MAX_TOKEN;
+    let mut decoder = Decoder::new();
+    decoder.feed(b"PLAY SAVE_AS:");
+    let junk = vec![b'a'; 4096];
+    for _ in 0..(max / junk.len() + 4) {
+        decoder.feed(&junk);
+    }
+    assert!(decoder.pending() <= max);
+    decoder.feed(b"aaa STOP ");
+
+    assert_eq!(decoder.next(), Some(Ok(Action::Play)));
+    assert_eq!(decoder.next(), Some(Err(ProtocolError {
+        verb: "SAVE_AS".to_string(),
+        index: 0,
+        reason: ProtocolReason::TooLong(max),
+    })));
+    assert_eq!(decoder.next(), Some(Ok(Action::Stop)));
+    assert_eq!(decoder.next(), None);
+    assert_eq!(decoder.pending(), 0);
+}
@@ -0,0 +1,96 @@
+extern crate libcommon;
+extern crate xmltree;
+
+use std::fs;
+use xmltree::Element;
+use libcommon::{read_document, read_header, validate_document, param_map, note_list, DocumentError, Problem};
+
+const BROKEN: &str = r#"
+<project>
+    <title>Broken</title>
+    <meta samplerate="fast" bitdepth="20" />
+    <modules>
+        <timeline id="3">
+            <param name="tempo" value="loud" />
+            <track>
+                <note id="1" key="60" t_in="0" t_out="100" />
+            </track>
+        </timeline>
+        <hammond id="3" />
+        <keyboard />
+    </modules>
+</project>
+"#;
+
+#[test]
+fn shipped_projects_load() {
+    for entry in fs::read_dir("../storage").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|e| e == "xml") {
+            let name = format!("../storage/{}", path.file_name().unwrap().to_str().unwrap());
+            if let Err(e) = read_document(name.clone()) {
+                panic!("{}: {}", name, e);
+            }
+        }
+    }
+}
+
+#[test]
+fn every_problem_is_reported() {
+    let doc = Element::parse(BROKEN.as_bytes()).unwrap();
+    let problems: Vec<String> = validate_document(&doc).iter().map(Problem::to_string).collect();
+    assert_eq!(problems, vec![
+        r#"/project/meta@samplerate: expected u32, found "fast""#,
+        r#"/project/meta@bitdepth: expected 16, 24 or 32, found "20""#,
+        r#"/project/modules/timeline[@id=3]/param[@name=tempo]@value: expected f32, found "loud""#,
+        "/project/modules/timeline[@id=3]/track[1]/note[@id=1]@vel: missing f64",
+        r#"/project/modules/hammond[@id=3]@id: expected unique module id, found "3""#,
+        "/project/modules/keyboard[1]@id: missing u16",
+    ]);
+}
+
+#[test]
+fn missing_modules() {
+    let doc = Element::parse("<project><title>Empty</title></project>".as_bytes()).unwrap();
+    let problems = validate_document(&doc);
+    assert_eq!(problems.len(), 1);
+    assert_eq!(problems[0].path, "/project");
+    assert_eq!(problems[0].expected, "modules element");
+}
+
+#[test]
+fn headers_are_read_without_validating() {
+    let path = std::env::temp_dir().join(format!("pt-test-header-{}.xml", std::process::id()));
+    fs::write(&path, BROKEN).unwrap();
+    let name = path.to_string_lossy().to_string();
+    let header = read_header(name.clone());
+    let opened = read_document(name);
+    let _ = fs::remove_file(&path);
+
+    let header = header.unwrap();
+    assert_eq!(header.title, "Broken");
+    assert_eq!(header.sample_rate, 48000);
+    assert_eq!(header.bit_depth, 20);
+    assert!(matches!(opened, Err(DocumentError::Invalid(_))));
+}
+
+#[test]
+fn load_errors() {
+    match read_document("../storage/nonexistent.xml".to_string()) {
+        Err(DocumentError::Io(_)) => {},
+        r => panic!("expected io error, got {:?}", r),
+    }
+}
+
+#[test]
+fn malformed_entries_are_skipped() {
+    let mut doc = Element::parse(BROKEN.as_bytes()).unwrap();
+    let mut timeline = doc.get_mut_child("modules").unwrap()
+        .get_mut_child("timeline").unwrap().clone();
+    let (_, params) = param_map(&mut timeline);
+    assert!(params.is_empty());
+
+    let mut track = timeline.get_mut_child("track").unwrap().clone();
+    let (_, notes) = note_list(&mut track, 0);
+    assert!(notes.is_empty());
+}
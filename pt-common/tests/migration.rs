@@ -0,0 +1,76 @@
+extern crate libcommon;
+extern crate xmltree;
+
+use std::fs;
+use xmltree::Element;
+
+use libcommon::{read_document, write_document, ModuleDoc, DocumentError, FORMAT_VERSION};
+use libcommon::migration::{migrate, format_version};
+
+fn seq_marks(doc: &libcommon::Document) -> (u32, u32) {
+    doc.modules.iter().find_map(|(_, m)| match m {
+        ModuleDoc::Timeline(t) => Some((t.seq_in, t.seq_out)),
+        _ => None,
+    }).unwrap()
+}
+
+#[test]
+fn unversioned_projects_are_upgraded() {
+    let doc = read_document("../storage/timeline.xml".to_string()).unwrap();
+    assert_eq!(doc.migrations.len(), FORMAT_VERSION as usize);
+    assert_eq!(doc.migrations[0].from, 0);
+    assert_eq!(seq_marks(&doc), (0, 68000));
+}
+
+#[test]
+fn saved_projects_need_no_migration() {
+    let mut doc = read_document("../storage/timeline.xml".to_string()).unwrap();
+    // Documents are relative to the projects directory
+    doc.src = format!("target/pt-test-migrated-{}", std::process::id());
+    write_document(&doc).unwrap();
+
+    let saved = read_document(format!("{}.xml", doc.src)).unwrap();
+    assert!(saved.migrations.is_empty());
+    assert_eq!(seq_marks(&saved), (0, 68000));
+    fs::remove_file(format!("{}.xml", doc.src)).unwrap();
+}
+
+#[test]
+fn seq_out_is_kept_when_set() {
+    let mut doc = Element::parse(r#"
+        <project>
+            <modules>
+                <timeline id="1">
+                    <mark name="seq_in" value="100" />
+                    <mark name="seq_out" value="500" />
+                    <mark name="duration" value="9000" />
+                </timeline>
+            </modules>
+        </project>
+    "#.as_bytes()).unwrap();
+    assert_eq!(format_version(&doc), Some(0));
+    let applied = migrate(&mut doc, 0);
+    assert_eq!(applied.len(), 1);
+
+    let timeline = doc.get_child("modules").unwrap().get_child("timeline").unwrap();
+    let marks: Vec<(&str, &str)> = timeline.children.iter().map(|m| (
+        &m.attributes["name"][..], &m.attributes["value"][..]
+    )).collect();
+    assert_eq!(marks, vec![("seq_in", "100"), ("seq_out", "500")]);
+}
+
+#[test]
+fn newer_projects_are_refused() {
+    let path = format!("target/pt-test-future-{}.xml", std::process::id());
+    fs::write(&path, r#"
+        <project>
+            <meta samplerate="48000" version="999" />
+            <modules />
+        </project>
+    "#).unwrap();
+    match read_document(path.clone()) {
+        Err(DocumentError::Version(999)) => {},
+        r => panic!("expected version error, got {:?}", r),
+    }
+    fs::remove_file(&path).unwrap();
+}
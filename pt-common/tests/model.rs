@@ -0,0 +1,116 @@
+extern crate libcommon;
+extern crate xmltree;
+
+use xmltree::Element;
+
+use libcommon::{read_document, read_module, write_module, ModuleDoc, InputDoc, KeyboardDoc, KEYBED};
+use libcommon::{BindingDoc, bind};
+
+#[test]
+fn shipped_projects_round_trip() {
+    let doc = read_document("../storage/one.xml".to_string()).unwrap();
+    for (_, module) in doc.modules.iter() {
+        assert_eq!(&read_module(&write_module(module)), module);
+    }
+}
+
+#[test]
+fn missing_values_use_defaults() {
+    let el = Element::parse(r#"
+        <hammond>
+            <param name="8" value="0.5" />
+            <param name="4" value="loud" />
+        </hammond>
+    "#.as_bytes()).unwrap();
+    match read_module(&el) {
+        ModuleDoc::Hammond(hammond) => {
+            assert_eq!(hammond.drawbars[2], 0.5);
+            assert_eq!(hammond.drawbars[3], 0.1);
+        },
+        m => panic!("unexpected {:?}", m),
+    }
+    assert_eq!(read_module(&Element::new("arpeggio")), ModuleDoc::new("arpeggio"));
+}
+
+#[test]
+fn input_anchors() {
+    let mut input = InputDoc { device: "USB".to_string(), channels: 3, stereo: false };
+    let names = |input: &InputDoc| input.anchors().into_iter().map(|(name, _)| name).collect::<Vec<_>>();
+    assert_eq!(names(&input), vec!["In 1", "In 2", "In 3"]);
+
+    input.stereo = true;
+    assert_eq!(names(&input), vec!["In 1-2", "In 3"]);
+    assert_eq!(input.anchors()[0].1, vec![0, 1]);
+
+    let module = ModuleDoc::Input(input);
+    assert_eq!(read_module(&write_module(&module)), module);
+}
+
+#[test]
+fn track_gain_and_pan() {
+    let el = Element::parse(r#"
+        <timeline>
+            <track id="1">
+                <param name="gain" value="-6.5" />
+                <param name="pan" value="0.25" />
+            </track>
+            <track id="2" />
+        </timeline>
+    "#.as_bytes()).unwrap();
+    let module = read_module(&el);
+    match &module {
+        ModuleDoc::Timeline(timeline) => {
+            assert_eq!((timeline.tracks[0].gain, timeline.tracks[0].pan), (-6.5, 0.25));
+            assert_eq!((timeline.tracks[1].gain, timeline.tracks[1].pan), (0.0, 0.0));
+        },
+        m => panic!("unexpected {:?}", m),
+    }
+    assert_eq!(read_module(&write_module(&module)), module);
+}
+
+#[test]
+fn keyboard_devices() {
+    assert_eq!(read_module(&Element::parse("<keyboard />".as_bytes()).unwrap()), ModuleDoc::new("keyboard"));
+    for device in [KEYBED, "Launchkey MIDI 1", ""].iter() {
+        let module = ModuleDoc::Keyboard(KeyboardDoc { device: device.to_string(), ..KeyboardDoc::default() });
+        assert_eq!(read_module(&write_module(&module)), module);
+    }
+}
+
+#[test]
+fn controller_bindings() {
+    let el = Element::parse(r#"
+        <hammond>
+            <cc number="74" param="16" min="0" max="0.5" />
+            <cc number="300" param="8" min="0" max="0.5" />
+        </hammond>
+    "#.as_bytes()).unwrap();
+    let module = read_module(&el);
+    let bindings = module.bindings().to_vec();
+    assert_eq!(bindings.len(), 1);
+    assert_eq!((bindings[0].scale(0), bindings[0].scale(127)), (0.0, 0.5));
+    assert_eq!(read_module(&write_module(&module)), module);
+
+    // Learning a bound controller or param again replaces it
+    let mut bindings = bindings;
+    bind(&mut bindings, BindingDoc { cc: 74, param: "8".to_string(), min: 0.0, max: 1.0 });
+    bind(&mut bindings, BindingDoc { cc: 1, param: "8".to_string(), min: 0.0, max: 1.0 });
+    assert_eq!(bindings.iter().map(|b| (b.cc, &b.param[..])).collect::<Vec<_>>(), vec![(1, "8")]);
+}
+
+#[test]
+fn out_of_range_asset_ids_find_nothing() {
+    let el = Element::parse(r#"
+        <timeline>
+            <asset id="65535" src="a.wav" size="10" channels="1" />
+        </timeline>
+    "#.as_bytes()).unwrap();
+    match read_module(&el) {
+        ModuleDoc::Timeline(timeline) => {
+            assert_eq!(timeline.asset(65, 535).map(|a| a.id), Some(65535));
+            assert_eq!(timeline.asset(65, 536), None);
+            assert_eq!(timeline.asset(66, 0), None);
+        },
+        m => panic!("unexpected {:?}", m),
+    }
+}
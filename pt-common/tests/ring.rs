@@ -0,0 +1,59 @@
+extern crate libcommon;
+
+use std::rc::Rc;
+use std::thread;
+use libcommon::ring::ring;
+
+#[test]
+fn full_ring_hands_values_back() {
+    let (mut tx, mut rx) = ring(2);
+    assert_eq!(tx.push(1), Ok(()));
+    assert_eq!(tx.push(2), Ok(()));
+    assert!(tx.is_full());
+    assert_eq!(tx.push(3), Err(3));
+    assert_eq!(rx.pop(), Some(1));
+    assert_eq!(tx.push(3), Ok(()));
+    assert_eq!(rx.pop(), Some(2));
+    assert_eq!(rx.pop(), Some(3));
+    assert_eq!(rx.pop(), None);
+    assert!(rx.is_empty());
+}
+
+#[test]
+fn queued_values_are_dropped_with_the_ring() {
+    let value = Rc::new(());
+    {
+        let (mut tx, mut rx) = ring(4);
+        for _ in 0..3 {
+            tx.push(value.clone()).unwrap();
+        }
+        rx.pop();
+        assert_eq!(Rc::strong_count(&value), 3);
+        drop(rx);
+        assert!(tx.is_abandoned());
+    }
+    assert_eq!(Rc::strong_count(&value), 1);
+}
+
+#[test]
+fn values_cross_threads_in_order() {
+    let (mut tx, mut rx) = ring::<u32>(8);
+    let producer = thread::spawn(move || {
+        for i in 0..100_000 {
+            let mut value = i;
+            while let Err(back) = tx.push(value) {
+                value = back;
+                thread::yield_now();
+            }
+        }
+    });
+    let mut next = 0;
+    while next < 100_000 {
+        match rx.pop() {
+            Some(value) => { assert_eq!(value, next); next += 1; },
+            None => thread::yield_now(),
+        }
+    }
+    producer.join().unwrap();
+    assert!(rx.is_abandoned());
+}
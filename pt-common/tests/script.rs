@@ -0,0 +1,42 @@
+extern crate libcommon;
+
+use libcommon::{read_script, Action};
+
+#[test]
+fn actions_in_frame_order() {
+    let script = read_script("
+        # Play a note for half a second
+        0 OPEN_PROJECT:one PLAY
+        48000 EXIT
+        24000   NOTE_OFF:60
+        0 NOTE_ON:60:1
+    ").unwrap();
+    assert_eq!(script, vec![
+        (0, Action::OpenProject("one".to_string())),
+        (0, Action::Play),
+        (0, Action::NoteOn(60, 1.0)),
+        (24000, Action::NoteOff(60)),
+        (48000, Action::Exit),
+    ]);
+}
+
+#[test]
+fn errors_name_the_line() {
+    let errors = [
+        ("soon PLAY", "line 1: expected a frame offset, found \"soon\""),
+        ("\n10", "line 2: no actions at 10"),
+        ("10 NOTE_ON:60", "line 1: NOTE_ON argument 2: missing"),
+    ];
+    for (text, message) in errors.iter() {
+        assert_eq!(read_script(text).unwrap_err().to_string(), *message);
+    }
+}
+
+#[test]
+fn hashes_inside_arguments_are_kept() {
+    let script = read_script("
+        #0 PLAY
+        0 OPEN_PROJECT:take#2 # the second take
+    ").unwrap();
+    assert_eq!(script, vec![(0, Action::OpenProject("take#2".to_string()))]);
+}
@@ -0,0 +1,196 @@
+extern crate libcommon;
+
+use std::env;
+use std::io::{Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+use libcommon::{Action, Event, Link, Server, Transport};
+
+fn socket(name: &str) -> Transport {
+    let path: PathBuf = env::temp_dir().join(format!("pt-test-{}-{}.sock", name, std::process::id()));
+    Transport {
+        socket: Some(path),
+        sound_fifo: PathBuf::from("/nonexistent/pt-sound"),
+        client_fifo: PathBuf::from("/nonexistent/pt-client"),
+    }
+}
+
+// Polls the server until it has produced n events
+fn wait_events(server: &mut Server, n: usize) -> Vec<Event> {
+    let mut events = vec![];
+    for _ in 0..200 {
+        events.extend(server.recv());
+        if events.len() >= n { break; }
+        thread::sleep(Duration::from_millis(5));
+    }
+    events
+}
+
+fn wait_actions(link: &mut Link, n: usize) -> Vec<Action> {
+    let mut actions = vec![];
+    for _ in 0..200 {
+        actions.extend(link.recv().unwrap().into_iter().map(|a| a.unwrap()));
+        if actions.len() >= n { break; }
+        thread::sleep(Duration::from_millis(5));
+    }
+    actions
+}
+
+// Links block on the handshake, so the server answers from another thread
+fn connect(server: &mut Server, transport: &Transport) -> Link {
+    let transport = transport.clone();
+    let pending = thread::spawn(move || Link::connect(&transport).unwrap());
+    let events = wait_events(server, 1);
+    assert!(matches!(events[..], [Event::Connected(_)]));
+    pending.join().unwrap()
+}
+
+#[test]
+fn many_clients() {
+    let transport = socket("many");
+    let mut server = Server::bind(&transport).unwrap();
+    let mut first = connect(&mut server, &transport);
+    let mut second = connect(&mut server, &transport);
+    assert_eq!(server.clients(), 2);
+
+    first.send(&Action::Play).unwrap();
+    second.send(&Action::NoteOn(60, 1.0)).unwrap();
+    let received: Vec<Action> = wait_events(&mut server, 2).into_iter().map(|e| match e {
+        Event::Received(_, a) => a.unwrap(),
+        e => panic!("unexpected {:?}", e),
+    }).collect();
+    assert!(received.contains(&Action::Play));
+    assert!(received.contains(&Action::NoteOn(60, 1.0)));
+
+    server.send(&Action::Goto(10));
+    assert_eq!(wait_actions(&mut first, 1), vec![Action::Goto(10)]);
+    assert_eq!(wait_actions(&mut second, 1), vec![Action::Goto(10)]);
+}
+
+#[test]
+fn disconnect() {
+    let transport = socket("disconnect");
+    let mut server = Server::bind(&transport).unwrap();
+    let link = connect(&mut server, &transport);
+    drop(link);
+    assert!(matches!(wait_events(&mut server, 1)[..], [Event::Disconnected(_)]));
+    assert_eq!(server.clients(), 0);
+
+    let mut link = connect(&mut server, &transport);
+    drop(server);
+    let mut result = link.recv();
+    for _ in 0..200 {
+        if result.is_err() { break; }
+        thread::sleep(Duration::from_millis(5));
+        result = link.recv();
+    }
+    assert!(result.is_err());
+}
+
+#[test]
+fn version_mismatch() {
+    let transport = socket("version");
+    let mut server = Server::bind(&transport).unwrap();
+    let mut stream = UnixStream::connect(transport.socket.as_ref().unwrap()).unwrap();
+    stream.write_all(b"PALIT:65535 ").unwrap();
+
+    assert!(wait_events(&mut server, 1).is_empty());
+    assert_eq!(server.clients(), 0);
+
+    let mut reply = String::new();
+    stream.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+    stream.read_to_string(&mut reply).unwrap();
+    assert!(reply.starts_with("ERROR:"), "{}", reply);
+}
+
+#[test]
+fn silent_clients_time_out() {
+    let transport = socket("silent");
+    let mut server = Server::bind(&transport).unwrap();
+    let mut stream = UnixStream::connect(transport.socket.as_ref().unwrap()).unwrap();
+
+    // Longer than the handshake may take
+    for _ in 0..3 {
+        assert!(wait_events(&mut server, 1).is_empty());
+    }
+
+    let mut reply = String::new();
+    stream.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+    stream.read_to_string(&mut reply).unwrap();
+    assert_eq!(reply, Action::Error("handshake timed out".to_string()).to_string());
+}
+
+#[test]
+fn stale_socket() {
+    let transport = socket("stale");
+    let path = transport.socket.clone().unwrap();
+    // A dropped listener leaves its file behind, as a crash would
+    drop(UnixListener::bind(&path).unwrap());
+    assert!(path.exists());
+
+    let server = Server::bind(&transport).unwrap();
+    assert!(Server::bind(&transport).is_err());
+    drop(server);
+    assert!(!path.exists());
+}
+
+fn fifos(name: &str) -> Transport {
+    let dir = env::temp_dir();
+    let transport = Transport {
+        socket: None,
+        sound_fifo: dir.join(format!("pt-test-{}-sound-{}", name, std::process::id())),
+        client_fifo: dir.join(format!("pt-test-{}-client-{}", name, std::process::id())),
+    };
+    for fifo in [&transport.sound_fifo, &transport.client_fifo].iter() {
+        let _ = std::fs::remove_file(fifo);
+        assert!(Command::new("mkfifo").arg(fifo).status().unwrap().success());
+    }
+    transport
+}
+
+// Both ends block until the other opens its pipe
+fn bind_fifos(transport: &Transport) -> (Server, Link) {
+    let sound_transport = transport.clone();
+    let pending = thread::spawn(move || Server::bind(&sound_transport).unwrap());
+    let link = Link::connect(transport).unwrap();
+    (pending.join().unwrap(), link)
+}
+
+#[test]
+fn fifo_mode() {
+    let transport = fifos("mode");
+    let (mut server, mut link) = bind_fifos(&transport);
+
+    link.send(&Action::Play).unwrap();
+    assert!(matches!(wait_events(&mut server, 1)[..], [Event::Received(_, Ok(Action::Play))]));
+    server.send(&Action::Goto(5));
+    assert_eq!(wait_actions(&mut link, 1), vec![Action::Goto(5)]);
+
+    drop(server);
+    assert!(link.recv().is_err());
+
+    let _ = std::fs::remove_file(&transport.sound_fifo);
+    let _ = std::fs::remove_file(&transport.client_fifo);
+}
+
+#[test]
+fn fifo_client_restarts() {
+    let transport = fifos("restart");
+    let (mut server, link) = bind_fifos(&transport);
+
+    // Nobody is reading, so this waits for the next client
+    drop(link);
+    server.send(&Action::Goto(1));
+    assert!(wait_events(&mut server, 1).is_empty());
+
+    let mut link = Link::connect(&transport).unwrap();
+    server.send(&Action::Goto(2));
+    assert_eq!(wait_actions(&mut link, 2), vec![Action::Goto(1), Action::Goto(2)]);
+
+    let _ = std::fs::remove_file(&transport.sound_fifo);
+    let _ = std::fs::remove_file(&transport.client_fifo);
+}
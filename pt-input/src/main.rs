@@ -1,6 +1,7 @@
 extern crate libc;
 extern crate termion;
 extern crate linux_raw_input_rs;
+extern crate libcommon;
 
 use termion::event::Key;
 use termion::input::TermRead;
@@ -13,11 +14,54 @@ use std::os::unix::fs::OpenOptionsExt;
 use std::ffi::CString;
 
 use linux_raw_input_rs::{InputReader, get_input_devices};
-use linux_raw_input_rs::keys::Keys;
 use linux_raw_input_rs::input::EventType;
 
+use libcommon::{Config, config};
+use libcommon::config::KeyTarget;
+
 // ACTION FORMAT: CMD1:PARAM1:PARAM2 CMD2:PARAM1 ...
 
+// Writes the actions bound to key, returns false once EXIT is sent
+// ... and fails when a program stops reading
+fn send(ipc_client: &mut File, ipc_sound: &mut File, key: &str, pressed: bool) -> std::io::Result<bool> {
+    let config = config();
+    let mut running = true;
+    for (target, ipc) in [(KeyTarget::Client, ipc_client), (KeyTarget::Sound, ipc_sound)] {
+        let actions = match config.binding(target, key) {
+            Some(binding) if pressed => &binding.press,
+            Some(binding) => &binding.release,
+            None => continue,
+        };
+        if actions.is_empty() { continue; }
+        ipc.write_all(format!("{} ", actions).as_bytes()).map_err(|e| std::io::Error::new(
+            e.kind(), format!("Could not send {} for {}: {}", actions, key, e)))?;
+        if actions.split_whitespace().any(|a| a == "EXIT") { running = false; }
+    }
+    Ok(running)
+}
+
+// Terminal keys named like the kernel's, so both platforms share a keymap
+#[cfg(target_os = "macos")]
+fn key_name(key: Key) -> Option<String> {
+    Some(match key {
+        Key::Up => "KEY_UP".to_string(),
+        Key::Down => "KEY_DOWN".to_string(),
+        Key::Left => "KEY_LEFT".to_string(),
+        Key::Right => "KEY_RIGHT".to_string(),
+        Key::Char('[') => "KEY_LEFTBRACE".to_string(),
+        Key::Char(']') => "KEY_RIGHTBRACE".to_string(),
+        Key::Char('\\') => "KEY_BACKSLASH".to_string(),
+        Key::Char(' ') => "KEY_SPACE".to_string(),
+        Key::Char('\n') => "KEY_ENTER".to_string(),
+        Key::Char('\t') => "KEY_TAB".to_string(),
+        Key::Char('-') => "KEY_MINUS".to_string(),
+        Key::Char('=') => "KEY_EQUAL".to_string(),
+        Key::Char(';') => "KEY_SEMICOLON".to_string(),
+        Key::Char(c) if c.is_ascii_alphanumeric() => format!("KEY_{}", c.to_ascii_uppercase()),
+        _ => return None,
+    })
+}
+
 #[cfg(target_os = "macos")]
 fn event_loop(mut ipc_client: File, mut ipc_sound: File) -> std::io::Result<()> {
 
@@ -32,92 +76,11 @@ fn event_loop(mut ipc_client: File, mut ipc_sound: File) -> std::io::Result<()>
 
     stdout.flush().unwrap();
 
+    // The terminal only reports presses
     for c in stdin.keys() {
-        let key = c.unwrap();
-
-        let client_buf: &str = match key.clone() {
-            Key::Up => "UP ",
-            Key::Down => "DN ",
-            Key::Left => "LT ",
-            Key::Right => "RT ",
-
-            Key::Char('q') => "EXIT ",
-            Key::Char('1') => "1 ",
-            Key::Char('2') => "2 ",
-
-            Key::Char('[') => "PLAY ",
-            Key::Char(']') => "STOP ",
-            Key::Char('\\') => "RECORD ",
-
-            Key::Char('m') => "M ",
-            Key::Char('r') => "R ",
-            Key::Char('v') => "V ",
-            Key::Char('i') => "I ",
-            Key::Char(' ') => "SPC ",
-
-            Key::Char('\n') => "DESELECT ",
-            Key::Char('\t') => "ROUTE ",
-
-            Key::Char('z') => "EFFECT ",
-            Key::Char('x') => "INSTRUMENT ",
-
-            Key::Char('-') => "OCTAVE:0 ",
-            Key::Char('=') => "OCTAVE:1 ",
-
-            /*
-            Key::Char('a') => "NOTE_ON:60:1 ",
-            Key::Char('w') => "NOTE_ON:61:1 ",
-            Key::Char('s') => "NOTE_ON:62:1 ",
-            Key::Char('e') => "NOTE_ON:63:1 ",
-            Key::Char('d') => "NOTE_ON:64:1 ",
-            Key::Char('f') => "NOTE_ON:65:1 ",
-            Key::Char('t') => "NOTE_ON:66:1 ",
-            Key::Char('g') => "NOTE_ON:67:1 ",
-            Key::Char('y') => "NOTE_ON:68:1 ",
-            Key::Char('h') => "NOTE_ON:69:1 ",
-            Key::Char('u') => "NOTE_ON:70:1 ",
-            Key::Char('j') => "NOTE_ON:71:1 ",
-            Key::Char('k') => "NOTE_ON:72:1 ",
-            Key::Char('o') => "NOTE_ON:73:1 ",
-            Key::Char('l') => "NOTE_ON:74:1 ",
-            Key::Char('p') => "NOTE_ON:75:1 ",
-            Key::Char(';') => "NOTE_ON:76:1 ",
-            */
-
-            _ => "",
-        };
-
-        let sound_buf: &str = match key.clone() {
-            Key::Char('q') => "EXIT ",
-
-            Key::Char('-') => "OCTAVE:0 ",
-            Key::Char('=') => "OCTAVE:1 ",
-
-            Key::Char('a') => "NOTE_ON:60:1 ",
-            Key::Char('w') => "NOTE_ON:61:1 ",
-            Key::Char('s') => "NOTE_ON:62:1 ",
-            Key::Char('e') => "NOTE_ON:63:1 ",
-            Key::Char('d') => "NOTE_ON:64:1 ",
-            Key::Char('f') => "NOTE_ON:65:1 ",
-            Key::Char('t') => "NOTE_ON:66:1 ",
-            Key::Char('g') => "NOTE_ON:67:1 ",
-            Key::Char('y') => "NOTE_ON:68:1 ",
-            Key::Char('h') => "NOTE_ON:69:1 ",
-            Key::Char('u') => "NOTE_ON:70:1 ",
-            Key::Char('j') => "NOTE_ON:71:1 ",
-            Key::Char('k') => "NOTE_ON:72:1 ",
-            Key::Char('o') => "NOTE_ON:73:1 ",
-            Key::Char('l') => "NOTE_ON:74:1 ",
-            Key::Char('p') => "NOTE_ON:75:1 ",
-            Key::Char(';') => "NOTE_ON:76:1 ",
-
-            _ => "",
-        };
-
-        if client_buf.len() > 0 { ipc_client.write(client_buf.as_bytes()); }
-        if sound_buf.len() > 0 { ipc_sound.write(sound_buf.as_bytes()); }
-
-        if client_buf == "EXIT " { break; }
+        if let Some(key) = key_name(c.unwrap()) {
+            if !send(&mut ipc_client, &mut ipc_sound, &key, true)? { break; }
+        }
     }
 
     Ok(())
@@ -136,154 +99,51 @@ fn event_loop(mut ipc_client: File, mut ipc_sound: File) -> std::io::Result<()>
 
         // Block on keyboard input
         let input = reader.current_state();
-        let event = (input.event_type(), input.get_key());
-
-        let client_buf: &str = match event {
-            /* (EventType::Release, _) => "GO", */
-            (EventType::Push, k) => match k {
-                Keys::KEY_UP => "UP ",
-                Keys::KEY_DOWN => "DN ",
-                Keys::KEY_LEFT => "LT ",
-                Keys::KEY_RIGHT => "RT ",
-
-                Keys::KEY_Q => "EXIT ",
-                Keys::KEY_1 => "1 ",
-                Keys::KEY_2 => "2 ",
-
-                Keys::KEY_LEFTBRACE => "PLAY ",
-                Keys::KEY_RIGHTBRACE => "STOP ",
-
-                Keys::KEY_M => "M ",
-                Keys::KEY_R => "R ",
-                Keys::KEY_V => "V ",
-                Keys::KEY_I => "I ",
-                Keys::KEY_SPACE => "SPC ",
-
-                Keys::KEY_TAB => "ROUTE ",
-
-                Keys::KEY_KPPLUS => "OCTAVE:1 ",
-                Keys::KEY_KPMINUS => "OCTAVE:0 ",
-
-                Keys::KEY_A => "NOTE_ON:60:1 ",
-                Keys::KEY_W => "NOTE_ON:61:1 ",
-                Keys::KEY_S => "NOTE_ON:62:1 ",
-                Keys::KEY_E => "NOTE_ON:63:1 ",
-                Keys::KEY_D => "NOTE_ON:64:1 ",
-                Keys::KEY_F => "NOTE_ON:65:1 ",
-                Keys::KEY_T => "NOTE_ON:66:1 ",
-                Keys::KEY_G => "NOTE_ON:67:1 ",
-                Keys::KEY_Y => "NOTE_ON:68:1 ",
-                Keys::KEY_H => "NOTE_ON:69:1 ",
-                Keys::KEY_U => "NOTE_ON:70:1 ",
-                Keys::KEY_J => "NOTE_ON:71:1 ",
-                Keys::KEY_K => "NOTE_ON:72:1 ",
-                Keys::KEY_O => "NOTE_ON:73:1 ",
-                Keys::KEY_L => "NOTE_ON:74:1 ",
-                Keys::KEY_P => "NOTE_ON:75:1 ",
-
-                _ => { eprintln!("UNKNOWN:{:?}", k); "" }
-            },
-            (EventType::Release, k) => match k {
-                Keys::KEY_M |
-                Keys::KEY_R |
-                Keys::KEY_V |
-                Keys::KEY_I |
-                Keys::KEY_SPACE => "DESELECT ",
-                
-                Keys::KEY_A => "NOTE_OFF:60 ",
-                Keys::KEY_W => "NOTE_OFF:61 ",
-                Keys::KEY_S => "NOTE_OFF:62 ",
-                Keys::KEY_E => "NOTE_OFF:63 ",
-                Keys::KEY_D => "NOTE_OFF:64 ",
-                Keys::KEY_F => "NOTE_OFF:65 ",
-                Keys::KEY_T => "NOTE_OFF:66 ",
-                Keys::KEY_G => "NOTE_OFF:67 ",
-                Keys::KEY_Y => "NOTE_OFF:68 ",
-                Keys::KEY_H => "NOTE_OFF:69 ",
-                Keys::KEY_U => "NOTE_OFF:70 ",
-                Keys::KEY_J => "NOTE_OFF:71 ",
-                Keys::KEY_K => "NOTE_OFF:72 ",
-                Keys::KEY_O => "NOTE_OFF:73 ",
-                Keys::KEY_L => "NOTE_OFF:74 ",
-                Keys::KEY_P => "NOTE_OFF:75 ",
-
-                _ => ""
-            },
-            (_, _) => ""
-        };
-
-        let sound_buf: &str = match event {
-            (EventType::Push, k) => match k {
-                Keys::KEY_Q => "EXIT ",
-
-                Keys::KEY_EQUAL => "OCTAVE:1 ",
-                Keys::KEY_MINUS => "OCTAVE:0 ",
-
-                Keys::KEY_A => "NOTE_ON:60:1 ",
-                Keys::KEY_W => "NOTE_ON:61:1 ",
-                Keys::KEY_S => "NOTE_ON:62:1 ",
-                Keys::KEY_E => "NOTE_ON:63:1 ",
-                Keys::KEY_D => "NOTE_ON:64:1 ",
-                Keys::KEY_F => "NOTE_ON:65:1 ",
-                Keys::KEY_T => "NOTE_ON:66:1 ",
-                Keys::KEY_G => "NOTE_ON:67:1 ",
-                Keys::KEY_Y => "NOTE_ON:68:1 ",
-                Keys::KEY_H => "NOTE_ON:69:1 ",
-                Keys::KEY_U => "NOTE_ON:70:1 ",
-                Keys::KEY_J => "NOTE_ON:71:1 ",
-                Keys::KEY_K => "NOTE_ON:72:1 ",
-                Keys::KEY_O => "NOTE_ON:73:1 ",
-                Keys::KEY_L => "NOTE_ON:74:1 ",
-                Keys::KEY_P => "NOTE_ON:75:1 ",
-
-                _ => ""
-            },
-            (EventType::Release, k) => match k {
-                Keys::KEY_A => "NOTE_OFF:60 ",
-                Keys::KEY_W => "NOTE_OFF:61 ",
-                Keys::KEY_S => "NOTE_OFF:62 ",
-                Keys::KEY_E => "NOTE_OFF:63 ",
-                Keys::KEY_D => "NOTE_OFF:64 ",
-                Keys::KEY_F => "NOTE_OFF:65 ",
-                Keys::KEY_T => "NOTE_OFF:66 ",
-                Keys::KEY_G => "NOTE_OFF:67 ",
-                Keys::KEY_Y => "NOTE_OFF:68 ",
-                Keys::KEY_H => "NOTE_OFF:69 ",
-                Keys::KEY_U => "NOTE_OFF:70 ",
-                Keys::KEY_J => "NOTE_OFF:71 ",
-                Keys::KEY_K => "NOTE_OFF:72 ",
-                Keys::KEY_O => "NOTE_OFF:73 ",
-                Keys::KEY_L => "NOTE_OFF:74 ",
-                Keys::KEY_P => "NOTE_OFF:75 ",
-                _ => ""  
-            },           
-            (_, _) => ""
+        let pressed = match input.event_type() {
+            EventType::Push => true,
+            EventType::Release => false,
+            _ => continue,
         };
 
-        if client_buf.len() > 0 { ipc_client.write(client_buf.as_bytes()); }
-        if sound_buf.len() > 0 { ipc_sound.write(sound_buf.as_bytes()); }
+        // Keys are bound by their debug names, KEY_A etc.
+        let key = format!("{:?}", input.get_key());
+        if !send(&mut ipc_client, &mut ipc_sound, &key, pressed)? { break; }
     };
+
+    Ok(())
 }
 
 fn main() -> std::io::Result<()> {
 
+    let args: Vec<String> = std::env::args().collect();
+    let config = match Config::load(&args) {
+        Ok(c) => config::init(c),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
+
     // Configure pt-client IPC
     println!("Waiting for pt-client...");
 
     // Blocked by pt-client reader
     let mut ipc_client = OpenOptions::new()
 	.write(true)
-	.open("/tmp/pt-client").unwrap();
+	.open(&config.transport.client_fifo).unwrap();
 
     println!("Waiting for pt-sound...");
 
     // Blocked by pt-client reader
     let mut ipc_sound = OpenOptions::new()
 	.write(true)
-	.open("/tmp/pt-sound").unwrap();
+	.open(&config.transport.sound_fifo).unwrap();
 
     println!("GO");
-    event_loop(ipc_client, ipc_sound);
+    if let Err(e) = event_loop(ipc_client, ipc_sound) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
 
     Ok(())
 }
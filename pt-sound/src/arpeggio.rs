@@ -1,6 +1,5 @@
 use std::borrow::BorrowMut;
-use xmltree::Element;
-use libcommon::{Action, Note, Key, Offset, Param, param_map};
+use libcommon::{Action, Note, Key, Offset, Param, ArpeggioDoc};
 
 pub struct Store {
     timer: Offset,
@@ -13,14 +12,14 @@ pub struct Store {
     queue: Vec<Action>,
 }
 
-pub fn init() -> Store {
+pub fn init(sample_rate: Offset) -> Store {
     Store {
         timer: 0,
         length: 4.0, // beats per loop
         pattern: 0,
         bpm: 127.0,
-        sample_rate: 48000,
-        bar: calculate_beat(48000, 127.0, 4.0),
+        sample_rate,
+        bar: calculate_beat(sample_rate, 127.0, 4.0),
         notes: vec![],
         queue: vec![],
     }
@@ -54,6 +53,17 @@ fn distribute_notes(notes: &mut Vec<Note>, length: Offset, pattern: usize) {
     }
 }
 
+pub fn set_sample_rate(store: &mut Store, sample_rate: Offset) {
+    store.sample_rate = sample_rate;
+    store.bar = calculate_beat(sample_rate, store.bpm, store.length);
+    if store.notes.len() > 0 {
+        distribute_notes(
+            store.notes.borrow_mut(), 
+            store.bar, 
+            store.pattern);
+    }
+}
+
 pub fn dispatch(store: &mut Store, action: Action) {
     match action {
         Action::NoteOn(note, vel) => {
@@ -97,7 +107,8 @@ pub fn dispatch(store: &mut Store, action: Action) {
             }
         }
         Action::SetParam(ref key, val) if key == "pattern" => {
-            store.pattern = val as usize;
+            // Controllers land between patterns
+            store.pattern = val.round() as usize;
             if store.notes.len() > 0 {
                 distribute_notes(
                     store.notes.borrow_mut(), 
@@ -109,12 +120,11 @@ pub fn dispatch(store: &mut Store, action: Action) {
     }
 }
 
-pub fn read(doc: &mut Element) -> Option<Store> {
-    let (mut doc, params) = param_map(doc);
-    let mut store: Store = init();
-    store.length = *params.get("length").unwrap_or(&4.0);
+pub fn read(doc: &ArpeggioDoc, sample_rate: Offset) -> Store {
+    let mut store: Store = init(sample_rate);
+    store.length = doc.length;
     store.bar = calculate_beat(store.sample_rate, store.bpm, store.length);
-    Some(store)
+    store
 }
 
 pub fn compute(store: &mut Store) {
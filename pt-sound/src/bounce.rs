@@ -0,0 +1,180 @@
+extern crate hound;
+
+use std::{error, io};
+use std::fs::File;
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use libcommon::{Action, Document, Offset, ModuleDoc, read_document, config};
+
+use crate::core::{Control, Request, Task, Output, CHANNELS};
+use crate::convert::{Converter, SampleFormat};
+use crate::offline::{render, Sink};
+use crate::master;
+use crate::{load_project, open_project};
+
+/*
+    Bounces render a saved project between its seq_in and seq_out marks,
+    or between its loop marks, into a wav at the project's sample rate
+    and bit depth, through the master gain that is playing. Stems are one
+    wav per track, rendered with the regions of every other track left
+    out, so instruments only play the notes of the stem's track:
+
+        song.wav
+        song_track1.wav, song_track2.wav ...
+
+    Progress goes to the client as BounceProgress, the last one has
+    done == total. Inputs are left out, the playing patch is capturing
+    from them and a bounce only renders what is saved.
+*/
+
+// How often progress is reported, in seconds of the bounce
+const PROGRESS_SECONDS: Offset = 1;
+
+struct Bounced {
+    writer: hound::WavWriter<io::BufWriter<File>>,
+    converter: Converter,
+    format: SampleFormat,
+    done: Offset,
+    total: Offset,
+    reported: Offset,
+    // Frames between progress reports, at the project's rate
+    every: Offset,
+    progress: Sender<Action>,
+}
+
+impl Sink for Bounced {
+    fn write(&mut self, frames: &[[Output; CHANNELS]]) -> io::Result<()> {
+        for frame in frames.iter() {
+            for channel in frame.iter() {
+                let written = match self.format {
+                    SampleFormat::S16 => self.writer.write_sample(self.converter.sample::<i16>(*channel)),
+                    _ => self.writer.write_sample(self.converter.sample::<i32>(*channel)),
+                };
+                written.map_err(io::Error::other)?;
+            }
+        }
+        self.done += frames.len() as Offset;
+        if self.done - self.reported >= self.every {
+            self.reported = self.done;
+            let _ = self.progress.send(Action::BounceProgress(self.done, self.total));
+        }
+        Ok(())
+    }
+}
+
+fn sample_format(bits: u16) -> Result<SampleFormat, String> {
+    match bits {
+        16 => Ok(SampleFormat::S16),
+        24 => Ok(SampleFormat::S24),
+        32 => Ok(SampleFormat::S32),
+        _ => Err(format!("cannot bounce at {} bits", bits)),
+    }
+}
+
+// What a pass renders, only the solo track's regions are kept for a stem
+fn rendered(doc: &Document, solo: Option<u16>) -> Document {
+    let mut doc = doc.clone();
+    doc.modules.retain(|(_, module)| match module {
+        ModuleDoc::Input(_) => false,
+        _ => true,
+    });
+    for (_, module) in doc.modules.iter_mut() {
+        if let (ModuleDoc::Timeline(timeline), Some(solo)) = (module, solo) {
+            for track in timeline.tracks.iter_mut().filter(|t| t.id != solo) {
+                track.audio_regions.clear();
+                track.midi_regions.clear();
+            }
+        }
+    }
+    doc
+}
+
+// Returns the files that were written
+pub fn bounce(
+        project: &str,
+        stems: bool,
+        looped: bool,
+        master_gain: f32,
+        progress: Sender<Action>) -> Result<Vec<PathBuf>, Box<error::Error>> {
+
+    let doc = read_document(project.to_string())?;
+    let (timeline_id, timeline) = doc.modules.iter().find_map(|(id, module)| match module {
+        ModuleDoc::Timeline(timeline) => Some((*id, timeline)),
+        _ => None,
+    }).ok_or("project has no timeline to bounce")?;
+
+    let (start, end) = if looped {
+        (timeline.loop_in, timeline.loop_out)
+    } else {
+        (timeline.seq_in, timeline.seq_out)
+    };
+    if end <= start {
+        Err(format!("nothing to bounce between {} and {}", start, end))?
+    }
+
+    let format = sample_format(doc.bit_depth)?;
+    let spec = hound::WavSpec {
+        channels: CHANNELS as u16,
+        sample_rate: doc.sample_rate,
+        bits_per_sample: doc.bit_depth,
+        sample_format: hound::SampleFormat::Int,
+    };
+
+    // Each pass renders the whole range, with one track at a time for stems
+    let name = project.trim_end_matches(".xml");
+    let passes: Vec<(Option<u16>, PathBuf)> = if stems {
+        timeline.tracks.iter().map(|track| (
+            Some(track.id),
+            config().projects.join(format!("{}_track{}.wav", name, track.id)),
+        )).collect()
+    } else {
+        vec![(None, config().projects.join(format!("{}.wav", name)))]
+    };
+    if passes.is_empty() {
+        Err("no tracks to bounce as stems")?
+    }
+
+    let length = end - start;
+    let total = length * passes.len() as Offset;
+    let mut done = 0;
+
+    for (solo, path) in passes.iter() {
+        let (modules, failed) = load_project(&rendered(&doc, *solo), doc.sample_rate);
+        if let Some(e) = failed.first() {
+            Err(e.clone())?
+        }
+        let mut master = master::init(doc.sample_rate);
+        master::dispatch(&mut master, Action::SetMasterGain(master_gain));
+        let mut patch = open_project(modules, master);
+
+        let at = |action: Action| Action::At(timeline_id, Box::new(action));
+        let script = vec![
+            (0, at(Action::Goto(start))),
+            (0, at(Action::Play)),
+            (length, Action::Exit),
+        ];
+
+        // Only the timeline is driven, so there is nothing else to route
+        let mut prepare = |r: Request| match r {
+            Request::Action(a @ Action::At(_, _)) => vec![Task::Audio(Control::Action(a))],
+            _ => vec![],
+        };
+
+        let mut sink = Bounced {
+            writer: hound::WavWriter::create(path, spec)?,
+            converter: Converter::new(format, config().dither),
+            format,
+            done,
+            total,
+            reported: done,
+            every: doc.sample_rate * PROGRESS_SECONDS,
+            progress: progress.clone(),
+        };
+        render(&mut patch, &mut prepare, &script, doc.sample_rate, &mut sink)?;
+        done = sink.done;
+        sink.writer.finalize()?;
+    }
+
+    let _ = progress.send(Action::BounceProgress(total, total));
+    Ok(passes.into_iter().map(|(_, path)| path).collect())
+}
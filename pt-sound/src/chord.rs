@@ -1,5 +1,4 @@
-use xmltree::Element;
-use libcommon::{Action, Note, Key, Offset, note_list};
+use libcommon::{Action, Note, Key, Offset, ChordDoc};
 
 pub struct Store {
     thru_queue: Vec<Action>,
@@ -13,11 +12,10 @@ pub fn init() -> Store {
     }
 }
 
-pub fn read(doc: &mut Element) -> Option<Store> {
-    let (mut doc, notes) = note_list(doc, 0);
+pub fn read(doc: &ChordDoc) -> Store {
     let mut store: Store = init();
-    store.intervals = notes.iter().map(|n| n.note).collect();
-    Some(store)
+    store.intervals = doc.notes.iter().map(|n| n.note).collect();
+    store
 }
 
 pub fn dispatch(store: &mut Store, action: Action) {
@@ -0,0 +1,277 @@
+#[cfg(target_os = "linux")]
+extern crate alsa;
+
+#[cfg(target_os = "linux")]
+use alsa::pcm;
+
+use crate::core::{Output, CHANNELS};
+
+/*
+    Converts the graph's float frames into whatever sample format the
+    card accepted. Frames are interleaved L R L R ..., full scale is
+    1.0 and anything past it is clipped. Integer formats are dithered
+    with TPDF noise of one LSB before rounding, unless turned off.
+
+    alsa's checked IO only knows the formats that match a Rust type,
+    so cards written or read without direct mmap go through raw bytes.
+*/
+
+// Formats we can write, most preferred first
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SampleFormat {
+    F32,
+    S32,
+    S24,
+    S16,
+}
+
+pub const SAMPLE_FORMATS: [SampleFormat; 4] = [
+    SampleFormat::F32,
+    SampleFormat::S32,
+    SampleFormat::S24,
+    SampleFormat::S16,
+];
+
+impl SampleFormat {
+    #[cfg(target_os = "linux")]
+    pub fn alsa(self) -> pcm::Format {
+        match self {
+            SampleFormat::F32 => pcm::Format::float(),
+            SampleFormat::S32 => pcm::Format::s32(),
+            // 24 bits in the low end of a 32 bit word
+            SampleFormat::S24 => if cfg!(target_endian = "little") {
+                pcm::Format::S24LE
+            } else {
+                pcm::Format::S24BE
+            },
+            SampleFormat::S16 => pcm::Format::s16(),
+        }
+    }
+
+    // Integer resolution, None for float
+    pub fn bits(self) -> Option<u32> {
+        match self {
+            SampleFormat::F32 => None,
+            SampleFormat::S32 => Some(32),
+            SampleFormat::S24 => Some(24),
+            SampleFormat::S16 => Some(16),
+        }
+    }
+
+    // Captured samples back to a full scale of 1.0
+    pub fn unscale<S: InputSample>(self, s: S) -> Output {
+        let raw = match self {
+            // Cards may leave anything above the low 24 bits
+            SampleFormat::S24 => f64::from(((s.raw() as i32) << 8) >> 8),
+            _ => s.raw(),
+        };
+        match self.bits() {
+            None => raw as Output,
+            Some(bits) => (raw / f64::from(1u32 << (bits - 1))) as Output,
+        }
+    }
+}
+
+// A sample type the card can be written with
+pub trait OutputSample: Copy {
+    // v is already scaled, rounded and clipped for the format
+    fn from_scaled(v: f64) -> Self;
+
+    // Stores the sample as the card holds it in memory. S24 is an i32
+    // ... with the sample in its low 24 bits, so this also fits it
+    fn write_ne(self, out: &mut [u8]);
+}
+
+impl OutputSample for i16 {
+    fn from_scaled(v: f64) -> Self { v as i16 }
+    fn write_ne(self, out: &mut [u8]) { out.copy_from_slice(&self.to_ne_bytes()) }
+}
+
+impl OutputSample for i32 {
+    fn from_scaled(v: f64) -> Self { v as i32 }
+    fn write_ne(self, out: &mut [u8]) { out.copy_from_slice(&self.to_ne_bytes()) }
+}
+
+impl OutputSample for f32 {
+    fn from_scaled(v: f64) -> Self { v as f32 }
+    fn write_ne(self, out: &mut [u8]) { out.copy_from_slice(&self.to_ne_bytes()) }
+}
+
+// A sample type the card can be read with
+pub trait InputSample: Copy + Default {
+    // Unscaled, as it was captured
+    fn raw(self) -> f64;
+
+    // Reads the sample back from the card's memory, see write_ne
+    fn read_ne(bytes: &[u8]) -> Self;
+}
+
+impl InputSample for i16 {
+    fn raw(self) -> f64 { f64::from(self) }
+    fn read_ne(bytes: &[u8]) -> Self { i16::from_ne_bytes([bytes[0], bytes[1]]) }
+}
+
+impl InputSample for i32 {
+    fn raw(self) -> f64 { f64::from(self) }
+    fn read_ne(bytes: &[u8]) -> Self { i32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) }
+}
+
+impl InputSample for f32 {
+    fn raw(self) -> f64 { f64::from(self) }
+    fn read_ne(bytes: &[u8]) -> Self { f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) }
+}
+
+pub struct Converter {
+    format: SampleFormat,
+    dither: bool,
+    seed: u32,
+}
+
+impl Converter {
+    pub fn new(format: SampleFormat, dither: bool) -> Self {
+        Converter { format, dither, seed: 0x9E37_79B9 }
+    }
+
+    // Uniform in [0, 1), xorshift is plenty for noise
+    fn uniform(&mut self) -> f64 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        f64::from(self.seed) / 4_294_967_296.0
+    }
+
+    pub fn sample<S: OutputSample>(&mut self, x: Output) -> S {
+        let x = if x.is_finite() { f64::from(x) } else { 0.0 };
+        match self.format.bits() {
+            None => S::from_scaled(x.max(-1.0).min(1.0)),
+            Some(bits) => {
+                let full = f64::from(1u32 << (bits - 1));
+                let mut v = x * full;
+                if self.dither {
+                    // Difference of two uniforms is triangular over +-1 LSB
+                    v += self.uniform() - self.uniform();
+                }
+                S::from_scaled(v.round().max(-full).min(full - 1.0))
+            }
+        }
+    }
+
+    // Interleaves frames into out, which is cleared first
+    pub fn interleave<S: OutputSample>(&mut self, frames: &[[Output; CHANNELS]], out: &mut Vec<S>) {
+        out.clear();
+        for frame in frames.iter() {
+            for channel in frame.iter() {
+                let sample = self.sample(*channel);
+                out.push(sample);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plain(format: SampleFormat) -> Converter {
+        Converter::new(format, false)
+    }
+
+    #[test]
+    fn full_scale_is_clipped() {
+        let mut c = plain(SampleFormat::S16);
+        let s16: Vec<i16> = [1.0, 2.0, -1.0, -2.0, 0.5].iter().map(|x| c.sample(*x)).collect();
+        assert_eq!(s16, vec![32767, 32767, -32768, -32768, 16384]);
+
+        let mut c = plain(SampleFormat::S24);
+        let s24: Vec<i32> = [1.0, 2.0, -1.0, -2.0].iter().map(|x| c.sample(*x)).collect();
+        assert_eq!(s24, vec![8_388_607, 8_388_607, -8_388_608, -8_388_608]);
+
+        let mut c = plain(SampleFormat::S32);
+        let s32: Vec<i32> = [1.0, 2.0, -1.0, -2.0].iter().map(|x| c.sample(*x)).collect();
+        assert_eq!(s32, vec![i32::MAX, i32::MAX, i32::MIN, i32::MIN]);
+
+        let mut c = plain(SampleFormat::F32);
+        let f32s: Vec<f32> = [1.5, -1.5, 0.25].iter().map(|x| c.sample(*x)).collect();
+        assert_eq!(f32s, vec![1.0, -1.0, 0.25]);
+    }
+
+    #[test]
+    fn non_finite_is_silence() {
+        for format in SAMPLE_FORMATS.iter() {
+            let mut c = plain(*format);
+            for x in [Output::NAN, Output::INFINITY, Output::NEG_INFINITY].iter() {
+                assert_eq!(c.sample::<f32>(*x), 0.0, "{:?}", format);
+            }
+        }
+    }
+
+    #[test]
+    fn channels_stay_in_order() {
+        let frames = [[0.5, -0.25], [0.0, 1.0], [-1.0, 0.125]];
+        let mut c = plain(SampleFormat::S16);
+        let mut out: Vec<i16> = vec![99];
+        c.interleave(&frames, &mut out);
+        assert_eq!(out, vec![16384, -8192, 0, 32767, -32768, 4096]);
+
+        let mut c = plain(SampleFormat::F32);
+        let mut out: Vec<f32> = vec![];
+        c.interleave(&frames, &mut out);
+        assert_eq!(out, vec![0.5, -0.25, 0.0, 1.0, -1.0, 0.125]);
+    }
+
+    // Converted and captured back, every format lands within a step
+    #[test]
+    fn every_format_round_trips() {
+        for format in SAMPLE_FORMATS.iter() {
+            let step = format.bits().map_or(1e-6, |bits| 1.0 / f64::from(1u32 << (bits - 1)));
+            for x in [-1.0, -0.5, -0.001, 0.0, 0.3, 0.999].iter() {
+                let mut c = plain(*format);
+                let back = match format {
+                    SampleFormat::F32 => format.unscale(c.sample::<f32>(*x)),
+                    SampleFormat::S32 | SampleFormat::S24 => format.unscale(c.sample::<i32>(*x)),
+                    SampleFormat::S16 => format.unscale(c.sample::<i16>(*x)),
+                };
+                assert!((f64::from(back) - f64::from(*x)).abs() <= step, "{:?} {} {}", format, x, back);
+            }
+        }
+    }
+
+    #[test]
+    fn dither_stays_within_a_step() {
+        let mut c = Converter::new(SampleFormat::S16, true);
+        let samples: Vec<i16> = (0..1000).map(|_| c.sample(0.0)).collect();
+        assert!(samples.iter().all(|s| (-1..=1).contains(s)));
+        assert!(samples.iter().any(|s| *s != 0));
+    }
+
+    #[test]
+    fn s24_ignores_the_top_byte() {
+        let bytes = 0x7F80_0000i32.to_ne_bytes();
+        assert_eq!(SampleFormat::S24.unscale(i32::read_ne(&bytes)), -1.0);
+        let bytes = 0x0040_0000i32.to_ne_bytes();
+        assert_eq!(SampleFormat::S24.unscale(i32::read_ne(&bytes)), 0.5);
+        assert_eq!(SampleFormat::S32.unscale(i32::read_ne(&0x4000_0000i32.to_ne_bytes())), 0.5);
+        assert_eq!(SampleFormat::S16.unscale(i16::read_ne(&(-16384i16).to_ne_bytes())), -0.5);
+    }
+
+    // S24 is written as the low three bytes of a native i32
+    #[test]
+    fn raw_bytes_match_the_card() {
+        let mut c = plain(SampleFormat::S24);
+        let mut bytes = [0u8; 4];
+        c.sample::<i32>(-1.0).write_ne(&mut bytes);
+        assert_eq!(i32::from_ne_bytes(bytes), -8_388_608);
+        c.sample::<i32>(0.5).write_ne(&mut bytes);
+        assert_eq!(i32::from_ne_bytes(bytes), 0x40_0000);
+
+        let mut c = plain(SampleFormat::S16);
+        let mut bytes = [0u8; 2];
+        c.sample::<i16>(-0.5).write_ne(&mut bytes);
+        assert_eq!(i16::from_ne_bytes(bytes), -16384);
+
+        let mut c = plain(SampleFormat::F32);
+        let mut bytes = [0u8; 4];
+        c.sample::<f32>(0.75).write_ne(&mut bytes);
+        assert_eq!(f32::from_ne_bytes(bytes), 0.75);
+    }
+}
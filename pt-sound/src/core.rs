@@ -2,19 +2,22 @@ extern crate sample;
 extern crate portaudio;
 extern crate hound;
 
-use std::{iter, error};
+use std::{iter, error, mem};
 use std::ffi::CString;
-use std::fs::File;
 use std::io::prelude::*;
 use std::thread;
+use std::time::Duration;
 use std::sync::Arc;
+use std::sync::mpsc::Receiver;
+use std::collections::HashMap;
 use std::ops::DerefMut;
-use libcommon::{Action, Key, Volume, Offset};
+use libcommon::{Action, Event, Server, Key, Volume, Offset, config};
+use libcommon::ring::{self, Producer, Consumer};
 
 #[cfg(target_os = "linux")]
 extern crate alsa;
 #[cfg(target_os = "linux")]
-use alsa::{seq, pcm, PollDescriptors};
+use alsa::{pcm, PollDescriptors};
 #[cfg(target_os = "linux")]
 use alsa::pcm::State;
 
@@ -25,25 +28,103 @@ use dsp::daggy::petgraph::Bfs;
 #[cfg(target_os = "macos")]
 use portaudio as pa;
 
-use crate::midi::{open_midi_dev, read_midi_event, connect_midi_source_ports};
+use crate::midi;
 use crate::synth;
 use crate::tape;
 use crate::chord;
 use crate::arpeggio;
 use crate::plugin;
+use crate::input;
+use crate::master;
+use crate::meter;
+use crate::convert::{Converter, OutputSample, SampleFormat, SAMPLE_FORMATS};
 
 // SAMPLE FORMATS 
 pub type Output = f32; // PORTAUDIO
 pub type SF = i16; // ALSA
 
 pub const CHANNELS: usize = 2;
-pub const SAMPLE_HZ: f64 = 48_000.0;
 pub const BUF_SIZE: usize = 24_000;
 pub const BIT_RATE: usize = 16;
 pub const FRAMES: u32 = 128;
 
 const DEBUG_KEY_PERIOD: u16 = 24100;
 
+// Messages queued between the control and audio threads
+const CONTROL_QUEUE: usize = 256;
+const CLIENT_QUEUE: usize = 1024;
+
+// Swaps the control thread may have in flight. Every swap sends one
+// patch back to be freed, so the trash never fills up
+const TRASH_QUEUE: usize = 16;
+
+// How long the control thread waits when nothing arrived
+const CONTROL_SLEEP: Duration = Duration::from_millis(2);
+
+// A graph and the maps to find its routes and modules. The control
+// thread builds it and the audio thread swaps it in whole
+pub struct Patch {
+    pub graph: Graph<[Output; CHANNELS], Module>,
+    pub routes: HashMap<u16, NodeIndex>,
+    pub operators: HashMap<u16, NodeIndex>,
+    // For the tapes to play once it's swapped in
+    pub assets: Option<Assets>,
+}
+
+impl Patch {
+    pub fn new() -> Self {
+        Patch {
+            graph: Graph::new(),
+            routes: HashMap::new(),
+            operators: HashMap::new(),
+            assets: None,
+        }
+    }
+}
+
+// Assets the control thread converted to a new rate, by source, and
+// room for the frames they replace to go back with the old patch
+pub struct Assets {
+    pub frames: HashMap<String, tape::Frames>,
+    pub replaced: Vec<tape::Frames>,
+}
+
+// What arrives on the control thread
+pub enum Request {
+    Action(Action),
+    // Played on a MIDI port, by port name
+    Midi(String, Action),
+    // An asset a region on the audio thread plays, by source
+    Region(String),
+}
+
+// What the control thread does with a prepared request
+pub enum Task {
+    // Handed to the audio thread
+    Audio(Control),
+    // Sent to the client
+    Client(Action),
+    // Notes played on this MIDI port are wanted
+    Listen(String),
+}
+
+// What the control thread hands to the audio thread. Anything that
+// reads files or changes the graph is done before it is sent, the
+// audio thread only swaps finished patches in.
+pub enum Control {
+    Action(Action),
+    Swap(Box<Patch>),
+}
+
+// Counted on the audio thread and reported with the device status
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Glitches {
+    // Times the card ran out of frames
+    pub xruns: u32,
+    // Actions for the client dropped because its queue was full
+    pub dropped: u32,
+}
+
 #[derive(Debug, Clone)]
 pub struct Note {
     pub id: u16,
@@ -53,45 +134,56 @@ pub struct Note {
     pub vel: Volume,
 }
 
+// What the card agreed to, which may not be what we asked for
+#[derive(Debug, Clone, Copy)]
+pub struct Negotiated {
+    pub rate: u32,
+    pub period: u32,
+    pub buffer: u32,
+    pub format: SampleFormat,
+}
+
 #[cfg(target_os = "linux")]
-pub fn open_audio_dev() -> Result<(alsa::PCM, u32), Box<error::Error>> {
-    let args: Vec<_> = std::env::args().collect();
-    if args.len() < 2 { 
-        println!("Usage: 'cargo run --release CARD_NAME SAMPLE_RATE BUF_SIZE'");
-        Err("No card name specified")?
-    }
-    let req_devname = format!("hw:{}", args[1]);
-    let req_samplerate = args.get(2).map(|x| x.parse()).unwrap_or(Ok(48000))?;
-    let req_bufsize = args.get(3).map(|x| x.parse()).unwrap_or(Ok(256))?; // A few ms latency by default, that should be nice 
+pub fn open_audio_dev() -> Result<(alsa::PCM, Negotiated), Box<error::Error>> {
+    let config = config();
+    let req_devname = format!("hw:{}", config.audio_card);
+    let req_samplerate = config.sample_rate;
+    let req_bufsize = i64::from(config.buffer_size); // A few ms latency by default, that should be nice 
+    let req_periodsize = i64::from(config.period_size);
     
     // Open the device
     let p = alsa::PCM::new(&req_devname, alsa::Direction::Playback, false)?;
     
     // Set hardware parameters
-    {
+    let format = {
         let hwp = pcm::HwParams::any(&p)?;
-        hwp.set_channels(2)?;
+        hwp.set_channels(CHANNELS as u32)?;
         hwp.set_rate(req_samplerate, alsa::ValueOr::Nearest)?;
-        hwp.set_format(pcm::Format::s16())?;
+
+        // Take the first format the card supports
+        let format = SAMPLE_FORMATS.iter().cloned()
+            .find(|f| hwp.set_format(f.alsa()).is_ok())
+            .ok_or("No supported sample format (S16, S24, S32 or F32)")?;
         hwp.set_access(pcm::Access::MMapInterleaved)?;
-        hwp.set_buffer_size(req_bufsize)?;
-        hwp.set_period_size(req_bufsize / 4, alsa::ValueOr::Nearest)?;
+        hwp.set_period_size_near(req_periodsize, alsa::ValueOr::Nearest)?;
+        hwp.set_buffer_size_near(req_bufsize)?;
         p.hw_params(&hwp)?;
-    }
+        format
+    };
 
     // Set software parameters
-    let rate = {
+    let (rate, period, buffer) = {
         let hwp = p.hw_params_current()?;
         let swp = p.sw_params_current()?;
         let (bufsize, periodsize) = (hwp.get_buffer_size()?, hwp.get_period_size()?);
         swp.set_start_threshold(bufsize - periodsize)?;
         swp.set_avail_min(periodsize)?;
         p.sw_params(&swp)?;
-        println!("Opened audio output {:?} with parameters: {:?}, {:?}", req_devname, hwp, swp);
-        hwp.get_rate()?
+        println!("Opened audio output {:?} as {:?} with parameters: {:?}, {:?}", req_devname, format, hwp, swp);
+        (hwp.get_rate()?, periodsize as u32, bufsize as u32)
     };
 
-    Ok((p, rate))
+    Ok((p, Negotiated { rate, period, buffer, format }))
 }
 
 #[cfg(target_os = "linux")]
@@ -101,35 +193,39 @@ fn set_buffer_size(p: &mut alsa::PCM, buf_size: i64) {
 }
 
 #[cfg(target_os = "linux")]
-pub fn write_samples_direct(
+pub fn write_samples_direct<S: OutputSample>(
     p: &alsa::PCM, 
-    mmap: &mut alsa::direct::pcm::MmapPlayback<SF>, 
-    synth: &mut Iterator<Item=SF>) -> Result<bool, Box<error::Error>>
+    mmap: &mut alsa::direct::pcm::MmapPlayback<S>, 
+    synth: &mut Iterator<Item=S>,
+    glitches: &mut Glitches) -> Result<bool, Box<error::Error>>
 {
     if mmap.avail() > 0 {
-        mmap.write(&mut Box::new(synth));
+        mmap.write(&mut synth);
     }
 
     match mmap.status().state() {
         State::Running => { return Ok(false); }, // All fine
-        State::Prepared => { println!("Starting audio output stream"); p.start()? },
-        State::XRun => { println!("Underrun in audio output stream!"); p.prepare()? },
-        State::Suspended => { println!("Resuming audio output stream"); p.resume()? },
+        State::Prepared => { p.start()? },
+        State::XRun => { glitches.xruns += 1; p.prepare()? },
+        State::Suspended => { p.resume()? },
         n @ _ => Err(format!("Unexpected pcm state {:?}", n))?,
     }
     Ok(true) // Call us again, please, there might be more data to write
 }
 
+// The io is unchecked, S24 has no type of its own for alsa to check
 #[cfg(target_os = "linux")]
-pub fn write_samples_io(
+pub fn write_samples_io<S: OutputSample>(
     p: &alsa::PCM, 
-    io: &mut alsa::pcm::IO<SF>, 
-    synth: &mut Iterator<Item=SF>) -> Result<bool, Box<error::Error>> 
+    io: &mut alsa::pcm::IO<u8>, 
+    synth: &mut Iterator<Item=S>,
+    glitches: &mut Glitches) -> Result<bool, Box<error::Error>> 
 {
     let avail = match p.avail_update() {
         Ok(n) => n,
         Err(e) => {
-            println!("Recovering from {}", e);
+            // Underruns land here, as EPIPE
+            glitches.xruns += 1;
             if let Some(errno) = e.errno() {
                 p.recover(errno as std::os::raw::c_int, true)?;
             }
@@ -139,44 +235,72 @@ pub fn write_samples_io(
 
     if avail > 0 {
         io.mmap(avail, |buf| {
-            for sample in buf.iter_mut() {
-                *sample = synth.next().unwrap()
+            // Only whole frames, and no more than we have
+            let mut written = 0;
+            for (bytes, value) in buf.chunks_exact_mut(mem::size_of::<S>()).zip(&mut *synth) {
+                value.write_ne(bytes);
+                written += 1;
             };
-	    buf.len() / 2 
+            written / CHANNELS
         })?;
     }
     use alsa::pcm::State;
     match p.state() {
         State::Running => Ok(false), // All fine
-        State::Prepared => { println!("Starting audio output stream"); p.start()?; Ok(true) },
+        State::Prepared => { p.start()?; Ok(true) },
         State::Suspended | State::XRun => Ok(true), // Recover from this in next round
         n @ _ => Err(format!("Unexpected pcm state {:?}", n))?,
     }
 }
 
 #[cfg(target_os = "macos")]
-pub fn event_loop<F: 'static>(
-        mut ipc_in: File, 
-        mut ipc_client: File, 
-        mut patch: Graph<[Output; CHANNELS], Module>, 
-        mut dispatch_f: F) -> Result<(), Box<error::Error>> 
-    where F: FnMut(&mut Graph<[Output; CHANNELS], Module>, Action) {
+pub fn event_loop<P>(
+        server: Server, 
+        patch: Patch, 
+        prepare_f: P,
+        replies: Receiver<Action>) -> Result<(), Box<error::Error>> 
+    where P: FnMut(Request) -> Vec<Task> + Send + 'static {
+
+    let rate = config().sample_rate;
+    let period = config().period_size;
+
+    let mut queues = spawn_control(server, prepare_f, replies, rate);
+    let mut patch = patch;
+    let mut glitches = Glitches::default();
+    let mut since_status = rate;
+    let mut since_levels = 0;
 
     // The callback we'll use to pass to the Stream. It will request audio from our dsp_graph.
-    let callback = move |pa::OutputStreamCallbackArgs { buffer, time, .. }| {
+    let callback = move |pa::OutputStreamCallbackArgs { buffer, time, flags, .. }| {
 
-        let ipc_actions: Vec<Action> = ipc_action(&ipc_in);
-
-        match ipc_dispatch(ipc_actions, &mut patch, &mut dispatch_f) {
-            Action::Exit => { return pa::Complete; },
-            _ => {}
+        if !dispatch_controls(&mut queues, &mut patch) {
+            return pa::Complete;
         }
 
-        walk_dispatch(&ipc_client, &mut patch);
+        walk_dispatch(&mut patch.graph, |action| send_client(&mut queues, action, &mut glitches));
+        if flags.contains(pa::StreamCallbackFlags::OUTPUT_UNDERFLOW) {
+            glitches.xruns += 1;
+        }
 
         let buffer: &mut [[Output; CHANNELS]] = buffer.to_frame_slice_mut().unwrap();
+
+        // About once a second, with how long until this buffer is heard
+        if since_status >= rate {
+            since_status = 0;
+            let latency = ((time.buffer_dac - time.current).max(0.0) * f64::from(rate)) as Offset;
+            send_client(&mut queues, Action::AudioStatus(rate, period, period, latency), &mut glitches);
+            send_client(&mut queues, Action::Glitches(glitches.xruns, glitches.dropped), &mut glitches);
+        }
+        since_status += buffer.len() as u32;
+
+        if since_levels >= rate / meter::LEVELS_PER_SECOND {
+            since_levels = 0;
+            send_levels(&mut patch, &mut queues, &mut glitches);
+        }
+        since_levels += buffer.len() as u32;
+
         dsp::slice::equilibrium(buffer);
-        patch.audio_requested(buffer, SAMPLE_HZ);
+        patch.graph.audio_requested(buffer, f64::from(rate));
 
         pa::Continue
     };
@@ -185,8 +309,8 @@ pub fn event_loop<F: 'static>(
     let pa = pa::PortAudio::new()?;
     let settings = pa.default_output_stream_settings::<Output>(
         CHANNELS as i32, 
-        SAMPLE_HZ, 
-        FRAMES
+        f64::from(rate), 
+        period
     )?;
     let mut stream = pa.open_non_blocking_stream(settings, callback)?;
     stream.start()?;
@@ -201,11 +325,11 @@ pub fn event_loop<F: 'static>(
 
 // Node types in our patch graph.
 pub enum Module {
-    // Exhibits default behavior of mixing inputs to its output
-    Master,
+    // Mixes its inputs, then applies master gain and the limiter
+    Master(master::Store),
     // A useful node which, when receiving an action, will dispatch it
-    // ... to its neighbors
-    Passthru(Vec<Action>),
+    // ... to its neighbors. Routes are passthrus, and meter what they mix
+    Passthru(Vec<Action>, meter::Level),
     Octave(Vec<Action>, Key),
     // A hacky node which will dispatch NoteOn actions to its neighbors,
     // and every second or so will send all corresponding NoteOff actions.
@@ -217,21 +341,50 @@ pub enum Module {
     Chord(chord::Store),
     Arpeggio(arpeggio::Store),
     Plugin(plugin::Store),
+    // One per input anchor
+    Input(input::Store),
+    // Stands in for a node of the playing patch, which is moved here
+    // ... when the patch this is in gets swapped in
+    Live(NodeIndex),
 }
 
 impl Module {
+    pub fn set_sample_rate(&mut self, rate: u32) {
+        match *self {
+            Module::Synth(ref mut store) => synth::set_sample_rate(store, rate),
+            Module::Tape(ref mut store) => tape::set_sample_rate(store, rate),
+            Module::Arpeggio(ref mut store) => arpeggio::set_sample_rate(store, rate),
+            Module::Plugin(ref mut store) => plugin::set_sample_rate(store, rate),
+            Module::Input(ref mut store) => input::set_sample_rate(store, rate),
+            Module::Master(ref mut store) => master::set_sample_rate(store, rate),
+            _ => {}
+        }
+    }
+    // Nodes that put out sound keep their peak for the patch view
+    pub fn level(&mut self) -> Option<&mut meter::Level> {
+        match *self {
+            Module::Passthru(_, ref mut level) => Some(level),
+            Module::Synth(ref mut store) => Some(&mut store.level),
+            Module::Tape(ref mut store) => Some(&mut store.level),
+            Module::Plugin(ref mut store) => Some(&mut store.level),
+            Module::Input(ref mut store) => Some(&mut store.level),
+            _ => None,
+        }
+    }
     pub fn dispatch(&mut self, a: Action) {
         match *self {
-            Module::Master => {}
+            Module::Master(ref mut store) => master::dispatch(store, a.clone()),
             Module::Operator(ref mut queue, _, _) |
-            Module::Passthru(ref mut queue) => { queue.push(a.clone()) }
+            Module::Passthru(ref mut queue, _) => { queue.push(a.clone()) }
             Module::DebugKeys(ref mut onqueue, _, _) => { onqueue.push(a.clone()); }
             Module::Synth(ref mut store) => synth::dispatch(store, a.clone()),
             Module::Tape(ref mut store) => tape::dispatch(store, a.clone()),
             Module::Chord(ref mut store) => chord::dispatch(store, a.clone()),
             Module::Octave(ref mut queue, ref mut n) => { 
                 match a {
-                    Action::NoteOn(_, _) | Action::NoteOff(_) => { queue.push(a.clone()); },
+                    Action::NoteOn(_, _) | Action::NoteOff(_) |
+                    Action::ControlChange(_, _) | Action::PitchBend(_) |
+                    Action::ProgramChange(_) => { queue.push(a.clone()); },
                     Action::Octave(up) => if up { *n = *n+1; } else { *n = if *n > 0 { *n-1 } else { 0 }; },
                     _ => (),
                 }
@@ -249,7 +402,7 @@ impl Module {
 
         match *self {
             Module::Operator(ref mut queue, _, _) |
-            Module::Passthru(ref mut queue) => {
+            Module::Passthru(ref mut queue, _) => {
                 let carry = queue.clone();
                 queue.clear();
                 return (Some(carry), None, None)
@@ -264,7 +417,8 @@ impl Module {
                             if shift > n as i8 { 0 } else { (n as i8 + shift) as u8 }, v),
                         Action::NoteOff(n) => Action::NoteOff(
                             if shift > n as i8 { 0 } else { (n as i8 + shift) as u8 }),
-                        _ => Action::Noop,
+                        // Controllers go on as they are, only notes are shown
+                        a => { carry.push(a); continue; },
                     };
                     carry.push(shifted_note.clone());
                     client_carry.push(shifted_note);
@@ -291,7 +445,7 @@ impl Module {
             Module::Arpeggio(ref mut store) => arpeggio::dispatch_requested(store),
             Module::Synth(ref mut store) => synth::dispatch_requested(store),
             Module::Plugin(ref mut store) => plugin::dispatch_requested(store),
-            Module::Master => (None, None, None), // TODO: give master levels to client
+            Module::Master(ref mut store) => master::dispatch_requested(store),
             _ => (None, None, None)
         }
     }
@@ -301,7 +455,9 @@ impl Node<[Output; CHANNELS]> for Module {
     // Override the audio_requested method and compute PCM audio
     fn audio_requested(&mut self, buffer: &mut [[Output; CHANNELS]], sample_hz: f64) {
         match *self {
-            Module::Master => (),
+            Module::Master(ref mut store) => {
+                master::compute_buf(store, buffer);
+            },
             Module::Synth(ref mut store) => {
                 dsp::slice::map_in_place(buffer, |_| synth::compute(store));
             },
@@ -325,9 +481,15 @@ impl Node<[Output; CHANNELS]> for Module {
             },
             Module::Plugin(ref mut store) => {
                 plugin::compute_buf(store, buffer);
+            },
+            Module::Input(ref mut store) => {
+                input::compute_buf(store, buffer);
             }
             _ => ()
         }
+        if let Some(level) = self.level() {
+            meter::measure(level, buffer);
+        }
     }
 }
 
@@ -337,9 +499,11 @@ impl Node<[Output; CHANNELS]> for Module {
 // while the computation of these 512 frames might not take 
 // 48000 / 512 seconds to calculate, that is the deadline, otherwise
 // we get an audio underrun.
-fn walk_dispatch(mut ipc_client: &File, patch: &mut Graph<[Output; CHANNELS], Module>) {
+pub fn walk_dispatch<C>(patch: &mut Graph<[Output; CHANNELS], Module>, mut client: C)
+    where C: FnMut(Action) {
     // Nodes dispatch actions to its ins, outs, or to client. Midi signals
-    // ... must travel opposite the direciton of audio in an acyclic graph
+    // ... must travel opposite the direciton of audio in an acyclic graph.
+    // Actions for the client are handed to the caller to send
     let mut walk = patch.visit_order_rev();
     while let Some(n) = walk.next(&patch) {
         let (out_d, in_d, client_d) = patch[n].dispatch_requested();
@@ -361,57 +525,312 @@ fn walk_dispatch(mut ipc_client: &File, patch: &mut Graph<[Output; CHANNELS], Mo
         }
         if let Some(client_a) = client_d {
             let mut ins = patch.inputs(n);
-            let mut op_id = 0;
+            let mut op_id = None;
             'search: while let Some(oid) = ins.next_node(&patch) {
                 match patch[oid] {
-                    Module::Operator(_, _, id) => { op_id = id; break 'search; },
+                    Module::Operator(_, _, id) => { op_id = Some(id); break 'search; },
                     _ => {}
                 }
             }
             for action in client_a.iter() {
-                let filtered_direct = match action {
-                    Action::Noop => None,
-                    a => Some(Action::At(op_id, Box::new(a.to_owned()))),
+                // Nodes outside any module, like master, talk to the client directly
+                let filtered_direct = match (action, op_id) {
+                    (Action::Noop, _) => None,
+                    (a, Some(id)) => Some(Action::At(id, Box::new(a.to_owned()))),
+                    (a, None) => Some(a.to_owned()),
                 };
                 if let Some(a) = filtered_direct {
-                    ipc_client.write(a.to_string().as_bytes());
+                    client(a);
+                }
+            }
+        }
+    }
+}
+
+// Moves every node the new patch stands in for out of the playing one,
+// then plays the new patch. The old patch is returned to be freed off
+// the audio thread.
+pub fn swap(patch: &mut Patch, mut next: Box<Patch>) -> Box<Patch> {
+    for i in 0..next.graph.node_count() {
+        let node = NodeIndex::new(i);
+        if let Module::Live(live) = next.graph[node] {
+            mem::swap(&mut next.graph[node], &mut patch.graph[live]);
+        }
+    }
+    // Operators are remade with their new anchors, but keep whatever
+    // ... was queued on them this period
+    for (id, operator) in next.operators.iter() {
+        if let Some(old) = patch.operators.get(id) {
+            if let Module::Operator(ref mut old_queue, _, _) = patch.graph[*old] {
+                if let Module::Operator(ref mut queue, _, _) = next.graph[*operator] {
+                    mem::swap(queue, old_queue);
                 }
             }
         }
     }
+    mem::swap(patch, &mut *next);
+    // Converted assets are played from now on, what they replace leaves
+    // ... with the old patch
+    if let Some(ref mut assets) = patch.assets {
+        for node in patch.graph.nodes_mut() {
+            if let Module::Tape(store) = node {
+                tape::reload(store, assets);
+            }
+        }
+    }
+    mem::swap(&mut patch.assets, &mut next.assets);
+    next
 }
 
-fn ipc_dispatch<F: 'static>(
-        ipc_actions: Vec<Action>, 
-        patch: &mut Graph<[Output; CHANNELS], Module>,
-        root_dispatch: &mut F) -> Action 
+// Applies a control on the audio thread, returning the patch it
+// replaced for the caller to get rid of
+pub fn apply(patch: &mut Patch, control: Control) -> Option<Box<Patch>> {
+    let a = match control {
+        Control::Swap(next) => return Some(swap(patch, next)),
+        Control::Action(a) => a,
+    };
+    match a {
+        Action::At(n_id, action) => {
+            if let Some(operator) = patch.operators.get(&n_id) {
+                patch.graph[*operator].dispatch(*action);
+            }
+        },
+        Action::SetSampleRate(rate) => {
+            for node in patch.graph.nodes_mut() {
+                node.set_sample_rate(rate);
+            }
+        },
+        Action::SetMasterGain(_) => {
+            if let Some(master) = patch.graph.master_index() {
+                patch.graph[master].dispatch(a);
+            }
+        },
+        Action::SetMeter(_, _) |
+        Action::SetTempo(_) => {
+            for operator in patch.operators.values() {
+                patch.graph[*operator].dispatch(a.clone());
+            }
+        },
+        // The control thread sends nothing else
+        _ => {}
+    }
+    None
+}
 
-    where F: FnMut(&mut Graph<[Output; CHANNELS], Module>, Action) {
+// Patches swapped out on the audio thread on their way back to the
+// control thread, which frees them. One that doesn't fit waits here
+// and is tried again next period, it is never dropped.
+struct Trash {
+    ring: Producer<Box<Patch>>,
+    pending: Vec<Box<Patch>>,
+}
 
-    for action in ipc_actions.iter() {
-        match action {
-            Action::Exit => { return Action::Exit; },
-            // Pass any other action to root
-            a => { 
-                root_dispatch(patch, a.clone()); 
+impl Trash {
+    fn new(ring: Producer<Box<Patch>>) -> Self {
+        Trash { ring, pending: Vec::with_capacity(TRASH_QUEUE) }
+    }
+
+    fn throw(&mut self, old: Box<Patch>) {
+        if let Err(old) = self.ring.push(old) {
+            self.pending.push(old);
+        }
+    }
+
+    fn retry(&mut self) {
+        while let Some(old) = self.pending.pop() {
+            if let Err(old) = self.ring.push(old) {
+                self.pending.push(old);
+                return;
             }
+        }
+    }
+}
+
+// The audio thread's ends of the queues to and from the control thread
+struct AudioQueues {
+    controls: Consumer<Control>,
+    to_client: Producer<Action>,
+    trash: Trash,
+}
+
+// Applies everything the control thread sent, false once it's time to exit
+fn dispatch_controls(queues: &mut AudioQueues, patch: &mut Patch) -> bool {
+    queues.trash.retry();
+    while let Some(control) = queues.controls.pop() {
+        match control {
+            Control::Action(Action::Exit) => { return false; },
+            control => if let Some(old) = apply(patch, control) {
+                queues.trash.throw(old);
+            },
+        }
+    }
+    !queues.controls.is_abandoned()
+}
+
+// Actions from the patch for the client, counted when there's no room
+fn send_client(queues: &mut AudioQueues, action: Action, glitches: &mut Glitches) {
+    if queues.to_client.push(action).is_err() {
+        glitches.dropped += 1;
+    }
+}
+
+// Peaks of every route and module anchor since the last time, for the
+// ... patch view. Anchors that share a node report the same peak.
+fn send_levels(patch: &mut Patch, queues: &mut AudioQueues, glitches: &mut Glitches) {
+    for (r_id, route) in patch.routes.iter() {
+        if let Some(peak) = patch.graph[*route].level().and_then(|l| meter::read(l)) {
+            send_client(queues, Action::RouteLevel(*r_id, peak), glitches);
+        }
+    }
+    for (m_id, operator) in patch.operators.iter() {
+        let count = match &patch.graph[*operator] {
+            Module::Operator(_, anchors, _) => anchors.len(),
+            _ => 0,
         };
+        for a_id in 0..count {
+            let node = match &patch.graph[*operator] {
+                Module::Operator(_, anchors, _) => anchors[a_id],
+                _ => continue,
+            };
+            if let Some(peak) = patch.graph[node].level().and_then(|l| meter::read(l)) {
+                send_client(queues, Action::AnchorLevel(*m_id, a_id as u16, peak), glitches);
+            }
+        }
+    }
+    for node in patch.graph.nodes_mut() {
+        if let Some(level) = node.level() {
+            meter::reset(level);
+        }
+    }
+}
+
+// Waits for room when the audio thread is far behind, and for old
+// patches to come back before too many swaps are in flight. False
+// once the audio thread is gone.
+fn send_control(
+        controls: &mut Producer<Control>,
+        trash: &mut Consumer<Box<Patch>>,
+        swaps: &mut usize,
+        mut control: Control) -> bool {
+
+    if let Control::Swap(_) = control {
+        while *swaps >= TRASH_QUEUE {
+            match trash.pop() {
+                Some(_) => *swaps -= 1,
+                None if trash.is_abandoned() => return false,
+                None => thread::sleep(CONTROL_SLEEP),
+            }
+        }
+        *swaps += 1;
     }
-    Action::Noop
+    while let Err(back) = controls.push(control) {
+        if controls.is_abandoned() { return false; }
+        control = back;
+        thread::sleep(CONTROL_SLEEP);
+    }
+    true
 }
 
-fn ipc_action(mut ipc_in: &File) -> Vec<Action> {
-    let mut buf: String = String::new();
-    ipc_in.read_to_string(&mut buf);
-    let mut ipc_iter = buf.split(" ");
+// The control thread owns IPC, anything that reads files and every
+// change to the graph, so the audio thread never waits on a pipe or a
+// disk, and never builds or frees a patch. Actions are still small
+// allocations, made and dropped on whichever thread handles them. It
+// hands finished work to the audio thread, frees what comes back and
+// forwards actions to the client. The first request it prepares is the
+// rate the card runs at.
+fn spawn_control<P>(
+        mut server: Server,
+        mut prepare_f: P,
+        replies: Receiver<Action>,
+        rate: u32) -> AudioQueues
+
+    where P: FnMut(Request) -> Vec<Task> + Send + 'static {
+
+    let (mut controls_tx, controls) = ring::ring(CONTROL_QUEUE);
+    let (to_client, mut from_audio) = ring::ring::<Action>(CLIENT_QUEUE);
+    let (trash_tx, mut trash) = ring::ring::<Box<Patch>>(TRASH_QUEUE);
+
+    thread::spawn(move || {
+        // Without MIDI, notes still come from pt-input
+        let mut midi_in = match midi::open() {
+            Ok(midi_in) => Some(midi_in),
+            Err(e) => { eprintln!("No midi input: {}", e); None },
+        };
+        let mut swaps = 0;
+        let mut requests = vec![Request::Action(Action::SetSampleRate(rate))];
+
+        loop {
+            while trash.pop().is_some() {
+                swaps -= 1;
+            }
+            while let Some(action) = from_audio.pop() {
+                // Recordings and copied regions, kept track of for when
+                // ... the rate changes
+                if let Action::At(_, ref region) = action {
+                    if let Action::AddRegion(_, _, _, _, _, _, ref src) = **region {
+                        requests.push(Request::Region(src.clone()));
+                    }
+                }
+                server.send(&action);
+            }
+            // Replies from work done off this thread, like bounces
+            for action in replies.try_iter() {
+                server.send(&action);
+            }
+
+            let played = midi_in.as_mut().map_or(vec![], midi::read);
+            requests.extend(played.into_iter().map(|(device, action)| Request::Midi(device, action)));
+            requests.extend(ipc_action(&mut server).into_iter().map(Request::Action));
+            let idle = requests.is_empty();
+
+            for request in requests.drain(..) {
+                let exit = match request {
+                    Request::Action(Action::ListMidiPorts) => {
+                        server.send(&Action::MidiPorts(midi_in.as_ref().map_or(vec![], midi::ports)));
+                        continue;
+                    },
+                    Request::Action(Action::Exit) => true,
+                    _ => false,
+                };
+                for task in prepare_f(request).into_iter() {
+                    match task {
+                        Task::Audio(control) => {
+                            if !send_control(&mut controls_tx, &mut trash, &mut swaps, control) { return; }
+                        },
+                        Task::Client(action) => server.send(&action),
+                        Task::Listen(device) => if let Some(midi_in) = midi_in.as_mut() {
+                            if let Err(e) = midi::listen(midi_in, &device) {
+                                eprintln!("Couldn't read from midi input {}: {}", device, e);
+                            }
+                        },
+                    }
+                }
+                if exit { return; }
+            }
+            if idle {
+                thread::sleep(CONTROL_SLEEP);
+            }
+        }
+    });
+
+    AudioQueues { controls, to_client, trash: Trash::new(trash_tx) }
+}
 
+fn ipc_action(server: &mut Server) -> Vec<Action> {
     let mut events: Vec<Action> = Vec::new();
 
-    while let Some(action_raw) = ipc_iter.next() {
-        match action_raw.parse::<Action>() {
-            Ok(Action::Noop) => (),
-            Ok(a) => { events.push(a) },
-            Err(r) => (),
+    // Anything after the last delimiter waits for the next read
+    for event in server.recv() {
+        match event {
+            Event::Received(_, Ok(Action::Noop)) => (),
+            Event::Received(_, Ok(a)) => { events.push(a) },
+            // Skip the token, but let the client know why
+            Event::Received(id, Err(e)) => {
+                eprintln!("{}", e);
+                server.reply(id, &Action::Error(e.to_string()));
+            },
+            Event::Connected(id) => eprintln!("Client {} connected", id),
+            Event::Disconnected(id) => eprintln!("Client {} disconnected", id),
         };
     };
 
@@ -419,61 +838,105 @@ fn ipc_action(mut ipc_in: &File) -> Vec<Action> {
 }
 
 #[cfg(target_os = "linux")]
-pub fn event_loop<F: 'static>(
-        mut ipc_in: File, 
-        mut ipc_client: File, 
-        mut patch: Graph<[Output; CHANNELS], Module>, 
-        mut dispatch_f: F) -> Result<(), Box<error::Error>> 
-    where F: FnMut(&mut Graph<[Output; CHANNELS], Module>, Action) {
+pub fn event_loop<P>(
+        server: Server, 
+        patch: Patch, 
+        prepare_f: P,
+        replies: Receiver<Action>) -> Result<(), Box<error::Error>> 
+    where P: FnMut(Request) -> Vec<Task> + Send + 'static {
     
     // Get audio devices
-    let (audio_dev, rate) = open_audio_dev()?;
+    let (audio_dev, device) = open_audio_dev()?;
+    let converter = Converter::new(device.format, config().dither);
+
+    // Modules are told the rate the card actually runs at, before
+    // the control thread loads anything for them
+    let queues = spawn_control(server, prepare_f, replies, device.rate);
+
+    // The sample type of the mmap follows the negotiated format
+    match device.format {
+        SampleFormat::F32 => play::<f32>(audio_dev, device, converter, queues, patch),
+        SampleFormat::S32 |
+        SampleFormat::S24 => play::<i32>(audio_dev, device, converter, queues, patch),
+        SampleFormat::S16 => play::<i16>(audio_dev, device, converter, queues, patch),
+    }
+}
+
+// Runs on the audio thread, everything else happens on the control thread
+#[cfg(target_os = "linux")]
+fn play<S: OutputSample>(
+        audio_dev: alsa::PCM,
+        device: Negotiated,
+        mut converter: Converter,
+        mut queues: AudioQueues,
+        mut patch: Patch) -> Result<(), Box<error::Error>> {
 
-    // Get midi devices
-    let midi_dev = open_midi_dev()?;
-    let mut midi_input = midi_dev.input();
+    let rate = device.rate;
+    let mut glitches = Glitches::default();
 
     // Create an array of file descriptors to poll
     let mut fds = audio_dev.get()?;
-    fds.append(&mut (&midi_dev, Some(alsa::Direction::Capture)).get()?); 
     
     // Use direct-mode memory mapping for minimum overhead
-    let mut mmap = audio_dev.direct_mmap_playback::<SF>();
+    let mut mmap = audio_dev.direct_mmap_playback::<S>();
     
     // if direct-mode unavailable, use mmap emulation instead
     let mut io = if mmap.is_err() {
-        Some(audio_dev.io_i16()?)
+        Some(audio_dev.io())
     } else { None };
 
-    loop {
+    // One period is computed at a time, and only once the card has
+    // ... taken all of the last one
+    let mut buffer: Vec<[Output; CHANNELS]> = vec![[0.0; CHANNELS]; device.period as usize];
+    let mut samples: Vec<S> = Vec::with_capacity(buffer.len() * CHANNELS);
+    let mut written = 0;
+    let mut since_status = rate;
+    let mut since_levels = 0;
 
-        let ipc_actions: Vec<Action> = ipc_action(&ipc_in);
+    loop {
 
-        match ipc_dispatch(ipc_actions, &mut patch, &mut dispatch_f) {
-            Action::Exit => { return Ok(()) },
-            _ => {}
+        if !dispatch_controls(&mut queues, &mut patch) {
+            return Ok(());
         }
 
-        walk_dispatch(&ipc_client, &mut patch);
-
-        let buffer: &mut [[Output; CHANNELS]] = &mut [[0.0; CHANNELS]; FRAMES as usize];
-
-        dsp::slice::equilibrium(buffer);
+        if written < samples.len() {
+            let mut pending = samples[written..].iter().cloned();
+            let again = if let Ok(ref mut mmap) = mmap {
+                write_samples_direct(&audio_dev, mmap, &mut pending, &mut glitches)?
+            } else if let Some(ref mut io) = io {
+                write_samples_io(&audio_dev, io, &mut pending, &mut glitches)?
+            } else { false };
+            written = samples.len() - pending.len();
+
+            // Nothing to do, let's sleep until woken up by the kernel.
+            if !again && written < samples.len() {
+                alsa::poll::poll(&mut fds, 100)?;
+            }
+            continue;
+        }
 
-        patch.audio_requested(buffer, rate as f64);
+        walk_dispatch(&mut patch.graph, |action| send_client(&mut queues, action, &mut glitches));
 
-        // TODO: float->int sample conversion
-        let mut buf_iter = buffer.iter().map(|a| (a[0]*500.0) as i16);
+        // About once a second, with what is queued on the card right now
+        if since_status >= rate {
+            since_status = 0;
+            let latency = audio_dev.status().map_or(0, |s| s.get_delay().max(0) as Offset);
+            send_client(&mut queues, Action::AudioStatus(rate, device.period, device.buffer, latency), &mut glitches);
+            send_client(&mut queues, Action::Glitches(glitches.xruns, glitches.dropped), &mut glitches);
+        }
+        since_status += device.period;
 
-        if let Ok(ref mut mmap) = mmap {
-            if write_samples_direct(&audio_dev, mmap, &mut buf_iter)? { continue; }
-        } else if let Some(ref mut io) = io {
-            if write_samples_io(&audio_dev, io, &mut buf_iter)? { continue; }
+        if since_levels >= rate / meter::LEVELS_PER_SECOND {
+            since_levels = 0;
+            send_levels(&mut patch, &mut queues, &mut glitches);
         }
+        since_levels += device.period;
+
+        dsp::slice::equilibrium(&mut buffer);
 
-        //if read_midi_event(&mut midi_input, &mut root.synths[0])? { continue; }
+        patch.graph.audio_requested(&mut buffer, rate as f64);
 
-        // Nothing to do, let's sleep until woken up by the kernel.
-        alsa::poll::poll(&mut fds, 100)?;
+        converter.interleave(&buffer, &mut samples);
+        written = 0;
     }
 }
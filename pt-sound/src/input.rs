@@ -0,0 +1,218 @@
+#[cfg(target_os = "linux")]
+extern crate alsa;
+
+#[cfg(target_os = "linux")]
+use alsa::pcm;
+use std::{error, mem};
+use std::thread;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use libcommon::{InputDoc, config};
+
+use crate::core::{Output, CHANNELS};
+use crate::meter;
+#[cfg(target_os = "linux")]
+use crate::convert::{InputSample, SampleFormat, SAMPLE_FORMATS};
+
+/*
+    Line and mic input. A capture thread reads the card a period at a
+    time and hands the frames to one node per anchor, so the module
+    shows up in the patch as In 1, In 2 ... or as In 1-2, In 3-4 when
+    it is stereo. A mono channel is sent to both sides.
+
+    Routing an anchor into a track lets the track record it. The card
+    is opened again when the engine's rate changes, and closed once the
+    nodes are dropped.
+*/
+
+// Periods waiting for the graph before new ones are dropped
+const QUEUE_PERIODS: usize = 4;
+
+type Block = Vec<[Output; CHANNELS]>;
+
+pub struct Store {
+    frames: Receiver<Block>,
+    // Played blocks go back to the capture thread to be filled again,
+    // ... so the audio thread never frees one
+    used: SyncSender<Block>,
+    pending: Block,
+    read: usize,
+    rate: Arc<AtomicU32>,
+    pub level: meter::Level,
+}
+
+// One store per anchor, in the order of InputDoc::anchors
+pub fn open(doc: &InputDoc, sample_rate: u32) -> Vec<Store> {
+    let rate = Arc::new(AtomicU32::new(sample_rate));
+    let layout: Vec<Vec<u16>> = doc.anchors().into_iter().map(|(_, channels)| channels).collect();
+
+    let mut senders = vec![];
+    let stores = layout.iter().map(|_| {
+        let (tx, rx) = mpsc::sync_channel(QUEUE_PERIODS);
+        // Room for every block there is, queued, pending or being filled
+        let (used_tx, used_rx) = mpsc::sync_channel(QUEUE_PERIODS + 2);
+        senders.push((tx, used_rx));
+        Store {
+            frames: rx,
+            used: used_tx,
+            pending: vec![],
+            read: 0,
+            rate: rate.clone(),
+            level: meter::Level::default(),
+        }
+    }).collect();
+
+    let device = if doc.device.is_empty() {
+        config().audio_card.clone()
+    } else {
+        doc.device.clone()
+    };
+    let channels = doc.channels;
+    thread::spawn(move || capture(device, channels, layout, rate, senders));
+    stores
+}
+
+pub fn set_sample_rate(store: &mut Store, sample_rate: u32) {
+    store.rate.store(sample_rate, Ordering::Relaxed);
+}
+
+// Silence until the card has delivered something
+pub fn compute_buf(store: &mut Store, buffer: &mut [[Output; CHANNELS]]) {
+    for frame in buffer.iter_mut() {
+        while store.read >= store.pending.len() {
+            match store.frames.try_recv() {
+                Ok(block) => {
+                    let used = mem::replace(&mut store.pending, block);
+                    let _ = store.used.try_send(used);
+                    store.read = 0;
+                },
+                Err(_) => break,
+            }
+        }
+        *frame = match store.pending.get(store.read) {
+            Some(captured) => { store.read += 1; *captured },
+            None => [0.0; CHANNELS],
+        };
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn open_capture(device: &str, channels: u16, rate: u32) -> Result<(alsa::PCM, SampleFormat, usize), Box<error::Error>> {
+    let devname = format!("hw:{}", device);
+    let p = alsa::PCM::new(&devname, alsa::Direction::Capture, false)?;
+
+    let format = {
+        let hwp = pcm::HwParams::any(&p)?;
+        hwp.set_channels(u32::from(channels))?;
+        hwp.set_rate(rate, alsa::ValueOr::Nearest)?;
+        let format = SAMPLE_FORMATS.iter().cloned()
+            .find(|f| hwp.set_format(f.alsa()).is_ok())
+            .ok_or("No supported sample format (S16, S24, S32 or F32)")?;
+        hwp.set_access(pcm::Access::RWInterleaved)?;
+        hwp.set_period_size_near(i64::from(config().period_size), alsa::ValueOr::Nearest)?;
+        p.hw_params(&hwp)?;
+        format
+    };
+
+    let period = {
+        let hwp = p.hw_params_current()?;
+        if hwp.get_rate()? != rate {
+            eprintln!("{} captures at {}Hz, not {}Hz", devname, hwp.get_rate()?, rate);
+        }
+        println!("Opened audio input {:?} as {:?} with parameters: {:?}", devname, format, hwp);
+        hwp.get_period_size()? as usize
+    };
+    Ok((p, format, period))
+}
+
+// Returns true when the card should be opened again at a new rate
+#[cfg(target_os = "linux")]
+fn read<S: InputSample>(
+        p: &alsa::PCM,
+        format: SampleFormat,
+        channels: usize,
+        period: usize,
+        layout: &[Vec<u16>],
+        rate: &AtomicU32,
+        senders: &[(SyncSender<Block>, Receiver<Block>)]) -> Result<bool, Box<error::Error>> {
+
+    let opened = rate.load(Ordering::Relaxed);
+    // Unchecked like playback, S24 has no type of its own for alsa to check
+    let io = p.io();
+    let width = mem::size_of::<S>();
+    let mut bytes: Vec<u8> = vec![0; period * channels * width];
+    let mut samples: Vec<S> = Vec::with_capacity(period * channels);
+
+    loop {
+        if rate.load(Ordering::Relaxed) != opened {
+            return Ok(true);
+        }
+        let frames = match io.readi(&mut bytes) {
+            Ok(n) => n,
+            Err(e) => {
+                println!("Recovering input from {}", e);
+                if let Some(errno) = e.errno() {
+                    p.recover(errno as std::os::raw::c_int, true)?;
+                }
+                continue;
+            }
+        };
+        samples.clear();
+        samples.extend(bytes[..frames * channels * width].chunks_exact(width).map(S::read_ne));
+
+        for ((tx, used), anchor) in senders.iter().zip(layout.iter()) {
+            let mut block = used.try_recv().unwrap_or_default();
+            block.clear();
+            block.extend(samples.chunks(channels).map(|frame| {
+                let left = format.unscale(frame[anchor[0] as usize]);
+                let right = anchor.get(1).map_or(left, |c| format.unscale(frame[*c as usize]));
+                [left, right]
+            }));
+            match tx.try_send(block) {
+                // A graph that isn't playing doesn't keep up, that's fine
+                Ok(()) | Err(TrySendError::Full(_)) => {},
+                Err(TrySendError::Disconnected(_)) => return Ok(false),
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn capture(
+        device: String,
+        channels: u16,
+        layout: Vec<Vec<u16>>,
+        rate: Arc<AtomicU32>,
+        senders: Vec<(SyncSender<Block>, Receiver<Block>)>) {
+
+    loop {
+        let result = open_capture(&device, channels, rate.load(Ordering::Relaxed)).and_then(|(p, format, period)| {
+            let channels = channels as usize;
+            match format {
+                SampleFormat::F32 => read::<f32>(&p, format, channels, period, &layout, &rate, &senders),
+                SampleFormat::S32 |
+                SampleFormat::S24 => read::<i32>(&p, format, channels, period, &layout, &rate, &senders),
+                SampleFormat::S16 => read::<i16>(&p, format, channels, period, &layout, &rate, &senders),
+            }
+        });
+        match result {
+            Ok(true) => continue,
+            Ok(false) => return,
+            Err(e) => {
+                eprintln!("couldn't capture from hw:{}: {}", device, e);
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn capture(
+        device: String,
+        _channels: u16,
+        _layout: Vec<Vec<u16>>,
+        _rate: Arc<AtomicU32>,
+        _senders: Vec<(SyncSender<Block>, Receiver<Block>)>) {
+    eprintln!("couldn't capture from {}: audio input needs ALSA", device);
+}
@@ -12,329 +12,599 @@ mod tape;
 mod chord;
 mod arpeggio;
 mod plugin;
+mod convert;
+mod resample;
+mod offline;
+mod bounce;
+mod input;
+mod master;
+mod meter;
 
-use std::{iter, error};
-use std::fs::{OpenOptions, File};
-use std::os::unix::fs::OpenOptionsExt;
+use std::{iter, error, fs};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+use std::thread;
 use std::io::prelude::*;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::borrow::BorrowMut;
-use dsp::{NodeIndex, Frame, FromSample, Graph, Node, Sample, Walker};
-use xmltree::Element;
+use dsp::{NodeIndex, Frame, FromSample, Sample, Walker};
 use sample::signal;
-use libcommon::{Action, Key, Document, read_document, param_map};
+use libcommon::{Action, Document, ModuleDoc, BindingDoc, Param, KEYBED, read_document, bind};
+use libcommon::{Server, Config, config, read_script};
 
-use crate::core::{event_loop, Module, Output, CHANNELS};
+use crate::core::{event_loop, Assets, Control, Module, Patch, Request, Task};
 const MASTER_ROUTE_ID: u16 = 1;
 
-fn add_module(
-    id: u16,
-    el: &mut Element,
-    patch: &mut Graph<[Output; CHANNELS], Module>, 
-    routes: &mut HashMap<u16, NodeIndex>, 
-    operators: &mut HashMap<u16, NodeIndex>) {
-        match &el.name[..] {
-            "timeline" => {
-                let mut anchors: Vec<NodeIndex> = vec![];
-
-                // We need to load the tracks in order of their track ID
-                // otherwise the patch will connect the wrong anchors
-                let mut sorted_tracks = vec![];
-
-                // Mutate el by removing track elements until none are left
-                while let Some(store) = tape::read(el) {
-                    sorted_tracks.push(store);
-                }
-                // Sort tracks
-                sorted_tracks.sort_by(|a, b| a.track_id.partial_cmp(&b.track_id).unwrap());
-                for track in sorted_tracks.drain(0..) {
-                    let tape = patch.add_node(Module::Tape(track));
-                    anchors.push(tape);
-                    anchors.push(tape);
-                }
+// Everything a module needs from disk, loaded off the audio thread.
+// ... Timelines are written at project_rate and played at sample_rate
+fn load_module(module: &ModuleDoc, dir: &Path, project_rate: u32, sample_rate: u32) -> Result<Vec<Module>, String> {
+    Ok(match module {
+        // Tracks are sorted by ID, otherwise the patch 
+        // ... will connect the wrong anchors
+        ModuleDoc::Timeline(timeline) => timeline.tracks.iter()
+            .map(|track| Module::Tape(tape::read(timeline, track, dir, project_rate, sample_rate)))
+            .collect(),
+        ModuleDoc::Hammond(hammond) => vec![Module::Synth(synth::read(hammond, sample_rate))],
+        ModuleDoc::Arpeggio(arpeggio) => vec![Module::Arpeggio(arpeggio::read(arpeggio, sample_rate))],
+        ModuleDoc::Chord(chord) => vec![Module::Chord(chord::read(chord))],
+        ModuleDoc::Keyboard(keyboard) => vec![Module::Octave(vec![], keyboard.octave)],
+        ModuleDoc::Patch(_) => vec![],
+        ModuleDoc::Plugin(plugin) => {
+            let src = config().modules.join(format!("{}.so", plugin.name));
+            vec![Module::Plugin(plugin::init(src.to_string_lossy().to_string(), sample_rate)?)]
+        },
+        ModuleDoc::Input(input_doc) => input::open(input_doc, sample_rate).into_iter()
+            .map(Module::Input)
+            .collect(),
+    })
+}
 
-                let operator = patch.add_node(Module::Operator(vec![], 
-                    anchors.clone(), id.clone()
-                ));
-                // Because each track is stored as two anchors,
-                // ... we need to make sure there is only one edge
-                // ... to each track, otherwise actions will be 
-                // ... dispatched two times. :^)
-                for anchor in anchors.iter() {
-                    if patch.find_connection(operator, *anchor).is_none() {
-                        patch.add_connection(operator, *anchor);
-                    }
-                }
-                operators.insert(id, operator);
-            },
-            "hammond" => {
-                let store = match synth::read(el) {
-                    Some(a) => a,
-                    None => panic!("Invalid module {}", id)
-                };
-                let instrument = patch.add_node(Module::Synth(store));
-                let operator = patch.add_node(Module::Operator(vec![], 
-                    vec![instrument, instrument], id.clone()
-                ));
-                patch.add_connection(operator, instrument);
-                operators.insert(id, operator);
-            },
-            "arpeggio" => {
-                let store = match arpeggio::read(el) {
-                    Some(a) => a,
-                    None => panic!("Invalid module {}", id)
-                };
-                let inst = patch.add_node(Module::Arpeggio(store));
-                let operator = patch.add_node(Module::Operator(vec![], 
-                    vec![inst, inst], id.clone()
-                ));
-                patch.add_connection(operator, inst);
-                operators.insert(id, operator);
-            },
-            "chord" => {
-                let store = chord::read(el).unwrap();
-                let inst = patch.add_node(Module::Chord(store));
-                let operator = patch.add_node(Module::Operator(vec![], 
-                    vec![inst, inst], id.clone()
-                ));
-                patch.add_connection(operator, inst);
-                operators.insert(id, operator);
-            },
-            "keyboard" => {
-                let (_, params) = param_map(el);
-                let shift = *params.get("octave").unwrap_or(&3.0) as Key;
-                let octave = patch.add_node(Module::Octave(vec![], shift));
-                //let shift = patch.add_node(Module::Octave(vec![], 4));
-                let operator = patch.add_node(Module::Operator(vec![], 
-                    vec![octave, octave], id.clone()
-                ));
-                patch.add_connection(operator, octave);
-                operators.insert(id, operator);
-            },
+// Loads every module of a project, in document order. Modules that
+// ... can't be loaded are left out, with the reason why
+fn load_project(doc: &Document, sample_rate: u32) -> (Vec<(u16, ModuleDoc, Vec<Module>)>, Vec<String>) {
+    let mut loaded = vec![];
+    let mut failed = vec![];
+    let dir = doc.dir();
+    for (id, module) in doc.modules.iter() {
+        match load_module(module, &dir, doc.sample_rate, sample_rate) {
+            Ok(nodes) => loaded.push((*id, module.clone(), nodes)),
+            Err(e) => failed.push(format!("Could not load module {}: {}", id, e)),
+        }
+    }
+    (loaded, failed)
+}
+
+// Sources of the assets loaded tapes play
+fn asset_srcs(loaded: &[Module]) -> Vec<String> {
+    loaded.iter()
+        .flat_map(|m| match m {
+            Module::Tape(store) => store.audio_regions.iter().map(|r| r.asset_src.clone()).collect::<Vec<_>>(),
+            _ => vec![],
+        })
+        .collect()
+}
+
+// Wires a loaded module into the patch behind an operator
+fn add_module(id: u16, module: &ModuleDoc, loaded: Vec<Module>, patch: &mut Patch) {
+
+        let nodes: Vec<NodeIndex> = loaded.into_iter().map(|m| patch.graph.add_node(m)).collect();
+        let anchors: Vec<NodeIndex> = match module {
             // This module should always be last in doc.modules or else 
             // operators and routes maps won't be completely filled
-            "patch" => {
-                while let Some(mut route_el) = el.take_child("route") {
-                    let r_id = route_el.attributes.get("id").unwrap();
-                    let _r_id = r_id.parse::<u16>().unwrap();
-                    let route = patch.add_node(Module::Passthru(vec![]));
-                    routes.insert(_r_id, route);
-                    while let Some(input) = route_el.take_child("input") {
-                        let m_id = input.attributes.get("module").unwrap();
-                        let _m_id = m_id.parse::<u16>().unwrap();
-
-                        let io_id = input.attributes.get("index").unwrap();
-                        let _io_id = io_id.parse::<usize>().unwrap();
-                        
-                        let op_id = operators.get(&_m_id).unwrap();
-
-                        let in_id = match &patch[*op_id] {
-                            Module::Operator(_, anchors, _) => anchors[_io_id],
-                            _ => panic!("No such input {}", io_id)
-                        };
-                        patch.add_connection(route, in_id);
-                    }
-                    while let Some(output) = route_el.take_child("output") {
-                        let m_id = output.attributes.get("module").unwrap();
-                        let _m_id = m_id.parse::<u16>().unwrap();
-                        
-                        let io_id = output.attributes.get("index").unwrap();
-                        let _io_id = io_id.parse::<usize>().unwrap();
-
-                        let op_id = operators.get(&_m_id).unwrap();
-
-                        let out_id = match &patch[*op_id] {
-                            Module::Operator(_, anchors, _) => anchors[_io_id],
-                            _ => panic!("No such output {}", io_id)
-                        };
-                        patch.add_connection(out_id ,route);
+            ModuleDoc::Patch(patch_doc) => {
+                for route_doc in patch_doc.routes.iter() {
+                    let route = patch.graph.add_node(Module::Passthru(vec![], meter::Level::default()));
+                    patch.routes.insert(route_doc.id, route);
+                    for anchor in route_doc.anchors.iter() {
+                        if let Err(reason) = patch_anchor(anchor.module, anchor.index, route_doc.id, 
+                            anchor.input, patch) {
+                            eprintln!("Skipped patch from module {}: {}", anchor.module, reason);
+                        }
                     }
                 }
+                return;
             },
-            plugin => {
-                // FIXME: use current directory
-                let store = plugin::init(format!("./modules/{}.so", plugin));
-                let plugin = patch.add_node(Module::Plugin(store));
-                let operator = patch.add_node(Module::Operator(vec![], 
-                    vec![plugin, plugin], id.clone()
-                ));
-                patch.add_connection(operator, plugin);
-                operators.insert(id, operator);
-            },
-            name @ _ => { eprintln!("Unimplemented module {:?}", name)}
+            // Each track is an input and an output
+            ModuleDoc::Timeline(_) => nodes.iter().flat_map(|n| vec![*n, *n]).collect(),
+            // Inputs only have outputs
+            ModuleDoc::Input(_) => nodes.clone(),
+            // Instruments and effects are one node for input and output
+            _ => vec![nodes[0], nodes[0]],
+        };
+
+        let operator = patch.graph.add_node(Module::Operator(vec![], 
+            anchors, id.clone()
+        ));
+        // Anchors list most nodes twice, but there must only
+        // ... be one edge to each, otherwise actions will be 
+        // ... dispatched two times. :^)
+        for node in nodes.iter() {
+            let _ = patch.graph.add_connection(operator, *node);
         }
+        patch.operators.insert(id, operator);
     }
 
+// Builds the patch for a loaded project
+fn open_project(modules: Vec<(u16, ModuleDoc, Vec<Module>)>, master: master::Store) -> Patch {
+    let mut patch = Patch::new();
+    for (id, module, loaded) in modules.into_iter() {
+        add_module(id, &module, loaded, &mut patch);
+    }
+    let root = patch.graph.add_node(Module::Master(master));
+    patch.graph.set_master(Some(root));
+
+    // Make sure we always have a master route 
+    if !patch.routes.contains_key(&MASTER_ROUTE_ID) {
+        let route = patch.graph.add_node(Module::Passthru(vec![], meter::Level::default()));
+        patch.routes.insert(MASTER_ROUTE_ID, route);
+    }
+
+    let _ = patch.graph.add_connection(patch.routes[&MASTER_ROUTE_ID], root);
+    patch
+}
+
+// Connects a module's anchor to a route, or leaves the patch untouched
+// ... and says why it couldn't
+fn patch_anchor(
+    n_id: u16,
+    a_id: u16,
+    r_id: u16,
+    input: bool,
+    patch: &mut Patch) -> Result<(), String> {
+
+    let route = *patch.routes.get(&r_id).ok_or(format!("no route {}", r_id))?;
+    let operator = *patch.operators.get(&n_id).ok_or(format!("no module {}", n_id))?;
+    let anchor = match &patch.graph[operator] {
+        Module::Operator(_, anchors, _) => anchors.get(a_id as usize).cloned(),
+        _ => None,
+    }.ok_or(format!("module {} has no anchor {}", n_id, a_id))?;
+
+    if patch.graph.find_connection(route, anchor).is_some() ||
+        patch.graph.find_connection(anchor, route).is_some() {
+        return Ok(());
+    }
+    let connected = if input {
+        patch.graph.add_connection(route, anchor)
+    } else {
+        patch.graph.add_connection(anchor, route)
+    };
+    // A refused edge is never added
+    connected.map(|_| ()).map_err(|_| format!(
+        "anchor {} of module {} to route {} would make a cycle", a_id, n_id, r_id))
+}
+
+// Takes nodes out of the patch. Removing a node moves the last node into
+// ... its index, so every index we keep that pointed at the last node is
+// ... moved too.
+fn remove_nodes(mut nodes: Vec<NodeIndex>, patch: &mut Patch) {
+
+    // Highest first, so the node that moves is never one we remove
+    nodes.sort();
+    nodes.dedup();
+    for idx in nodes.into_iter().rev() {
+        let last = NodeIndex::new(patch.graph.node_count() - 1);
+        let master = patch.graph.master_index();
+        patch.graph.remove_node(idx);
+        if last == idx { continue; }
+
+        let moved = |n: &mut NodeIndex| if *n == last { *n = idx };
+        patch.routes.values_mut().for_each(moved);
+        patch.operators.values_mut().for_each(moved);
+        for node in patch.graph.nodes_mut() {
+            if let Module::Operator(_, anchors, _) = node {
+                anchors.iter_mut().for_each(moved);
+            }
+        }
+        patch.graph.set_master(master.map(|m| if m == last { idx } else { m }));
+    }
+}
+
+// A patch that stands in for this one node for node and edge for edge,
+// ... to be changed and swapped in. Operators are remade since their
+// ... anchors are needed here, everything else stays where it plays.
+fn stand_in(patch: &Patch) -> Patch {
+    let mut next = Patch::new();
+    for i in 0..patch.graph.node_count() {
+        let node = NodeIndex::new(i);
+        next.graph.add_node(match &patch.graph[node] {
+            Module::Operator(_, anchors, id) => Module::Operator(vec![], anchors.clone(), *id),
+            _ => Module::Live(node),
+        });
+    }
+    for i in 0..patch.graph.node_count() {
+        let node = NodeIndex::new(i);
+        let mut outs = patch.graph.outputs(node);
+        while let Some(out) = outs.next_node(&patch.graph) {
+            let _ = next.graph.add_connection(node, out);
+        }
+    }
+    next.graph.set_master(patch.graph.master_index());
+    next.routes = patch.routes.clone();
+    next.operators = patch.operators.clone();
+    next
+}
+
+// The playing patch with every asset converted to rate, for the tapes
+// ... to play once it's swapped in
+fn reload(shape: &Patch, srcs: &HashSet<String>, rate: u32) -> Patch {
+    let mut frames = HashMap::new();
+    for src in srcs.iter() {
+        match resample::load(src, rate) {
+            Ok(loaded) => { frames.insert(src.clone(), Arc::new(tape::chunk(loaded))); },
+            Err(e) => eprintln!("couldn't reload asset {}: {}", src, e),
+        }
+    }
+    // At most one set of frames per asset on each tape is let go of
+    let replaced = Vec::with_capacity(frames.len() * shape.graph.node_count());
+    let mut next = stand_in(shape);
+    next.assets = Some(Assets { frames, replaced });
+    next
+}
+
+// Sends the next patch to be swapped in, remembering what it looks like
+fn commit(shape: &mut Patch, next: Patch) -> Task {
+    *shape = stand_in(&next);
+    Task::Audio(Control::Swap(Box::new(next)))
+}
+
+// An action for the audio thread
+fn audio(action: Action) -> Task {
+    Task::Audio(Control::Action(action))
+}
+
+// Hands what a device played to the note sources playing from it, which
+// ... send it on through whatever routes they're patched to
+fn play(device: &str, action: Action, sources: &HashMap<u16, String>) -> Vec<Task> {
+    sources.iter()
+        .filter(|(_, d)| d.as_str() == device)
+        .map(|(id, _)| audio(Action::At(*id, Box::new(action.clone()))))
+        .collect()
+}
+
+// The param waiting for the next controller moved, and its module
+type Learning = Option<(u16, String, Param, Param)>;
+
+// Moves the params bound to a controller, binding it first if a param
+// ... is learning. The client is sent the controller to move its views.
+fn control(
+    cc: u8,
+    value: u8,
+    bindings: &mut HashMap<u16, Vec<BindingDoc>>,
+    learning: &mut Learning) -> Vec<Task> {
+
+    let mut tasks = vec![];
+    if let Some((m_id, param, min, max)) = learning.take() {
+        bind(bindings.entry(m_id).or_insert_with(Vec::new), 
+            BindingDoc { cc, param: param.clone(), min, max });
+        tasks.push(Task::Client(Action::At(m_id, Box::new(Action::BindParam(cc, param, min, max)))));
+    }
+    for (m_id, bound) in bindings.iter() {
+        let mut moved = false;
+        for binding in bound.iter().filter(|b| b.cc == cc) {
+            let set = Action::SetParam(binding.param.clone(), binding.scale(value));
+            tasks.push(audio(Action::At(*m_id, Box::new(set))));
+            moved = true;
+        }
+        if moved {
+            tasks.push(Task::Client(Action::At(*m_id, Box::new(Action::ControlChange(cc, value)))));
+        }
+    }
+    tasks
+}
+
 fn main() -> Result<(), Box<error::Error>> {
 
-    // Blocked by pt-client reader
-    println!("Waiting for pt-client...");
+    let mut args: Vec<String> = std::env::args().collect();
 
-    // Configure pt-client IPC
-    let mut ipc_client = match OpenOptions::new() 
-        .write(true)
-        .open("/tmp/pt-client") {
-            Ok(a) => a,
-            Err(_) => panic!("Could not open /tmp/pt-client")
-        };
+    // Render a script offline instead of playing
+    let render = offline::take_arg(&mut args, "render");
+    let out = offline::take_arg(&mut args, "out").unwrap_or_else(|| offline::DEFAULT_OUT.to_string());
 
-    let mut ipc_in = match OpenOptions::new()
-        .custom_flags(libc::O_NONBLOCK)
-        .read(true)
-        .open("/tmp/pt-sound") {
-            Ok(a) => a,
-            Err(_) => panic!("Could not open /tmp/pt-sound")
-        };
+    let config = config::init(Config::load(&args)?);
 
     // Construct our dsp graph.
-    let mut graph = Graph::new();
-
-    let mut operators: HashMap<u16, NodeIndex> = HashMap::new();
-    let mut routes: HashMap<u16, NodeIndex> = HashMap::new();
+    let mut patch = Patch::new();
 
     // Make a master route available without a project
-    let master_node = graph.add_node(Module::Master);
-    let master_route = graph.add_node(Module::Master);
-    routes.insert(MASTER_ROUTE_ID, master_route);
-    graph.add_connection(master_route, master_node);
-    graph.set_master(Some(master_node));
-
-    event_loop(ipc_in, ipc_client, graph, move |mut patch, a| { 
-        // ROOT DISPATCH
+    let master_node = patch.graph.add_node(Module::Master(master::init(config.sample_rate)));
+    let master_route = patch.graph.add_node(Module::Passthru(vec![], meter::Level::default()));
+    patch.routes.insert(MASTER_ROUTE_ID, master_route);
+    let _ = patch.graph.add_connection(master_route, master_node);
+    patch.graph.set_master(Some(master_node));
+
+    // What the playing patch looks like, every change is made to a
+    // ... stand in for it and swapped in whole
+    let mut shape = stand_in(&patch);
+
+    // Bounces run on their own thread and report back through here
+    let (replies_tx, replies) = mpsc::channel();
+
+    // Until the card tells us otherwise
+    let mut sample_rate = config.sample_rate;
+    // Kept when another project is opened
+    let mut master_gain = 0.0;
+    // Where new recordings go, and the rate its timeline is written at
+    let mut project_dir = PathBuf::new();
+    let mut project_rate = config.sample_rate;
+    // Every asset the tapes play, converted again when the rate changes
+    let mut assets: HashSet<String> = HashSet::new();
+
+    // Note sources by module ID, with the device each one plays from
+    let mut sources: HashMap<u16, String> = HashMap::new();
+
+    // Controllers bound to params by module ID
+    let mut bindings: HashMap<u16, Vec<BindingDoc>> = HashMap::new();
+    let mut learning: Learning = None;
+
+    // CONTROL DISPATCH
+    // Runs on the control thread, anything that reads files or changes
+    // ... the graph is done here and only the result goes to the audio
+    // ... thread
+    let mut prepare = move |request: Request| -> Vec<Task> {
         // n_id Node ID
         // r_id Route ID
         // a_id Anchor ID (Any input or output from a module)
         // op_id Module Operator ID (Dispatches to a cluster of nodes)
         // m_id Module ID (Key of operators)
 
+        let a = match request {
+            Request::Action(a) => a,
+            Request::Midi(device, action) => {
+                let mut tasks = vec![];
+                if let Action::ControlChange(cc, value) = action {
+                    tasks = control(cc, value, &mut bindings, &mut learning);
+                }
+                tasks.extend(play(&device, action, &sources));
+                return tasks;
+            },
+            Request::Region(src) => {
+                assets.insert(src);
+                return vec![];
+            },
+        };
+
         match a {
+            Action::OpenProject(name) => {
+                let doc = match read_document(name.clone()) {
+                    Ok(doc) => doc,
+                    Err(e) => {
+                        eprintln!("Could not open {}: {}", name, e);
+                        return vec![];
+                    }
+                };
+                for migration in doc.migrations.iter() {
+                    eprintln!("Upgraded {} {}", name, migration);
+                }
+                for relink in doc.relinks.iter() {
+                    eprintln!("{}: {}", name, relink);
+                }
+                if doc.sample_rate != sample_rate {
+                    eprintln!("{} was made at {}Hz, playing at {}Hz", name, doc.sample_rate, sample_rate);
+                }
+                project_dir = doc.dir();
+                project_rate = doc.sample_rate;
+                let (modules, failed) = load_project(&doc, sample_rate);
+                let mut tasks: Vec<Task> = failed.into_iter()
+                    .map(|e| Task::Client(Action::Error(e)))
+                    .collect();
+
+                sources = modules.iter()
+                    .filter_map(|(id, module, _)| module.device().map(|d| (*id, d.to_string())))
+                    .collect();
+                bindings = modules.iter()
+                    .map(|(id, module, _)| (*id, module.bindings().to_vec()))
+                    .collect();
+                learning = None;
+                assets = modules.iter().flat_map(|(_, _, loaded)| asset_srcs(loaded)).collect();
+                tasks.extend(sources.values().map(|d| Task::Listen(d.clone())));
+
+                let mut master = master::init(sample_rate);
+                master::dispatch(&mut master, Action::SetMasterGain(master_gain));
+                let next = open_project(modules, master);
+                eprintln!("Loaded {} with {} Nodes", name, next.graph.node_count());
+                eprintln!("Loaded {} Edges", next.graph.connection_count());
+                tasks.push(commit(&mut shape, next));
+                tasks
+            },
+            Action::AddModule(id, name) => {
+                let module = ModuleDoc::new(&name);
+                let loaded = match load_module(&module, &project_dir, project_rate, sample_rate) {
+                    Ok(loaded) => loaded,
+                    Err(e) => return vec![Task::Client(Action::Error(format!("Could not add {}: {}", name, e)))],
+                };
+                let mut tasks = vec![];
+                if let Some(d) = module.device() {
+                    sources.insert(id, d.to_string());
+                    tasks.push(Task::Listen(d.to_string()));
+                }
+                let mut next = stand_in(&shape);
+                add_module(id, &module, loaded, &mut next);
+                eprintln!("Currently {} Nodes", next.graph.node_count());
+                eprintln!("Currently {} Edges", next.graph.connection_count());
+                tasks.push(commit(&mut shape, next));
+                tasks
+            },
+            Action::Bounce(project, stems, looped) => {
+                let progress = replies_tx.clone();
+                // Heard as it plays now
+                let gain = master_gain;
+                thread::spawn(move || {
+                    match bounce::bounce(&project, stems, looped, gain, progress.clone()) {
+                        Ok(paths) => for path in paths.iter() {
+                            eprintln!("Bounced {} to {}", project, path.display());
+                        },
+                        Err(e) => {
+                            eprintln!("Could not bounce {}: {}", project, e);
+                            let _ = progress.send(Action::Error(format!("Could not bounce {}: {}", project, e)));
+                        },
+                    }
+                });
+                vec![]
+            },
             Action::At(n_id, action) => {
                 match *action {
                     Action::AddTrack(t_id) => {
-                        if let Some(id) = operators.get(&n_id) {
-                            let new_tape = patch.add_node(Module::Tape(tape::init(t_id)));
-                            patch.add_connection(*id, new_tape);
-                            match patch[*id] {
-                                Module::Operator(_, ref mut anchors, _) => {
-                                    anchors.push(new_tape); // INPUT
-                                    anchors.push(new_tape); // OUTPUT
-                                },
-                                _ => {}
-                            }
+                        let operator = match shape.operators.get(&n_id) {
+                            Some(operator) => *operator,
+                            None => return vec![],
+                        };
+                        let mut next = stand_in(&shape);
+                        let new_tape = next.graph.add_node(Module::Tape(tape::init(t_id, &project_dir, project_rate, sample_rate)));
+                        let _ = next.graph.add_connection(operator, new_tape);
+                        match next.graph[operator] {
+                            Module::Operator(_, ref mut anchors, _) => {
+                                anchors.push(new_tape); // INPUT
+                                anchors.push(new_tape); // OUTPUT
+                            },
+                            _ => {}
                         }
+                        vec![commit(&mut shape, next)]
                     },
-                    direct_action => {
-                        if let Some(id) = operators.get(&n_id) {
-                            patch[*id].dispatch(direct_action)
+                    Action::SetDevice(device) => {
+                        if !shape.operators.contains_key(&n_id) { return vec![]; }
+                        if device.is_empty() {
+                            sources.remove(&n_id);
+                            vec![]
+                        } else {
+                            sources.insert(n_id, device.clone());
+                            vec![Task::Listen(device)]
                         }
-                    }
+                    },
+                    Action::LearnParam(param, min, max) => {
+                        if shape.operators.contains_key(&n_id) {
+                            learning = Some((n_id, param, min, max));
+                        }
+                        vec![]
+                    },
+                    Action::ForgetParam(param) => {
+                        if learning.as_ref().is_some_and(|(m_id, p, _, _)| *m_id == n_id && *p == param) {
+                            learning = None;
+                        }
+                        if let Some(bound) = bindings.get_mut(&n_id) {
+                            bound.retain(|b| b.param != param);
+                        }
+                        vec![]
+                    },
+                    direct_action => vec![audio(Action::At(n_id, Box::new(direct_action)))],
                 }
             },
-            Action::SetMeter(_, _) |
-            Action::SetTempo(_) => {
-                for (_, node) in operators.iter() {
-                    patch[*node].dispatch(a.clone())
+            Action::SetSampleRate(rate) => {
+                // Converted here, the tapes swap them in right after
+                let mut tasks = vec![audio(a)];
+                if rate != sample_rate && !assets.is_empty() {
+                    let next = reload(&shape, &assets, rate);
+                    tasks.push(commit(&mut shape, next));
                 }
+                sample_rate = rate;
+                tasks
+            },
+            Action::SetMasterGain(db) => {
+                master_gain = db;
+                vec![audio(a)]
             },
+            Action::SetMeter(_, _) |
+            Action::SetTempo(_) |
+            Action::Exit => vec![audio(a)],
+            // Notes from pt-input are played on the keybed
             Action::NoteOn(_,_) | Action::NoteOff(_) | Action::Octave(_) => {
-                if let Some(id) = operators.get(&104) {
-                    patch[*id].dispatch(a)
-                }
+                play(KEYBED, a, &sources)
             },
             Action::AddRoute(r_id) => {
-                let route = patch.add_node(Module::Passthru(vec![]));
-                routes.insert(r_id, route);
-            },
-            Action::PatchIn(n_id, a_id, r_id) => {
-                if let Some(route) = routes.get(&r_id) {
-                    match &patch[*operators.get(&n_id).unwrap()] {
-                        Module::Operator(_, anchors, _) => {
-                            let input = anchors[a_id as usize];
-                            if let Err(e) = &patch.add_connection(*route, input) {
-                                println!("CYCLE");
-                            }
-                        }
-                        _ => {}
-                    };
-                }
+                let mut next = stand_in(&shape);
+                let route = next.graph.add_node(Module::Passthru(vec![], meter::Level::default()));
+                next.routes.insert(r_id, route);
+                vec![commit(&mut shape, next)]
             },
+            Action::PatchIn(n_id, a_id, r_id) |
             Action::PatchOut(n_id, a_id, r_id) => {
-                if let Some(route) = routes.get(&r_id) {
-                    match &patch[*operators.get(&n_id).unwrap()] {
-                        Module::Operator(_, anchors, _) => {
-                            let output = anchors[a_id as usize];
-                            if let Err(e) = &patch.add_connection(output, *route) {
-                                println!("CYCLE");
-                            }
-                        }
-                        _ => {}
-                    };
+                let input = if let Action::PatchIn(_, _, _) = a { true } else { false };
+                let mut next = stand_in(&shape);
+                match patch_anchor(n_id, a_id, r_id, input, &mut next) {
+                    Ok(()) => vec![commit(&mut shape, next)],
+                    Err(reason) => vec![Task::Client(Action::PatchRefused(n_id, a_id, r_id, reason))],
                 }
             },
             Action::DelPatch(n_id, a_id, input) => {
-                match &patch[*operators.get(&n_id).unwrap()] {
-                    Module::Operator(_, anchors, _) => {
+                let operator = match shape.operators.get(&n_id) {
+                    Some(operator) => *operator,
+                    None => return vec![],
+                };
+                let mut next = stand_in(&shape);
+                match &next.graph[operator] {
+                    Module::Operator(_, anchors, _) if (a_id as usize) < anchors.len() => {
                         let id = anchors[a_id as usize].clone();
-                        for (_, route) in routes.iter() {
+                        for (_, route) in shape.routes.iter() {
                             let edge = if input {
-                                patch.find_connection(*route, id)
+                                next.graph.find_connection(*route, id)
                             } else {
-                                patch.find_connection(id, *route)
+                                next.graph.find_connection(id, *route)
                             };
                             if let Some(e) = edge {
-                                patch.remove_edge(e);
+                                next.graph.remove_edge(e);
                             }
                         }
                     }
                     _ => {}
                 }
+                vec![commit(&mut shape, next)]
             },
-            Action::OpenProject(name) => {
-                *patch = Graph::new();
-                operators = HashMap::new();
-                routes = HashMap::new();
-                let mut doc = read_document(name);
-                for (id, el) in doc.modules.iter_mut() {
-                    add_module(*id, el, patch, &mut routes, &mut operators);
-                }
-                let root = patch.add_node(Module::Master);
-                patch.set_master(Some(root));
-
-                // Make sure we always have a master route 
-                if !routes.contains_key(&MASTER_ROUTE_ID) {
-                    routes.insert(MASTER_ROUTE_ID, 
-                        patch.add_node(Module::Passthru(vec![])));
-                }
-
-                patch.add_connection(*routes.get(&MASTER_ROUTE_ID).unwrap(), root);
-                eprintln!("Loaded {} Nodes", patch.node_count());
-                eprintln!("Loaded {} Edges", patch.connection_count());
-            },
-            Action::AddModule(id, name) => {
-                let mut new_el = Element::new(&name);
-                new_el.attributes.insert("id".to_string(), id.to_string());
-                add_module(id, &mut new_el, patch, &mut routes, &mut operators);
-                eprintln!("Currently {} Nodes", patch.node_count());
-                eprintln!("Currently {} Edges", patch.connection_count());
-            }
             Action::DelModule(id) => {
-                // Because removing a node from the graph will cause indicies to
-                // ... shift, we're just going to lazily remove all edges on the
-                // ... node cluster but leave the nodes there.
-                if let Some(operator) = operators.remove(&id) {
-                    let mut module_cluster = patch.outputs(operator);
-                    while let Some(output_idx) = module_cluster.next_node(&patch) {
-                        patch.remove_all_output_connections(output_idx);
-                        patch.remove_all_input_connections(output_idx);
-                    }
+                // The operator and every node it dispatches to, along
+                // ... with their edges. They are freed when the patch
+                // ... they played in comes back.
+                sources.remove(&id);
+                bindings.remove(&id);
+                if learning.as_ref().is_some_and(|(m_id, _, _, _)| *m_id == id) {
+                    learning = None;
+                }
+                let mut next = stand_in(&shape);
+                match next.operators.remove(&id) {
+                    Some(operator) => {
+                        let mut cluster = vec![operator];
+                        if let Module::Operator(_, anchors, _) = &next.graph[operator] {
+                            cluster.extend(anchors.iter().cloned());
+                        }
+                        remove_nodes(cluster, &mut next);
+                        eprintln!("Currently {} Nodes", next.graph.node_count());
+                        vec![commit(&mut shape, next)]
+                    },
+                    None => vec![],
                 }
             }
             Action::DelRoute(id) => {
-                let route = routes.remove(&id).unwrap();
-                patch.remove_all_output_connections(route);
-                patch.remove_all_input_connections(route);
+                let mut next = stand_in(&shape);
+                match next.routes.remove(&id) {
+                    Some(route) => {
+                        remove_nodes(vec![route], &mut next);
+                        vec![commit(&mut shape, next)]
+                    },
+                    None => vec![],
+                }
             }
-            _ => { eprintln!("unimplemented action {:?}", a); }
+            _ => { eprintln!("unimplemented action {:?}", a); vec![] }
         }
-    })
+    };
+
+    if let Some(script) = render {
+        let script = read_script(&fs::read_to_string(&script)?)?;
+        let mut wav = offline::wav(Path::new(&out), config.sample_rate)?;
+        let frames = offline::render(&mut patch, &mut prepare, &script, config.sample_rate, &mut wav)?;
+        wav.finalize()?;
+        println!("Rendered {} frames to {}", frames, out);
+        return Ok(());
+    }
+
+    let transport = config.transport.clone();
+    match transport.socket {
+        Some(ref path) => println!("Serving clients at {}", path.display()),
+        // Blocked by pt-client reader
+        None => println!("Waiting for pt-client..."),
+    };
+
+    let server = match Server::bind(&transport) {
+        Ok(a) => a,
+        Err(e) => panic!("Could not open IPC: {}", e)
+    };
+
+    event_loop(server, patch, prepare, replies)
 }
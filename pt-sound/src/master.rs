@@ -0,0 +1,249 @@
+use libcommon::Action;
+
+use crate::core::{Output, CHANNELS};
+
+/*
+    The master bus. Everything routed to master is summed into one
+    buffer, scaled by the master gain and kept under CEILING by a
+    limiter, which is the only thing that clips the mix.
+
+    The limiter looks a little ahead and follows the true peak, the
+    highest point the waveform reaches between samples once it's
+    converted back to analog. It's estimated by interpolating three
+    points between every two samples (4x oversampling).
+
+    Levels are measured after the limiter and sent to the client a
+    few times a second.
+*/
+
+// -1dBTP, leaves room for converters and lossy encoders
+const CEILING: Output = 0.891;
+
+// How far ahead the limiter looks, in ms
+const LOOKAHEAD_MS: u32 = 1;
+
+// How long the limiter takes to let go, in ms
+const RELEASE_MS: f32 = 80.0;
+
+// Gain changes are spread out so they don't click, in ms
+const SMOOTH_MS: f32 = 10.0;
+
+// How often levels go to the client, in ms
+const METER_MS: u32 = 50;
+
+// Master gain range in dB
+pub const MIN_GAIN: f32 = -60.0;
+pub const MAX_GAIN: f32 = 12.0;
+
+pub struct Store {
+    // Gain in dB, the linear gain that is applied follows it
+    gain: f32,
+    current: Output,
+    smooth: Output,
+    release: Output,
+    // The last three frames in, to interpolate the true peak
+    history: [[Output; CHANNELS]; 3],
+    // Frames waiting to go out, and the gain each of them needs
+    delay: Vec<[Output; CHANNELS]>,
+    needed: Vec<Output>,
+    pos: usize,
+    envelope: Output,
+    // Measured since the last report
+    peak: [Output; CHANNELS],
+    square: [Output; CHANNELS],
+    reduction: Output,
+    frames: u32,
+    meter_period: u32,
+    client: Vec<Action>,
+}
+
+pub fn init(sample_rate: u32) -> Store {
+    let mut store = Store {
+        gain: 0.0,
+        current: 1.0,
+        smooth: 0.0,
+        release: 0.0,
+        history: [[0.0; CHANNELS]; 3],
+        delay: vec![],
+        needed: vec![],
+        pos: 0,
+        envelope: 1.0,
+        peak: [0.0; CHANNELS],
+        square: [0.0; CHANNELS],
+        reduction: 1.0,
+        frames: 0,
+        meter_period: 0,
+        client: vec![],
+    };
+    set_sample_rate(&mut store, sample_rate);
+    store
+}
+
+fn db_to_gain(db: f32) -> Output {
+    10f32.powf(db / 20.0)
+}
+
+// Per sample coefficient of a one pole filter settling in ms
+fn coefficient(ms: f32, sample_rate: u32) -> Output {
+    1.0 - (-1.0 / (ms / 1000.0 * sample_rate as f32)).exp()
+}
+
+// What was waiting in the lookahead is dropped, it is only a millisecond
+pub fn set_sample_rate(store: &mut Store, sample_rate: u32) {
+    let lookahead = (sample_rate * LOOKAHEAD_MS / 1000).max(4) as usize;
+    store.delay = vec![[0.0; CHANNELS]; lookahead];
+    store.needed = vec![1.0; lookahead];
+    store.pos = 0;
+    store.smooth = coefficient(SMOOTH_MS, sample_rate);
+    store.release = coefficient(RELEASE_MS, sample_rate);
+    store.meter_period = sample_rate * METER_MS / 1000;
+}
+
+// Catmull-Rom between p1 and p2
+fn interpolate(p: [Output; 4], t: Output) -> Output {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    0.5 * ((2.0 * p[1]) +
+        (p[2] - p[0]) * t +
+        (2.0 * p[0] - 5.0 * p[1] + 4.0 * p[2] - p[3]) * t2 +
+        (3.0 * p[1] - p[0] - 3.0 * p[2] + p[3]) * t3)
+}
+
+fn true_peak(history: &[[Output; CHANNELS]; 3], frame: [Output; CHANNELS]) -> Output {
+    let mut peak: Output = 0.0;
+    for c in 0..CHANNELS {
+        let p = [history[0][c], history[1][c], history[2][c], frame[c]];
+        peak = peak.max(frame[c].abs());
+        for t in [0.25, 0.5, 0.75].iter() {
+            peak = peak.max(interpolate(p, *t).abs());
+        }
+    }
+    peak
+}
+
+pub fn compute_buf(store: &mut Store, buffer: &mut [[Output; CHANNELS]]) {
+    let target = db_to_gain(store.gain);
+    for frame in buffer.iter_mut() {
+        store.current += (target - store.current) * store.smooth;
+        let input = [frame[0] * store.current, frame[1] * store.current];
+
+        let peak = true_peak(&store.history, input);
+        store.history = [store.history[1], store.history[2], input];
+
+        // Down as soon as anything in the lookahead needs it,
+        // ... including the frame going out, back up slowly once it's out
+        let new_needed = if peak > CEILING { CEILING / peak } else { 1.0 };
+        let needed = store.needed.iter().cloned().fold(new_needed, Output::min);
+
+        // The oldest frame goes out as the newest comes in
+        let output = store.delay[store.pos];
+        store.delay[store.pos] = input;
+        store.needed[store.pos] = new_needed;
+        store.pos = (store.pos + 1) % store.delay.len();
+
+        if needed < store.envelope {
+            store.envelope = needed;
+        } else {
+            store.envelope += (needed - store.envelope) * store.release;
+        }
+
+        for c in 0..CHANNELS {
+            frame[c] = output[c] * store.envelope;
+            store.peak[c] = store.peak[c].max(frame[c].abs());
+            store.square[c] += frame[c] * frame[c];
+        }
+        store.reduction = store.reduction.min(store.envelope);
+
+        store.frames += 1;
+        if store.frames >= store.meter_period {
+            let frames = store.frames as Output;
+            store.client.push(Action::MasterLevels(
+                store.peak[0],
+                store.peak[1],
+                (store.square[0] / frames).sqrt(),
+                (store.square[1] / frames).sqrt(),
+                -20.0 * store.reduction.log10(),
+            ));
+            store.peak = [0.0; CHANNELS];
+            store.square = [0.0; CHANNELS];
+            store.reduction = 1.0;
+            store.frames = 0;
+        }
+    }
+}
+
+pub fn dispatch(store: &mut Store, a: Action) {
+    match a {
+        Action::SetMasterGain(db) => { store.gain = db.max(MIN_GAIN).min(MAX_GAIN); },
+        _ => {}
+    }
+}
+
+pub fn dispatch_requested(store: &mut Store) -> (
+        Option<Vec<Action>>, // Output
+        Option<Vec<Action>>, // Input
+        Option<Vec<Action>>) { // Client
+    if store.client.is_empty() {
+        (None, None, None)
+    } else {
+        (None, None, Some(store.client.drain(..).collect()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48_000;
+
+    fn run(store: &mut Store, signal: &[[Output; CHANNELS]]) -> Vec<[Output; CHANNELS]> {
+        let mut buffer = signal.to_vec();
+        for block in buffer.chunks_mut(64) {
+            compute_buf(store, block);
+        }
+        buffer
+    }
+
+    #[test]
+    fn quiet_signal_passes_through_delayed() {
+        let mut store = init(RATE);
+        let lookahead = store.delay.len();
+        let signal: Vec<[Output; CHANNELS]> = (0..1024)
+            .map(|i| { let x = (i as Output * 0.05).sin() * 0.5; [x, -x] })
+            .collect();
+        let out = run(&mut store, &signal);
+        for (i, frame) in out.iter().enumerate().skip(lookahead) {
+            assert!((frame[0] - signal[i - lookahead][0]).abs() < 1e-4);
+            assert!((frame[1] - signal[i - lookahead][1]).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn loud_signal_stays_under_ceiling() {
+        let mut store = init(RATE);
+        dispatch(&mut store, Action::SetMasterGain(MAX_GAIN));
+        let signal: Vec<[Output; CHANNELS]> = (0..RATE)
+            .map(|i| { let x = (i as Output * 0.3).sin() * 0.9; [x, x] })
+            .collect();
+        let out = run(&mut store, &signal);
+        assert!(out.iter().all(|f| f[0].abs() <= CEILING + 1e-6 && f[1].abs() <= CEILING + 1e-6));
+    }
+
+    #[test]
+    fn levels_are_reported() {
+        let mut store = init(RATE);
+        let signal = vec![[0.25, 0.5]; RATE as usize / 10];
+        run(&mut store, &signal);
+        let (_, _, client) = dispatch_requested(&mut store);
+        let client = client.unwrap();
+        assert_eq!(client.len(), 2);
+        match client[1] {
+            Action::MasterLevels(peak_l, peak_r, rms_l, rms_r, reduction) => {
+                assert!((peak_l - 0.25).abs() < 1e-3 && (peak_r - 0.5).abs() < 1e-3);
+                assert!((rms_l - 0.25).abs() < 1e-3 && (rms_r - 0.5).abs() < 1e-3);
+                assert!(reduction.abs() < 1e-3);
+            },
+            ref a => panic!("unexpected {:?}", a),
+        }
+    }
+}
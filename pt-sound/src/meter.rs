@@ -0,0 +1,56 @@
+use crate::core::{Output, CHANNELS};
+
+/*
+    Peak meters for the patch view. Routes and the nodes behind module
+    anchors measure what they put out, and a few times a second the
+    peaks are read and sent to the client, then started over.
+
+    Silence is only reported once, so a patch with nothing playing
+    doesn't keep talking to the client.
+*/
+
+// How often levels go to the client
+pub const LEVELS_PER_SECOND: u32 = 10;
+
+#[derive(Debug, Default, Clone)]
+pub struct Level {
+    peak: Output,
+    // Whether the client already knows this node is silent
+    silent: bool,
+}
+
+pub fn measure(level: &mut Level, buffer: &[[Output; CHANNELS]]) {
+    for frame in buffer.iter() {
+        for sample in frame.iter() {
+            level.peak = level.peak.max(sample.abs());
+        }
+    }
+}
+
+// The peak since the last reset, if the client should hear about it
+pub fn read(level: &Level) -> Option<Output> {
+    if level.peak == 0.0 && level.silent { None } else { Some(level.peak) }
+}
+
+pub fn reset(level: &mut Level) {
+    level.silent = level.peak == 0.0;
+    level.peak = 0.0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn silence_is_reported_once() {
+        let mut level = Level::default();
+        measure(&mut level, &[[0.25, -0.5], [0.1, 0.0]]);
+        assert_eq!(read(&level), Some(0.5));
+        reset(&mut level);
+        assert_eq!(read(&level), Some(0.0));
+        reset(&mut level);
+        assert_eq!(read(&level), None);
+        measure(&mut level, &[[0.0, 0.75]]);
+        assert_eq!(read(&level), Some(0.75));
+    }
+}
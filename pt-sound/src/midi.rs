@@ -1,93 +1,170 @@
 #[cfg(target_os = "linux")]
 extern crate alsa;
-extern crate sample;
 
 #[cfg(target_os = "linux")]
-use alsa::{seq, pcm};
-use std::{iter, error};
+use alsa::seq;
+use std::error;
+#[cfg(target_os = "linux")]
 use std::ffi::CString;
-use sample::signal;
 use libcommon::Action;
-
 #[cfg(target_os = "linux")]
-pub fn connect_midi_source_ports(s: &alsa::Seq, our_port: i32) -> Result<(), Box<error::Error>> {
-    // Iterate over clients and clients' ports
-    let our_id = s.client_id()?;
-    let ci = seq::ClientIter::new(&s);
-    for client in ci {
-        if client.get_client() == our_id { continue; } // Skip ourselves
-        let pi = seq::PortIter::new(&s, client.get_client());
-        for port in pi {
-            let caps = port.get_capability();
+use libcommon::{KEYBED, config};
 
-            // Check that it's a normal input port
-            if !caps.contains(seq::READ) || !caps.contains(seq::SUBS_READ) { continue; }
-            if !port.get_type().contains(seq::MIDI_GENERIC) { continue; }
+/*
+    MIDI input through the ALSA sequencer. We show up as one client
+    with one port, and only subscribe to the ports we are asked for:
+    the ones listed in midi.inputs and the ones keyboard modules play
+    from. Anything played is passed on with the name of the port it
+    came from, which is the device a keyboard module names.
 
-            // Connect source and dest ports
-            let subs = seq::PortSubscribe::empty()?;
-            subs.set_sender(seq::Addr { client: port.get_client(), port: port.get_port() });
-            subs.set_dest(seq::Addr { client: our_id, port: our_port });
-            println!("Reading from midi input {:?}", port);
-            s.subscribe_port(&subs)?;
-        }
-    }
+    Read on the control thread, the audio thread never polls MIDI.
+*/
 
-    Ok(())
-} 
+#[cfg(target_os = "linux")]
+const CLIENT_NAME: &str = "Song Garden";
+#[cfg(target_os = "linux")]
+const PORT_NAME: &str = "Notes in";
 
 #[cfg(target_os = "linux")]
-pub fn open_midi_dev() -> Result<alsa::Seq, Box<error::Error>> {
-    // Open the sequencer.
+pub struct MidiIn {
+    seq: alsa::Seq,
+    port: i32,
+    // Ports we're subscribed to, with their names
+    sources: Vec<(seq::Addr, String)>,
+}
+
+#[cfg(target_os = "linux")]
+pub fn open() -> Result<MidiIn, Box<error::Error>> {
     let s = alsa::Seq::open(None, Some(alsa::Direction::Capture), true)?;
-    let cstr = CString::new("rust_synth_example").unwrap();
-    s.set_client_name(&cstr)?;
+    s.set_client_name(&CString::new(CLIENT_NAME)?)?;
 
-    // Create a destination port we can read from
-    let mut dinfo = seq::PortInfo::empty().unwrap();
+    // A destination port others can write to
+    let mut dinfo = seq::PortInfo::empty()?;
     dinfo.set_capability(seq::WRITE | seq::SUBS_WRITE);
     dinfo.set_type(seq::MIDI_GENERIC | seq::APPLICATION);
-    dinfo.set_name(&cstr);
-    s.create_port(&dinfo).unwrap();
-    let dport = dinfo.get_port();
+    dinfo.set_name(&CString::new(PORT_NAME)?);
+    s.create_port(&dinfo)?;
 
-    // source ports should ideally be configurable, but right now we're just reading them all.
-    connect_midi_source_ports(&s, dport)?;
+    let mut midi = MidiIn { port: dinfo.get_port(), seq: s, sources: vec![] };
+    for device in config().midi_inputs.iter() {
+        listen(&mut midi, device)?;
+    }
+    Ok(midi)
+}
 
-    Ok(s)
+// Every port we could read from, other than our own
+#[cfg(target_os = "linux")]
+fn readable(s: &alsa::Seq) -> Vec<(seq::Addr, String)> {
+    let our_id = match s.client_id() {
+        Ok(id) => id,
+        Err(_) => return vec![],
+    };
+    let mut ports = vec![];
+    for client in seq::ClientIter::new(s) {
+        if client.get_client() == our_id { continue; }
+        for port in seq::PortIter::new(s, client.get_client()) {
+            let caps = port.get_capability();
+            if !caps.contains(seq::READ) || !caps.contains(seq::SUBS_READ) { continue; }
+            if !port.get_type().contains(seq::MIDI_GENERIC) { continue; }
+            ports.push((
+                seq::Addr { client: port.get_client(), port: port.get_port() },
+                port.get_name().unwrap_or("").to_string(),
+            ));
+        }
+    }
+    ports
 }
 
-#[cfg(target_os = "macos")]
-pub fn open_midi_dev() -> Result<(), Box<error::Error>> { Ok(()) }
+#[cfg(target_os = "linux")]
+pub fn ports(midi: &MidiIn) -> Vec<String> {
+    readable(&midi.seq).into_iter().map(|(_, name)| name).collect()
+}
 
-#[cfg(target_os = "macos")]
-pub fn read_midi_event() -> Result<(), Box<error::Error>> { Ok(()) }
+// Subscribes to the ports named like device, the keybed isn't one of them
+#[cfg(target_os = "linux")]
+pub fn listen(midi: &mut MidiIn, device: &str) -> Result<(), Box<error::Error>> {
+    if device.is_empty() || device == KEYBED { return Ok(()); }
+    let our_id = midi.seq.client_id()?;
+    for (addr, name) in readable(&midi.seq).into_iter() {
+        if !name.contains(device) { continue; }
+        if midi.sources.iter().any(|(a, _)| *a == addr) { continue; }
+        let subs = seq::PortSubscribe::empty()?;
+        subs.set_sender(addr);
+        subs.set_dest(seq::Addr { client: our_id, port: midi.port });
+        midi.seq.subscribe_port(&subs)?;
+        println!("Reading from midi input {} ({}:{})", name, addr.client, addr.port);
+        midi.sources.push((addr, name));
+    }
+    Ok(())
+}
 
-#[cfg(target_os = "macos")]
-pub fn connect_midi_source_ports() -> Result<(), Box<error::Error>> { Ok(()) }
+// Everything played since the last read, with the port it came from
+#[cfg(target_os = "linux")]
+pub fn read(midi: &mut MidiIn) -> Vec<(String, Action)> {
+    let mut played = vec![];
+    let mut input = midi.seq.input();
+    while let Ok(pending) = input.event_input_pending(true) {
+        if pending == 0 { break; }
+        let ev = match input.event_input() {
+            Ok(ev) => ev,
+            Err(_) => break,
+        };
+        let source = ev.get_source();
+        let device = match midi.sources.iter().find(|(a, _)| *a == source) {
+            Some((_, name)) => name,
+            None => continue,
+        };
+        if let Some(action) = translate(&ev) {
+            played.push((device.clone(), action));
+        }
+    }
+    played
+}
 
 #[cfg(target_os = "linux")]
-pub fn read_midi_event(input: &mut seq::Input) -> Result<Option<Action>, Box<error::Error>> {
-    if input.event_input_pending(true)? == 0 { return Ok(None); }
-    let ev = input.event_input()?;
-    // println!("Received: {:?}", ev);
+fn translate(ev: &seq::Event) -> Option<Action> {
     match ev.get_type() {
         seq::EventType::Noteon => {
-            let data: seq::EvNote = ev.get_data().unwrap();
+            let data: seq::EvNote = ev.get_data()?;
+            // Running status sends note off as a silent note on
             if data.velocity == 0 {
-                Ok(Some(Action::NoteOff(data.note)))
+                Some(Action::NoteOff(data.note))
             } else {
-                Ok(Some(Action::NoteOn(data.note, f64::from(data.velocity + 64) / 2048.)))
+                Some(Action::NoteOn(data.note, f64::from(data.velocity) / 127.0))
             }
         },
         seq::EventType::Noteoff => {
-            let data: seq::EvNote = ev.get_data().unwrap();
-            Ok(Some(Action::NoteOff(data.note)))
+            let data: seq::EvNote = ev.get_data()?;
+            Some(Action::NoteOff(data.note))
         },
         seq::EventType::Controller => {
-            let data: seq::EvCtrl = ev.get_data().unwrap();
-            Ok(Some(Action::SetParam(0, format!("{}", data.param), data.value)))
+            let data: seq::EvCtrl = ev.get_data()?;
+            Some(Action::ControlChange(data.param as u8, data.value.max(0).min(127) as u8))
+        },
+        seq::EventType::Pitchbend => {
+            let data: seq::EvCtrl = ev.get_data()?;
+            // -8192 to 8191
+            Some(Action::PitchBend((data.value as f32 / 8192.0).max(-1.0).min(1.0)))
         },
-        _ => Ok(None),
+        seq::EventType::Pgmchange => {
+            let data: seq::EvCtrl = ev.get_data()?;
+            Some(Action::ProgramChange(data.value.max(0).min(127) as u8))
+        },
+        _ => None,
     }
 }
+
+#[cfg(target_os = "macos")]
+pub struct MidiIn;
+
+#[cfg(target_os = "macos")]
+pub fn open() -> Result<MidiIn, Box<error::Error>> { Ok(MidiIn) }
+
+#[cfg(target_os = "macos")]
+pub fn ports(_midi: &MidiIn) -> Vec<String> { vec![] }
+
+#[cfg(target_os = "macos")]
+pub fn listen(_midi: &mut MidiIn, _device: &str) -> Result<(), Box<error::Error>> { Ok(()) }
+
+#[cfg(target_os = "macos")]
+pub fn read(_midi: &mut MidiIn) -> Vec<(String, Action)> { vec![] }
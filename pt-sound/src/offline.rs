@@ -0,0 +1,235 @@
+extern crate hound;
+
+use std::io;
+use std::fs::File;
+use std::path::Path;
+use dsp::Node;
+use libcommon::{Action, Offset};
+
+use crate::core::{apply, walk_dispatch, Patch, Request, Task, Output, CHANNELS, FRAMES};
+
+/*
+    Renders the patch without a sound card, as fast as it can be
+    computed. Actions come from a script (see libcommon::script) instead
+    of IPC, and each one is dispatched on exactly the frame it is given,
+    so the same script always renders the same audio:
+
+        pt-sound --render song.script --out song.wav
+
+    Rendering stops at the first EXIT, or after the last action.
+*/
+
+pub const DEFAULT_OUT: &str = "render.wav";
+
+// Where rendered frames are written
+pub trait Sink {
+    fn write(&mut self, frames: &[[Output; CHANNELS]]) -> io::Result<()>;
+}
+
+// Rendering into memory, for tests
+impl Sink for Vec<[Output; CHANNELS]> {
+    fn write(&mut self, frames: &[[Output; CHANNELS]]) -> io::Result<()> {
+        self.extend_from_slice(frames);
+        Ok(())
+    }
+}
+
+impl<W: io::Write + io::Seek> Sink for hound::WavWriter<W> {
+    fn write(&mut self, frames: &[[Output; CHANNELS]]) -> io::Result<()> {
+        for frame in frames.iter() {
+            for sample in frame.iter() {
+                self.write_sample(*sample).map_err(io::Error::other)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// A float wav, so nothing is lost to quantization
+pub fn wav(path: &Path, rate: u32) -> io::Result<hound::WavWriter<io::BufWriter<File>>> {
+    let spec = hound::WavSpec {
+        channels: CHANNELS as u16,
+        sample_rate: rate,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    };
+    hound::WavWriter::create(path, spec).map_err(io::Error::other)
+}
+
+// Removes --key value or --key=value from args, returning the value
+pub fn take_arg(args: &mut Vec<String>, key: &str) -> Option<String> {
+    let flag = format!("--{}", key);
+    let i = args.iter().position(|a| *a == flag || a.starts_with(&format!("{}=", flag)))?;
+    let arg = args.remove(i);
+    match arg.find('=') {
+        Some(eq) => Some(arg[eq + 1..].to_string()),
+        None if i < args.len() => Some(args.remove(i)),
+        None => Some(String::new()),
+    }
+}
+
+// Returns the number of frames rendered. Actions are prepared and
+// applied in turn, as the control and audio threads would
+pub fn render<P, S: Sink>(
+        patch: &mut Patch,
+        prepare_f: &mut P,
+        script: &[(Offset, Action)],
+        rate: u32,
+        sink: &mut S) -> io::Result<Offset>
+
+    where P: FnMut(Request) -> Vec<Task> {
+
+    // Nobody is listening for the client's actions, or for MIDI
+    let mut run = |patch: &mut Patch, action: Action| {
+        for task in prepare_f(Request::Action(action)).into_iter() {
+            if let Task::Audio(control) = task {
+                apply(patch, control);
+            }
+        }
+    };
+
+    let end = script.iter()
+        .find(|(_, a)| *a == Action::Exit)
+        .or_else(|| script.last())
+        .map_or(0, |(time, _)| *time);
+
+    run(patch, Action::SetSampleRate(rate));
+
+    let mut buffer = [[0.0; CHANNELS]; FRAMES as usize];
+    let mut next = 0;
+    let mut time: Offset = 0;
+
+    loop {
+        // Everything due now, as if it just arrived over IPC
+        while let Some((at, action)) = script.get(next) {
+            if *at > time { break; }
+            if *action == Action::Exit { return Ok(time); }
+            run(patch, action.clone());
+            next += 1;
+        }
+        if time >= end { return Ok(time); }
+
+        walk_dispatch(&mut patch.graph, |_| ());
+
+        // Blocks end early so the next action lands on its frame
+        let until = script.get(next).map_or(end, |(at, _)| *at);
+        let len = (until - time).min(FRAMES) as usize;
+        let block = &mut buffer[..len];
+
+        dsp::slice::equilibrium(block);
+        patch.graph.audio_requested(block, f64::from(rate));
+        sink.write(block)?;
+        time += len as Offset;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use libcommon::{HammondDoc, read_script};
+    use crate::{synth, tape, arpeggio};
+    use crate::core::{Control, Module};
+
+    // Module 1 is sent every action in the script
+    fn play_on(mut patch: Patch, script: &[(Offset, Action)]) -> Vec<[Output; CHANNELS]> {
+        let mut frames = vec![];
+        let mut prepare = |r: Request| match r {
+            Request::Action(Action::SetSampleRate(rate)) => vec![Task::Audio(Control::Action(Action::SetSampleRate(rate)))],
+            Request::Action(a) => vec![Task::Audio(Control::Action(Action::At(1, Box::new(a))))],
+            Request::Midi(_, _) | Request::Region(_) => vec![],
+        };
+        let rendered = render(&mut patch, &mut prepare, script, 48000, &mut frames).unwrap();
+        assert_eq!(rendered as usize, frames.len());
+        frames
+    }
+
+    fn synth_patch() -> Patch {
+        let mut patch = Patch::new();
+        let synth = patch.graph.add_node(Module::Synth(synth::read(&HammondDoc::default(), 48000)));
+        patch.graph.set_master(Some(synth));
+        patch.operators.insert(1, synth);
+        patch
+    }
+
+    fn play(script: &[(Offset, Action)]) -> Vec<[Output; CHANNELS]> {
+        play_on(synth_patch(), script)
+    }
+
+    #[test]
+    fn actions_land_on_their_frame() {
+        let frames = play(&[(300, Action::NoteOn(60, 1.0)), (600, Action::Exit)]);
+        assert_eq!(frames.len(), 600);
+        assert!(frames[..300].iter().all(|f| *f == [0.0; CHANNELS]));
+        assert!(frames[300..].iter().any(|f| f[0] != 0.0));
+    }
+
+    #[test]
+    fn renders_are_repeatable() {
+        let script = [
+            (0, Action::NoteOn(60, 1.0)),
+            (1000, Action::NoteOn(64, 0.5)),
+            (2000, Action::NoteOff(60)),
+            (3000, Action::NoteOff(64)),
+        ];
+        let frames = play(&script);
+        assert_eq!(frames.len(), 3000);
+        assert_eq!(frames, play(&script));
+    }
+
+    #[test]
+    fn tapes_render_their_assets() {
+        let asset: Vec<[Output; CHANNELS]> = (0..2000)
+            .map(|i| [i as Output / 2000.0, -(i as Output) / 4000.0])
+            .collect();
+        let mut store = tape::init(2, Path::new(""), 48000, 48000);
+        store.audio_regions.push(tape::AudioRegion {
+            id: 1,
+            buffer: Arc::new(vec![asset.clone()]),
+            offset: 100,
+            duration: 1000,
+            asset_in: 50,
+            gain: 1.0,
+            asset_id: 1,
+            asset_src: String::new(),
+        });
+        let mut patch = Patch::new();
+        let tape = patch.graph.add_node(Module::Tape(store));
+        patch.graph.set_master(Some(tape));
+        patch.operators.insert(1, tape);
+
+        let script = read_script("0 PLAY\n1500 EXIT").unwrap();
+        let frames = play_on(patch, &script);
+
+        // The fader is at unity, so the region is the asset sample for sample
+        let mut golden = vec![[0.0; CHANNELS]; 1500];
+        golden[100..1100].copy_from_slice(&asset[50..1050]);
+        assert_eq!(frames, golden);
+    }
+
+    #[test]
+    fn arpeggios_render_their_notes_in_time() {
+        let mut patch = synth_patch();
+        let arpeggio = patch.graph.add_node(Module::Arpeggio(arpeggio::init(48000)));
+        patch.graph.add_connection(arpeggio, patch.operators[&1]).unwrap();
+        patch.operators.insert(1, arpeggio);
+
+        let script = read_script("0 NOTE_ON:60:1 NOTE_ON:64:1\n96000 EXIT").unwrap();
+        let frames = play_on(patch, &script);
+
+        // Two notes split a bar of 4 beats at 127 bpm into quarters of
+        // ... 22677 frames, highest note first. What the arpeggio plays
+        // ... reaches the synth with the block after, every 128 frames.
+        let golden = play(&[
+            (128, Action::NoteOn(64, 1.0)),
+            (22784, Action::NoteOff(64)),
+            (45440, Action::NoteOn(60, 1.0)),
+            (68096, Action::NoteOff(60)),
+            (90752, Action::NoteOn(64, 1.0)),
+            (96000, Action::Exit),
+        ]);
+        assert_eq!(frames.len(), 96000);
+        assert!(frames[128..].iter().any(|f| f[0] != 0.0));
+        assert!(frames == golden, "arpeggio render differs from its notes played by hand");
+    }
+}
@@ -1,10 +1,11 @@
 use std::ffi::{OsStr, CStr};
 use std::collections::HashMap;
 use libc::{c_int, c_char, c_float};
-use libcommon::{Action, note_to_hz};
+use libcommon::{Action, note_to_hz, config};
 use libloading::{Library, Symbol};
 use libloading::os::unix::Symbol as RawSymbol;
-use crate::core::{Output, CHANNELS, FRAMES};
+use crate::core::{Output, CHANNELS};
+use crate::meter;
 
 // So we are going to build a struct which implements the UI trait
 // ... and load an object from our compiled library which will
@@ -250,16 +251,17 @@ struct PluginVTable {
 }
 
 impl PluginVTable {
-    fn new(lib: &Library) -> Self {
+    fn new(lib: &Library) -> Result<Self, String> {
+        let missing = |e| format!("not a faust plugin: {}", e);
         unsafe {
-            let new: Symbol<DspNew> = lib.get(b"newmydsp").unwrap();
-            let init: Symbol<DspInit> = lib.get(b"initmydsp").unwrap();
-            let compute: Symbol<DspCompute> = lib.get(b"computemydsp").unwrap();
-            let getNumInputs: Symbol<DspNumIO> = lib.get(b"getNumInputsmydsp").unwrap();
-            let getNumOutputs: Symbol<DspNumIO> = lib.get(b"getNumOutputsmydsp").unwrap();
-            let buildUserInterface: Symbol<DspBuildUI> = lib.get(b"buildUserInterfacemydsp").unwrap();
-            let delete: Symbol<DspDelete> = lib.get(b"deletemydsp").unwrap();
-            PluginVTable {
+            let new: Symbol<DspNew> = lib.get(b"newmydsp").map_err(missing)?;
+            let init: Symbol<DspInit> = lib.get(b"initmydsp").map_err(missing)?;
+            let compute: Symbol<DspCompute> = lib.get(b"computemydsp").map_err(missing)?;
+            let getNumInputs: Symbol<DspNumIO> = lib.get(b"getNumInputsmydsp").map_err(missing)?;
+            let getNumOutputs: Symbol<DspNumIO> = lib.get(b"getNumOutputsmydsp").map_err(missing)?;
+            let buildUserInterface: Symbol<DspBuildUI> = lib.get(b"buildUserInterfacemydsp").map_err(missing)?;
+            let delete: Symbol<DspDelete> = lib.get(b"deletemydsp").map_err(missing)?;
+            Ok(PluginVTable {
                 new: new.into_raw(),
                 init: init.into_raw(),
                 compute: compute.into_raw(),
@@ -267,7 +269,7 @@ impl PluginVTable {
                 getNumOutputs: getNumOutputs.into_raw(),
                 buildUserInterface: buildUserInterface.into_raw(),
                 delete: delete.into_raw(),
-            }
+            })
         }
     }
 }
@@ -282,17 +284,39 @@ pub struct Store {
     buffer_in: Vec<Vec<c_float>>,
     buffer_out: Vec<Vec<c_float>>,
     buffer_sum: Vec<Vec<c_float>>,
+    // Where compute finds each channel, refilled every block
+    ptrs_in: Vec<*const c_float>,
+    ptrs_out: Vec<*mut c_float>,
     voices: [Option<(*mut Voice, Box<PluginUI>, Box<UIGlue>)>; MAX_VOICES],
     next_voice: usize,
+    sample_rate: u32,
+    pub level: meter::Level,
 }
 
-pub fn init(lib_src: String) -> Store {
-    let lib = match Library::new(OsStr::new(&lib_src)) {
-        Ok(lib) => lib,
-        Err(_) => panic!("No such plugin {}", lib_src)
-    };
+// Plugins are loaded on the control thread and then handed to the
+// audio thread. The voices are only ever touched by whoever owns the
+// store, never by both.
+unsafe impl Send for Store {}
+
+// Voices are deleted by the plugin itself, then the library is unloaded
+// ... when lib is dropped
+impl Drop for Store {
+    fn drop(&mut self) {
+        for voice in self.voices.iter_mut() {
+            if let Some((voice, _, _)) = voice.take() {
+                (self.vtable.delete)(voice);
+            }
+        }
+    }
+}
+
+// Fails when the library is missing or isn't a faust plugin
+pub fn init(lib_src: String, sample_rate: u32) -> Result<Store, String> {
+    let lib = Library::new(OsStr::new(&lib_src))
+        .map_err(|e| format!("no such plugin {}: {}", lib_src, e))?;
 
-    let vtable = PluginVTable::new(&lib);
+    let vtable = PluginVTable::new(&lib)
+        .map_err(|e| format!("{}: {}", lib_src, e))?;
     let mut declarations: Vec<Action> = vec![];
 
     let mut ui = Box::new(PluginUI::new());
@@ -300,7 +324,7 @@ pub fn init(lib_src: String) -> Store {
 
     // Initialize temp voice
     let voice0 = (vtable.new)();
-    (vtable.init)(voice0, 48000);
+    (vtable.init)(voice0, sample_rate as c_int);
 
     // Add anchor declaration
     let num_inputs = (vtable.getNumInputs)(voice0) as usize;
@@ -333,12 +357,16 @@ pub fn init(lib_src: String) -> Store {
         num_inputs,
         num_outputs,
         // Make sure we are not allocating this in tight loop (see below)
-        buffer_in: vec![vec![0.0; FRAMES as usize]; num_inputs],
-        buffer_out: vec![vec![0.0; FRAMES as usize]; num_outputs],
-        buffer_sum: vec![vec![0.0; FRAMES as usize]; num_outputs],
+        buffer_in: vec![vec![0.0; config().period_size as usize]; num_inputs],
+        buffer_out: vec![vec![0.0; config().period_size as usize]; num_outputs],
+        buffer_sum: vec![vec![0.0; config().period_size as usize]; num_outputs],
+        ptrs_in: Vec::with_capacity(num_inputs),
+        ptrs_out: Vec::with_capacity(num_outputs),
         next_voice: 0,
+        sample_rate,
+        level: meter::Level::default(),
     };
-    store
+    Ok(store)
 }
 
 pub fn compute_buf(store: &mut Store, buffer: &mut [[Output; CHANNELS]]) {
@@ -347,6 +375,18 @@ pub fn compute_buf(store: &mut Store, buffer: &mut [[Output; CHANNELS]]) {
     // ... computemydsp. We will zero the output buffer from
     // ... the previous computation, and copy the core buffer
     // ... to the inputs for each channel it expects (0, 1, or 2)
+    let frames = buffer.len();
+
+    // Buffers only grow, so a larger period allocates once
+    if store.buffer_out.first().map_or(0, |b| b.len()) < frames ||
+        store.buffer_in.first().map_or(0, |b| b.len()) < frames {
+        for channel in store.buffer_in.iter_mut()
+            .chain(store.buffer_out.iter_mut())
+            .chain(store.buffer_sum.iter_mut()) {
+            channel.resize(frames, 0.0);
+        }
+    }
+
     for (i, frame) in buffer.iter().enumerate() {
         for (j, sample) in frame.iter().enumerate() {
             if j < store.num_inputs {
@@ -359,15 +399,12 @@ pub fn compute_buf(store: &mut Store, buffer: &mut [[Output; CHANNELS]]) {
         }
     }
 
-    // Buffer which will be written to
-    let mut output_ptrs: Vec<*mut Output> =
-        store.buffer_out.iter_mut().map(|out| out.as_mut_ptr()
-    ).collect();
-
-    // Buffer which will be read from
-    let input_ptrs: Vec<*const Output> = 
-        store.buffer_in.iter().map(|out| out.as_ptr()
-    ).collect();
+    // Buffer which will be written to, and the one read from. Both
+    // ... were made with room for every channel
+    store.ptrs_out.clear();
+    store.ptrs_out.extend(store.buffer_out.iter_mut().map(|out| out.as_mut_ptr()));
+    store.ptrs_in.clear();
+    store.ptrs_in.extend(store.buffer_in.iter().map(|inp| inp.as_ptr()));
 
     for voice in store.voices.iter() {
 
@@ -375,15 +412,15 @@ pub fn compute_buf(store: &mut Store, buffer: &mut [[Output; CHANNELS]]) {
             unsafe {
                 (store.vtable.compute)(
                     *voice,
-                    FRAMES as c_int, 
-                    input_ptrs.as_ptr() as *const *const Output,
-                    output_ptrs.as_mut_ptr() as *mut *mut Output,
+                    frames as c_int, 
+                    store.ptrs_in.as_ptr() as *const *const Output,
+                    store.ptrs_out.as_mut_ptr() as *mut *mut Output,
                 );
             }
 
             // Add voices together
             for i in 0..store.num_outputs {
-                for j in 0..FRAMES as usize {
+                for j in 0..frames {
                     store.buffer_sum[i][j] += store.buffer_out[i][j];
                 }
             }
@@ -396,7 +433,7 @@ pub fn compute_buf(store: &mut Store, buffer: &mut [[Output; CHANNELS]]) {
     // ... our core will expect a stereo signal and the faust
     // ... plugin might only have 1 output, we will need to 
     // ... copy this value twice to each channel
-    for i in 0..FRAMES as usize {
+    for i in 0..frames {
         for j in 0..CHANNELS {
             if j < store.num_outputs {
                 buffer[i][j] = store.buffer_sum[j][i] as f32;
@@ -408,13 +445,31 @@ pub fn compute_buf(store: &mut Store, buffer: &mut [[Output; CHANNELS]]) {
     }
 }
 
+// Faust's init resets every param, so they are put back afterwards
+pub fn set_sample_rate(store: &mut Store, sample_rate: u32) {
+    store.sample_rate = sample_rate;
+    for voice in store.voices.iter_mut() {
+        if let Some((voice, ui, _)) = voice {
+            let values: Vec<(String, c_float)> = ui.params.iter()
+                .map(|(name, param)| (name.clone(), unsafe { **param }))
+                .collect();
+            (store.vtable.init)(*voice, sample_rate as c_int);
+            for (name, value) in values.into_iter() {
+                if let Some(param) = ui.params.get(&name) {
+                    unsafe { **param = value; }
+                }
+            }
+        }
+    }
+}
+
 pub fn dispatch(store: &mut Store, a: Action) {
     match a {
         Action::NoteOn(key, vel) => {
             if store.midi_enabled {
                 if store.voices[store.next_voice].is_none() {
                     let new_voice = (store.vtable.new)();
-                    (store.vtable.init)(new_voice, 48000);
+                    (store.vtable.init)(new_voice, store.sample_rate as c_int);
                     let mut new_ui = Box::new(PluginUI::new());
                     let mut new_ui_glue = Box::new(UIGlue::new(&mut *new_ui));
                     (store.vtable.buildUserInterface)(new_voice, &mut *new_ui_glue);
@@ -0,0 +1,212 @@
+use std::{error, fs};
+use std::path::{Path, PathBuf};
+use sample::{signal, Signal, ring_buffer};
+use sample::interpolate::{Linear, Sinc};
+use hound;
+use libcommon::config;
+use libcommon::config::ResampleQuality;
+
+use crate::core::{Output, CHANNELS};
+
+/*
+    Assets are played at the engine's rate, so a wav recorded at any
+    other rate is converted when it is loaded. Converted copies are
+    kept next to the original, named after the rate and quality:
+
+        assets/drums.wav
+        assets/drums.48000.good.wav
+
+    A copy older than its original is converted again.
+*/
+
+// Sinc window in source frames, half of it on either side
+fn taps(quality: ResampleQuality) -> usize {
+    match quality {
+        ResampleQuality::Fast => 0,
+        ResampleQuality::Good => 32,
+        ResampleQuality::Best => 128,
+    }
+}
+
+pub fn cache_path(src: &Path, rate: u32, quality: ResampleQuality) -> PathBuf {
+    let stem = src.file_stem().unwrap_or_default().to_string_lossy();
+    src.with_file_name(format!("{}.{}.{}.wav", stem, rate, quality.name()))
+}
+
+fn newer(cached: &Path, src: &Path) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified());
+    match (modified(cached), modified(src)) {
+        (Ok(cached), Ok(src)) => cached >= src,
+        _ => false,
+    }
+}
+
+// Frames of a wav at whatever rate it was written, and that rate
+pub fn decode(src: &Path) -> Result<(Vec<[Output; CHANNELS]>, u32), Box<error::Error>> {
+    let mut wav_f = hound::WavReader::open(src)?;
+    let spec = wav_f.spec();
+    let channels = spec.channels as usize;
+    let bitrate = spec.bits_per_sample;
+
+    let mut frames = Vec::with_capacity(wav_f.duration() as usize);
+    let mut push_sample = |i, sample| {
+        let frame_index = i % channels;
+        if frame_index == 0 {
+            frames.push([0.0; CHANNELS]);
+        }
+        if frame_index < CHANNELS {
+            frames.last_mut().unwrap()[frame_index] = sample
+        }
+    };
+
+    if spec.sample_format == hound::SampleFormat::Float {
+        for (i, sample) in wav_f.samples::<f32>().enumerate() {
+            push_sample(i, sample?);
+        }
+    } else {
+        for (i, sample) in wav_f.samples::<i32>().enumerate() {
+            push_sample(i, sample? as f32 / 2_f32.powf((bitrate - 1) as f32));
+        }
+    }
+    Ok((frames, spec.sample_rate))
+}
+
+fn write(path: &Path, frames: &[[Output; CHANNELS]], rate: u32) -> Result<(), Box<error::Error>> {
+    let spec = hound::WavSpec {
+        channels: CHANNELS as u16,
+        sample_rate: rate,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    };
+    let mut writer = hound::WavWriter::create(path, spec)?;
+    for frame in frames.iter() {
+        for sample in frame.iter() {
+            writer.write_sample(*sample)?;
+        }
+    }
+    writer.finalize()?;
+    Ok(())
+}
+
+// The first delay frames are the interpolator filling up
+fn collect<S>(signal: S, delay: usize, len: usize) -> Vec<[Output; CHANNELS]>
+    where S: Signal<Frame=[Output; CHANNELS]> {
+    signal.take(delay + len).skip(delay).collect()
+}
+
+pub fn resample(
+        frames: &[[Output; CHANNELS]],
+        from: u32,
+        to: u32,
+        quality: ResampleQuality) -> Vec<[Output; CHANNELS]> {
+
+    if from == to || frames.is_empty() {
+        return frames.to_vec();
+    }
+    let (from_hz, to_hz) = (f64::from(from), f64::from(to));
+    let len = (frames.len() as f64 * to_hz / from_hz).ceil() as usize;
+
+    // Past the end the source is silence, which flushes the window
+    let mut source = signal::from_iter(frames.iter().cloned());
+    match quality {
+        ResampleQuality::Fast => {
+            let interp = Linear::from_source(&mut source);
+            collect(source.from_hz_to_hz(interp, from_hz, to_hz), 0, len)
+        },
+        quality => {
+            let taps = taps(quality);
+            let interp = Sinc::new(ring_buffer::Fixed::from(vec![[0.0; CHANNELS]; taps]));
+            let delay = ((taps / 2) as f64 * to_hz / from_hz).round() as usize;
+            collect(source.from_hz_to_hz(interp, from_hz, to_hz), delay, len)
+        },
+    }
+}
+
+// Loads an asset at rate, converting it or reading a converted copy
+pub fn load(src: &str, rate: u32) -> Result<Vec<[Output; CHANNELS]>, Box<error::Error>> {
+    let config = config();
+    let src = Path::new(src);
+    let cached = cache_path(src, rate, config.resample);
+
+    if config.resample_cache && newer(&cached, src) {
+        match decode(&cached) {
+            Ok((frames, cached_rate)) if cached_rate == rate => return Ok(frames),
+            Ok(_) => {},
+            Err(e) => eprintln!("ignoring cached {}: {}", cached.display(), e),
+        }
+    }
+
+    let (frames, from) = decode(src)?;
+    if from == rate {
+        return Ok(frames);
+    }
+
+    let frames = resample(&frames, from, rate, config.resample);
+    if config.resample_cache {
+        if let Err(e) = write(&cached, &frames, rate) {
+            eprintln!("couldn't cache {}: {}", cached.display(), e);
+        }
+    }
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(hz: f64, rate: u32, len: usize) -> Vec<[Output; CHANNELS]> {
+        (0..len).map(|i| {
+            let x = (i as f64 * hz * 2.0 * std::f64::consts::PI / f64::from(rate)).sin() as Output;
+            [x, x]
+        }).collect()
+    }
+
+    // Zero crossings going up, which counts whole cycles
+    fn cycles(frames: &[[Output; CHANNELS]]) -> usize {
+        frames.windows(2).filter(|w| w[0][0] < 0.0 && w[1][0] >= 0.0).count()
+    }
+
+    #[test]
+    fn keeps_duration_and_pitch() {
+        let source = sine(441.0, 44100, 44100);
+        for quality in [ResampleQuality::Fast, ResampleQuality::Good, ResampleQuality::Best].iter() {
+            let converted = resample(&source, 44100, 48000, *quality);
+            assert_eq!(converted.len(), 48000);
+            let count = cycles(&converted[..]) as i64;
+            assert!((count - 441).abs() <= 1, "{:?} has {} cycles", quality, count);
+        }
+    }
+
+    #[test]
+    fn same_rate_is_untouched() {
+        let source = sine(441.0, 48000, 1000);
+        assert_eq!(resample(&source, 48000, 48000, ResampleQuality::Best), source);
+    }
+
+    #[test]
+    fn integer_wavs_are_full_scale() {
+        let path = std::env::temp_dir().join(format!("pt-test-scale-{}.wav", std::process::id()));
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 48000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for sample in [i16::MIN, 16384].iter() {
+            writer.write_sample(*sample).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let (frames, rate) = decode(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(rate, 48000);
+        assert_eq!(frames, vec![[-1.0, 0.5]]);
+    }
+
+    #[test]
+    fn cached_copies_are_named_by_rate() {
+        let path = cache_path(Path::new("assets/drums.wav"), 48000, ResampleQuality::Good);
+        assert_eq!(path, PathBuf::from("assets/drums.48000.good.wav"));
+    }
+}
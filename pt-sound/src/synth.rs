@@ -1,9 +1,9 @@
 use std::{iter};
 use sample::{signal, Signal, Sample};
-use xmltree::Element;
-use libcommon::{Action, Param, param_map, note_to_hz};
+use libcommon::{Action, Param, HammondDoc, note_to_hz};
 
-use crate::core::{SF, Output, CHANNELS, SAMPLE_HZ};
+use crate::core::{SF, Output, CHANNELS};
+use crate::meter;
 
 // Standard Hammond drawbar.
 const BAR_FREQS: [Param; 9] = [16., 5.+1./3., 8., 4., 2.+2./3., 2., 1.+3./5., 1.+1./3., 1.];
@@ -24,17 +24,24 @@ pub struct Store {
     pub sigs: Vec<Option<Sig>>,
     pub sample_rate: signal::Rate,
     pub bar_values: [Param; 9],
+    pub level: meter::Level,
 }
 
-pub fn init() -> Store {
+pub fn init(sample_rate: u32) -> Store {
     Store {
         queue: vec![],
         sigs: iter::repeat(None).take(256).collect(),
-        sample_rate: signal::rate(f64::from(SAMPLE_HZ)),
+        sample_rate: signal::rate(f64::from(sample_rate)),
         bar_values: [0.25, 0.25, 0.25, 0.75, 0.5, 0., 0., 0., 0.],
+        level: meter::Level::default(),
     }
 }
 
+// Notes already sounding keep their pitch until released
+pub fn set_sample_rate(store: &mut Store, sample_rate: u32) {
+    store.sample_rate = signal::rate(f64::from(sample_rate));
+}
+
 pub fn dispatch(store: &mut Store, action: Action) {
     match action {
         Action::NoteOn(note, vol) |
@@ -84,21 +91,10 @@ pub fn dispatch(store: &mut Store, action: Action) {
     }
 }
 
-pub fn read(doc: &mut Element) -> Option<Store> {
-    let (_, params) = param_map(doc);
-    let mut store = init();
-    store.bar_values = [
-        *params.get("16").unwrap_or(&0.1),
-        *params.get("5.3").unwrap_or(&0.1),
-        *params.get("8").unwrap_or(&0.1),
-        *params.get("4").unwrap_or(&0.1),
-        *params.get("2.6").unwrap_or(&0.1),
-        *params.get("2").unwrap_or(&0.1),
-        *params.get("1.6").unwrap_or(&0.1),
-        *params.get("1.3").unwrap_or(&0.1),
-        *params.get("1").unwrap_or(&0.1),
-    ];
-    Some(store)
+pub fn read(doc: &HammondDoc, sample_rate: u32) -> Store {
+    let mut store = init(sample_rate);
+    store.bar_values = doc.drawbars;
+    store
 }
 
 pub fn compute(store: &mut Store) -> [Output; CHANNELS] {
@@ -125,7 +121,6 @@ pub fn compute(store: &mut Store) -> [Output; CHANNELS] {
         }
         if remove { *sig = None };
     }
-    let z = z.min(0.999).max(-0.999);
     [z, z]
 }
 
@@ -1,30 +1,39 @@
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Write;
 use std::borrow::Borrow;
-use std::convert::TryInto;
-use std::collections::{HashMap, LinkedList};
+use std::collections::LinkedList;
 use std::thread;
 use std::time;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock, atomic::Ordering, atomic::AtomicU32};
 use std::ops::{Deref, DerefMut};
+use std::mem;
 use sample::{signal, Signal, Sample, Frame, ring_buffer};
 use sample::interpolate::{Converter, Floor, Linear, Sinc};
-use xmltree::Element;
 use hound;
 use object_pool::Pool;
 use chrono::prelude::*;
 use libcommon::{Action, Offset, Note, Key, Param};
-use libcommon::{param_map, param_add, mark_map, mark_add, note_list};
+use libcommon::{TimelineDoc, TrackDoc};
+use libcommon::bundle::BUNDLE_ASSETS;
 
-use crate::core::{SAMPLE_HZ, BUF_SIZE, CHANNELS, BIT_RATE};
-use crate::core::{SF, Output};
+use crate::core::{BUF_SIZE, CHANNELS, BIT_RATE};
+use crate::core::{SF, Output, Assets};
+use crate::resample;
+use crate::meter;
 
 const SCRUB_MAX: f64 = 0.25;
 const SCRUB_ACC: f64 = 0.01;
 
+// Fader moves are spread out so they don't click, in ms
+const SMOOTH_MS: f32 = 10.0;
+
+// An asset's frames in BUF_SIZE chunks, shared by the regions playing it
+pub type Frames = Arc<Vec<Vec<[Output; CHANNELS]>>>;
+
 pub struct AudioRegion {
     pub id: u16,
-    pub buffer: Vec<Vec<[Output; CHANNELS]>>,
+    pub buffer: Frames,
     pub offset: Offset,
     pub duration: Offset,
     pub asset_in: Offset,
@@ -59,8 +68,20 @@ pub struct Store {
     pub monitor: bool,
     pub mute: bool,
     pub solo: bool,
+    // Fader, gain in dB and pan from -1 (left) to 1 (right)
+    pub gain: Param,
+    pub pan: Param,
+    // Gain applied per channel, follows gain and pan
+    pub fader: [Output; CHANNELS],
+    pub smooth: Output,
+    pub level: meter::Level,
     pub track_id: u16,
+    // The project directory, recordings go in its assets
+    pub dir: PathBuf,
     pub out_queue: Vec<Action>,
+    // Positions are counted in frames at sample_rate, the client and
+    // ... the document count them at the project's rate
+    pub project_rate: u32,
     pub sample_rate: u32,
     pub beat: Offset,
     pub zoom: Offset,
@@ -75,7 +96,52 @@ fn calculate_beat(sample_rate: u32, bpm: u16) -> u32 {
     (60 * sample_rate) / (bpm as u32)
 }
 
-pub fn init(track_id: u16) -> Store {
+// Per sample coefficient of a one pole filter settling in SMOOTH_MS
+fn smooth(sample_rate: u32) -> Output {
+    1.0 - (-1.0 / (SMOOTH_MS / 1000.0 * sample_rate as f32)).exp()
+}
+
+// Per channel level the fader asks for, panning only ever turns
+// ... the other side down so centered stays at unity
+fn target_level(store: &Store) -> [Output; CHANNELS] {
+    if store.mute { return [0.0; CHANNELS]; }
+    let gain = 10f32.powf(store.gain / 20.0);
+    [gain * (1.0 - store.pan).min(1.0), gain * (1.0 + store.pan).min(1.0)]
+}
+
+// An offset in frames at one rate, in frames at another
+fn retime(offset: Offset, from: u32, to: u32) -> Offset {
+    if from == to || from == 0 { return offset; }
+    let frames = (offset as u64 * to as u64 + from as u64 / 2) / from as u64;
+    frames.min(Offset::MAX as u64) as Offset
+}
+
+// Converts the positions an action carries, as it crosses between
+// ... the client and the tape
+fn retime_action(a: Action, from: u32, to: u32) -> Action {
+    let t = |offset| retime(offset, from, to);
+    match a {
+        Action::Goto(offset) => Action::Goto(t(offset)),
+        Action::SetLoop(l_in, l_out) => Action::SetLoop(t(l_in), t(l_out)),
+        Action::Loop(l_in, l_out) => Action::Loop(t(l_in), t(l_out)),
+        Action::MoveRegion(t_id, r_id, offset) => Action::MoveRegion(t_id, r_id, t(offset)),
+        Action::SplitRegion(t_id, r_id, offset) => Action::SplitRegion(t_id, r_id, t(offset)),
+        Action::AddRegion(t_id, r_id, a_id, offset, duration, asset_in, src) => {
+            Action::AddRegion(t_id, r_id, a_id, t(offset), t(duration), t(asset_in), src)
+        },
+        Action::AddMidiRegion(t_id, r_id, offset, duration) => {
+            Action::AddMidiRegion(t_id, r_id, t(offset), t(duration))
+        },
+        Action::AddNote(t_id, note) => Action::AddNote(t_id, Note {
+            t_in: t(note.t_in),
+            t_out: t(note.t_out),
+            ..note
+        }),
+        a => a,
+    }
+}
+
+pub fn init(track_id: u16, dir: &Path, project_rate: u32, sample_rate: u32) -> Store {
     return Store {
         bpm: 127,
         duration: 960000,
@@ -91,13 +157,20 @@ pub fn init(track_id: u16) -> Store {
         monitor: true,
         mute: false,
         solo: false,
+        gain: 0.0,
+        pan: 0.0,
+        fader: [1.0; CHANNELS],
+        smooth: smooth(sample_rate),
+        level: meter::Level::default(),
         recording: 0,
         audio_regions: vec![],
         midi_regions: vec![],
         track_id,
+        dir: dir.to_path_buf(),
         out_queue: vec![],
-        sample_rate: SAMPLE_HZ as u32,
-        beat: calculate_beat(SAMPLE_HZ as u32, 127),
+        project_rate,
+        sample_rate,
+        beat: calculate_beat(sample_rate, 127),
         zoom: 1,
         // Make SURE not to clone these when implementing undo/redo
         pool: None,
@@ -187,6 +260,10 @@ pub fn dispatch_requested(store: &mut Store) -> (
 
     store.out_queue.clear();
 
+    let client_actions: Vec<Action> = client_actions.into_iter()
+        .map(|a| retime_action(a, store.sample_rate, store.project_rate))
+        .collect();
+
     (
         if output_actions.len() > 0 { Some(output_actions) } else { None }, 
         None, 
@@ -194,17 +271,26 @@ pub fn dispatch_requested(store: &mut Store) -> (
     )
 }
 
-// Indexes via an offset into the two dimensional region buffer 
+// Indexes via an offset into the two dimensional region buffer. Past
+// ... its end is silence, as while a region waits for frames at a new rate
 fn frame_with_offset(region: &AudioRegion, offset: usize) -> [Output; CHANNELS] {
     let index = offset / BUF_SIZE;
-    return region.buffer[index][offset - (BUF_SIZE * index)];
+    region.buffer.get(index)
+        .and_then(|chunk| chunk.get(offset - (BUF_SIZE * index)))
+        .cloned()
+        .unwrap_or([0.0; CHANNELS])
+}
+
+// Splits loaded frames into region buffers of BUF_SIZE
+pub fn chunk(frames: Vec<[Output; CHANNELS]>) -> Vec<Vec<[Output; CHANNELS]>> {
+    frames.chunks(BUF_SIZE).map(|c| c.to_vec()).collect()
 }
 
 // Worker thread for writing to disk during record
-fn write_recording_region(source_region: Arc<RwLock<Option<AudioRegion>>>, source_count: Arc<AtomicU32>) {
+fn write_recording_region(source_region: Arc<RwLock<Option<AudioRegion>>>, source_count: Arc<AtomicU32>, sample_rate: u32) {
     let wav_spec = hound::WavSpec {
         channels: CHANNELS as u16,
-        sample_rate: SAMPLE_HZ as u32,
+        sample_rate,
         bits_per_sample: BIT_RATE as u16,
         sample_format: hound::SampleFormat::Int,
     };
@@ -217,9 +303,15 @@ fn write_recording_region(source_region: Arc<RwLock<Option<AudioRegion>>>, sourc
                     Some(_region) => {
                         let mut count = source_count.load(Ordering::SeqCst);
                         match writer {
-                            None => writer = Some(
-                                hound::WavWriter::create(_region.asset_src.clone(), wav_spec).unwrap()
-                            ),
+                            None => {
+                                // A new project has no assets yet
+                                if let Some(assets) = Path::new(&_region.asset_src).parent() {
+                                    let _ = fs::create_dir_all(assets);
+                                }
+                                writer = Some(
+                                    hound::WavWriter::create(_region.asset_src.clone(), wav_spec).unwrap()
+                                )
+                            },
                             Some(ref mut _writer) => {
                                 while count < _region.duration {
                                     let frame = frame_with_offset(&_region, count as usize);
@@ -251,8 +343,51 @@ fn write_recording_region(source_region: Arc<RwLock<Option<AudioRegion>>>, sourc
     }
 }
 
+// Takes effect from the next buffer, positions are counted at the new
+// ... rate from here on. Regions keep their frames until the control
+// ... thread converts them and they are swapped in by reload.
+pub fn set_sample_rate(store: &mut Store, sample_rate: u32) {
+    let from = store.sample_rate;
+    let t = move |offset| retime(offset, from, sample_rate);
+    store.playhead = t(store.playhead);
+    store.loop_in = t(store.loop_in);
+    store.loop_out = t(store.loop_out);
+    store.duration = t(store.duration);
+    for region in store.audio_regions.iter_mut() {
+        region.offset = t(region.offset);
+        region.duration = t(region.duration);
+        region.asset_in = t(region.asset_in);
+    }
+    for region in store.midi_regions.iter_mut() {
+        region.offset = t(region.offset);
+        region.duration = t(region.duration);
+        for note in region.notes.iter_mut().chain(region.note_queue.iter_mut()) {
+            note.t_in = t(note.t_in);
+            note.t_out = t(note.t_out);
+        }
+    }
+    store.sample_rate = sample_rate;
+    store.beat = calculate_beat(sample_rate, store.bpm);
+    store.smooth = smooth(sample_rate);
+}
+
+// Plays assets converted for a new rate. Frames nothing plays any more
+// ... are kept in assets, to be freed off the audio thread.
+pub fn reload(store: &mut Store, assets: &mut Assets) {
+    for region in store.audio_regions.iter_mut() {
+        let frames = match assets.frames.get(&region.asset_src) {
+            Some(frames) if !Arc::ptr_eq(frames, &region.buffer) => frames.clone(),
+            _ => continue,
+        };
+        let old = mem::replace(&mut region.buffer, frames);
+        if Arc::strong_count(&old) == 1 && assets.replaced.len() < assets.replaced.capacity() {
+            assets.replaced.push(old);
+        }
+    }
+}
+
 pub fn dispatch(store: &mut Store, a: Action) {
-    match a {
+    match retime_action(a, store.project_rate, store.sample_rate) {
         Action::LoopMode(on) => {
             store.loop_on = on;
         },
@@ -296,15 +431,16 @@ pub fn dispatch(store: &mut Store, a: Action) {
                     let mut new_asset_id = store.audio_regions.iter().fold(0, |max, r| 
                         if r.asset_id > max {r.asset_id} else {max}) + 1;
                     let timestamp = chrono::offset::Local::now().format("%s").to_string();
-                    let src_dir = std::fs::canonicalize(std::path::Path::new(".")).unwrap();
-                    let new_src = format!("{}/assets/{}_{}.wav", 
-                        src_dir.into_os_string().into_string().unwrap(), 
-                        timestamp, store.track_id);
+                    // In the project's assets, saved relative to it so it can be
+                    // ... moved or collected
+                    let new_src = store.dir.join(BUNDLE_ASSETS)
+                        .join(format!("{}_{}.wav", timestamp, store.track_id))
+                        .to_string_lossy().to_string();
                     let mut region_guard = store.rec_region.write().unwrap();
                     *region_guard = Some(AudioRegion {
                         id: new_region_id,
                         offset: store.playhead,
-                        buffer: vec![],
+                        buffer: Arc::new(vec![]),
                         duration: 0,
                         asset_in: 0,
                         gain: 1.0,
@@ -373,7 +509,8 @@ pub fn dispatch(store: &mut Store, a: Action) {
                             // Doesn't actually clone, just increments the reference counter
                             let source_region = store.rec_region.clone();
                             let source_count = store.written.clone();
-                            store.writer = Some(thread::spawn(|| write_recording_region(source_region, source_count)));
+                            let sample_rate = store.sample_rate;
+                            store.writer = Some(thread::spawn(move || write_recording_region(source_region, source_count, sample_rate)));
                         }
                     },
                     // Mode 0 (OFF) or 1 (MIDI)
@@ -388,6 +525,10 @@ pub fn dispatch(store: &mut Store, a: Action) {
         Action::MuteTrack(t_id, is_on) => { 
             if store.track_id == t_id {
                 store.mute = is_on;
+                // Snap while stopped, bounces mute tracks right before playing
+                if store.velocity == 0.0 {
+                    store.fader = target_level(store);
+                }
             }
         },
         Action::MonitorTrack(t_id, is_on) => { 
@@ -395,6 +536,16 @@ pub fn dispatch(store: &mut Store, a: Action) {
                 store.monitor = is_on; 
             }
         },
+        Action::SetTrackGain(t_id, db) => {
+            if store.track_id == t_id {
+                store.gain = db.max(-60.0).min(12.0);
+            }
+        },
+        Action::SetTrackPan(t_id, pan) => {
+            if store.track_id == t_id {
+                store.pan = pan.max(-1.0).min(1.0);
+            }
+        },
         Action::Loop(loop_in, loop_out) => { 
             store.loop_in = loop_in; 
             store.loop_out = loop_out; 
@@ -662,14 +813,14 @@ pub fn compute_buf(store: &mut Store, buffer: &mut [[Output; CHANNELS]]) {
                                 let index = _region.duration as usize % BUF_SIZE;
                                 if index == 0 {
                                     if let Some(new_buf) = this_pool.try_pull() {
-                                        _region.buffer.push(new_buf.to_vec());
+                                        Arc::make_mut(&mut _region.buffer).push(new_buf.to_vec());
                                     } else {
                                         // Out of space! Stop record
                                         store.recording = 0;
                                         break;
                                     }
                                 }
-                                _region.buffer.last_mut().unwrap()[index] = *frame;
+                                Arc::make_mut(&mut _region.buffer).last_mut().unwrap()[index] = *frame;
                                 _region.duration += 1;
                             }
                         },
@@ -692,7 +843,7 @@ pub fn compute_buf(store: &mut Store, buffer: &mut [[Output; CHANNELS]]) {
         /*
         let frames = ring_buffer::Fixed::from(vec![[0.0]; 100]);
         let interp = Sinc::new(frames);
-        let mut resampled = source.from_hz_to_hz(interp, SAMPLE_HZ, SAMPLE_HZ * playback_rate);
+        let mut resampled = source.from_hz_to_hz(interp, rate, rate * playback_rate);
         */
         let interp = Linear::from_source(&mut source);
         let mut resampled = source.scale_hz(interp, playback_rate);
@@ -705,6 +856,14 @@ pub fn compute_buf(store: &mut Store, buffer: &mut [[Output; CHANNELS]]) {
         });
     }
 
+    // Fader, mute included
+    let target = target_level(store);
+    for frame in buffer.iter_mut() {
+        for c in 0..CHANNELS {
+            store.fader[c] += (target[c] - store.fader[c]) * store.smooth;
+            frame[c] *= store.fader[c];
+        }
+    }
 }
 
 pub fn compute(store: &mut Store) -> [Output; CHANNELS] {
@@ -733,12 +892,6 @@ pub fn compute(store: &mut Store) -> [Output; CHANNELS] {
             }
         }
     }
-    // Prevent speaker damage
-    z = [
-        z[0].min(0.999).max(-0.999),
-        z[1].min(0.999).max(-0.999),
-    ];
-
     if store.velocity < 0.0 && store.playhead == 0 { 
         store.scrub = None;
         store.velocity = 0.0 
@@ -771,137 +924,158 @@ pub fn compute(store: &mut Store) -> [Output; CHANNELS] {
     z
 }
 
-pub fn read(doc: &mut Element) -> Option<Store> {
-    let mut store = init(0);
-
-    let (mut doc, mut params) = param_map(doc);
-    let (mut doc, mut marks) = mark_map(doc);
+pub fn read(timeline: &TimelineDoc, track: &TrackDoc, dir: &Path, project_rate: u32, sample_rate: u32) -> Store {
+    let mut store = init(track.id, dir, project_rate, sample_rate);
+    let t = |offset| retime(offset, project_rate, sample_rate);
 
-    store.bpm = (*params.get("bpm").unwrap_or(&127.0)) as u16;
-    store.meter_beat = (*params.get("meter_beat").unwrap_or(&4.0)) as u16;
-    store.meter_note = (*params.get("meter_note").unwrap_or(&4.0)) as u16;
-    store.loop_in = (*marks.get("loop_in").unwrap_or(&0)).try_into().unwrap();
-    store.loop_out = (*marks.get("loop_out").unwrap_or(&0)).try_into().unwrap();
-    store.duration = (*marks.get("seq_out").unwrap_or(&48000) - 
-                      *marks.get("seq_in").unwrap_or(&0)).try_into().unwrap();
+    store.bpm = timeline.bpm;
+    store.meter_beat = timeline.meter_beat;
+    store.meter_note = timeline.meter_note;
+    store.loop_in = t(timeline.loop_in);
+    store.loop_out = t(timeline.loop_out);
+    store.duration = t(timeline.seq_out.saturating_sub(timeline.seq_in));
     store.beat = calculate_beat(store.sample_rate, store.bpm);
+    store.gain = track.gain;
+    store.pan = track.pan;
+    store.fader = target_level(&store);
 
-    for (name, value) in params.drain() {
-        param_add(doc, value, name);
-    }
-
-    for (name, offset) in marks.drain() {
-        mark_add(doc, offset, name);
-    }
+    for region in track.audio_regions.iter() {
+        // Assets can only be used on a single track for now
+        let asset = match timeline.asset(track.id, region.asset) {
+            Some(asset) => asset,
+            None => {
+                eprintln!("couldn't find asset {} on track {}", region.asset, track.id);
+                continue;
+            }
+        };
 
-    let mut assets: HashMap<u16, Element> = HashMap::new();
+        // Regions of the same asset share its frames
+        let shared = store.audio_regions.iter()
+            .find(|r| r.asset_src == asset.src)
+            .map(|r| r.buffer.clone());
+        let buffer = match shared {
+            Some(buffer) => buffer,
+            None => match resample::load(&asset.src, sample_rate) {
+                Ok(frames) => Arc::new(chunk(frames)),
+                Err(e) => {
+                    eprintln!("couldn't open asset {}: {}", asset.src, e);
+                    continue;
+                }
+            },
+        };
 
-    while let Some(asset) = doc.take_child("asset") {
-        let a_id: &str = asset.attributes.get("id").unwrap();
-        assets.insert(a_id.parse().unwrap(), asset);
+        store.audio_regions.push(AudioRegion {
+            id: region.id,
+            asset_id: region.asset,
+            asset_in: t(region.asset_in),
+            offset: t(region.offset),
+            duration: t(region.duration),
+            asset_src: asset.src.clone(),
+            gain: 1.0,
+            buffer,
+        });
     }
 
-    // Only take one track 
-    if let Some(mut track) = doc.take_child("track") {
-        let t_id: &str = track.attributes.get("id").unwrap();
-        let _t_id = t_id.parse::<u16>().unwrap();
-        store.track_id = _t_id;
-
-        while let Some(region) = track.take_child("audio") {
-            let r_id: &str = region.attributes.get("id").unwrap();
-            let a_id: &str = region.attributes.get("asset").unwrap();
-            let offset: &str = region.attributes.get("offset").unwrap();
-            let duration: &str = region.attributes.get("duration").unwrap();
-            let a_in: &str = region.attributes.get("in").unwrap();
-
-            let _r_id: u16 = r_id.parse().unwrap();
-            let _a_id: u16 = a_id.parse().unwrap();
-            let _a_in: Offset = a_in.parse().unwrap();
-            let _duration: Offset = duration.parse().unwrap();
-            let _offset: Offset = offset.parse().unwrap();
-
-            let mut buffer = vec![];
-            let mut _src: Option<String> = None;
-
-            // Assets can only be used on a single track for now
-            let global_id = _t_id * 1000 + _a_id;
-            if let Some((_, asset)) = assets.iter().find(|(id, _)| **id == global_id) {
-                let src: &str = asset.attributes.get("src").unwrap();
-                _src = Some(src.to_string());
-                let duration: &str = asset.attributes.get("size").unwrap();
-                let _duration: usize = duration.parse().unwrap();
-
-                buffer = Vec::with_capacity(_duration / BUF_SIZE);
-                
-                let mut wav_f = hound::WavReader::open(src).unwrap();
-                let spec = wav_f.spec();
-                let channels = spec.channels as usize;
-                let bitrate = spec.bits_per_sample;
-
-                let mut push_sample = |i, sample| {
-                    if i % (BUF_SIZE * channels) == 0 {
-                        buffer.push(vec![])
-                    }
-                    let frame_index = i % channels;
-                    if frame_index == 0 {
-                        buffer.last_mut().unwrap().push([0.0; CHANNELS]);
-                    }
-                    buffer.last_mut().unwrap().last_mut().unwrap()[frame_index] = sample
-                };
+    for region in track.midi_regions.iter() {
+        store.midi_regions.push(MidiRegion {
+            id: region.id,
+            notes: region.notes.iter().map(|note| Note {
+                t_in: t(note.t_in),
+                t_out: t(note.t_out),
+                ..note.clone()
+            }).collect(),
+            note_queue: vec![],
+            offset: t(region.offset),
+            duration: t(region.duration),
+        });
+    }
 
-                if spec.sample_format == hound::SampleFormat::Float {
-                    for (i, sample) in wav_f.samples::<f32>().enumerate() {
-                        push_sample(i, sample.unwrap());
-                    }
-                } else {
-                    for (i, sample) in wav_f.samples::<i32>().enumerate() {
-                        push_sample(i, sample.unwrap() as f32 / 2_f32.powf(bitrate as f32));
-                    }
-                }
-            }
-            if _src.is_none() {
-                eprintln!("couldn't find asset {}", global_id);
-            }
+    store
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libcommon::{AssetDoc, AudioRegionDoc, REGIONS_PER_TRACK};
 
-            store.audio_regions.push(AudioRegion {
-                id: _r_id,
-                asset_id: _a_id,
-                asset_in: _a_in,
-                offset: _offset,
-                duration: _duration,
-                asset_src: _src.unwrap(),
-                gain: 1.0,
-                buffer,
-            });
+    #[test]
+    fn regions_play_at_another_rate() {
+        let path = std::env::temp_dir().join(format!("pt-test-rate-{}.wav", std::process::id()));
+        let spec = hound::WavSpec {
+            channels: CHANNELS as u16,
+            sample_rate: 24000,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for _ in 0..1000 * CHANNELS {
+            writer.write_sample(0.5_f32).unwrap();
         }
+        writer.finalize().unwrap();
 
-        while let Some(mut midi_region) = track.take_child("midi") {
-            let r_id: &str = midi_region.attributes.get("id").unwrap();
-            let offset: &str = midi_region.attributes.get("offset").unwrap();
-            let duration: &str = midi_region.attributes.get("duration").unwrap();
+        let src = path.to_string_lossy().to_string();
+        let track = TrackDoc {
+            id: 1,
+            gain: 0.0,
+            pan: 0.0,
+            audio_regions: vec![AudioRegionDoc { id: 1, asset: 1, asset_in: 250, offset: 100, duration: 500 }],
+            midi_regions: vec![],
+        };
+        let timeline = TimelineDoc {
+            assets: vec![AssetDoc { id: REGIONS_PER_TRACK + 1, src: src.clone(), size: 1000, channels: 2 }],
+            tracks: vec![track.clone()],
+            ..TimelineDoc::default()
+        };
 
-            let _r_id: u16 = r_id.parse().unwrap();
-            let _offset: Offset = offset.parse().unwrap();
-            let _duration: Offset = duration.parse().unwrap();
+        // Written at 24kHz, played at 48kHz
+        let mut store = read(&timeline, &track, Path::new(""), 24000, 48000);
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(resample::cache_path(&path, 48000, libcommon::config().resample));
+        assert_eq!(store.audio_regions.len(), 1);
+        let region = &store.audio_regions[0];
+        assert_eq!((region.offset, region.duration, region.asset_in), (200, 1000, 500));
 
-            let (midi_region, notes) = note_list(&mut midi_region, _r_id);
+        dispatch(&mut store, Action::Play);
+        let frames: Vec<[Output; CHANNELS]> = (0..1500).map(|_| compute(&mut store)).collect();
+        assert!(frames[..200].iter().all(|f| *f == [0.0; CHANNELS]));
+        assert!(frames[200..1200].iter().all(|f| (f[0] - 0.5).abs() < 0.01), "the region plays out of time");
+        assert!(frames[1200..].iter().all(|f| *f == [0.0; CHANNELS]));
 
-            store.midi_regions.push(MidiRegion {
-                id: _r_id,
-                notes,
-                note_queue: vec![],
-                offset: _offset,
-                duration: _duration,
-            });
-        }
-
-    } else {
-        return None;
+        // Until its frames are converted, what runs past them is silence
+        set_sample_rate(&mut store, 96000);
+        dispatch(&mut store, Action::Goto(100));
+        let frames: Vec<[Output; CHANNELS]> = (0..2000).map(|_| compute(&mut store)).collect();
+        assert!(frames[1000..].iter().all(|f| *f == [0.0; CHANNELS]));
     }
 
-    for (id, asset) in assets.drain() {
-        doc.children.push(asset);
-    }
+    #[test]
+    fn positions_cross_at_the_project_rate() {
+        let mut store = init(1, Path::new(""), 48000, 44100);
+        store.audio_regions.push(AudioRegion {
+            id: 1,
+            buffer: Arc::new(vec![]),
+            offset: 0,
+            duration: 44100,
+            asset_in: 0,
+            gain: 1.0,
+            asset_id: 1,
+            asset_src: String::new(),
+        });
+        dispatch(&mut store, Action::SetLoop(48000, 96000));
+        dispatch(&mut store, Action::Goto(24000));
+        assert_eq!((store.loop_in, store.loop_out, store.playhead), (44100, 88200, 22050));
+
+        // The card changes rate under a playing project
+        set_sample_rate(&mut store, 88200);
+        assert_eq!((store.loop_in, store.loop_out, store.playhead), (88200, 176400, 44100));
+        assert_eq!(store.audio_regions[0].duration, 88200);
 
-    return Some(store);
-}
\ No newline at end of file
+        // Half a second in, at either rate
+        dispatch(&mut store, Action::SplitRegion(1, 1, 24000));
+        assert_eq!(store.audio_regions[1].offset, 44100);
+        let (_, _, client) = dispatch_requested(&mut store);
+        assert_eq!(client, Some(vec![
+            Action::AddRegion(1, 2, 1, 24000, 24000, 24000, String::new()),
+            Action::AddRegion(1, 1, 1, 0, 24000, 0, String::new()),
+        ]));
+    }
+}